@@ -1,8 +1,10 @@
 use esp_idf_sys::*;
 use num_enum::TryFromPrimitive;
 
+use crate::{utilities::ah, BLEError};
+
 /// Bluetooth Device address type
-#[derive(PartialEq, Eq, TryFromPrimitive)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum BLEAddressType {
   Public = BLE_ADDR_PUBLIC as _,
@@ -11,6 +13,25 @@ pub enum BLEAddressType {
   RandomID = BLE_ADDR_RANDOM_ID as _,
 }
 
+/// Kind of a device address, see the Core specification (Vol 6, Part B, 1.3).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BLEAddressKind {
+  Public,
+  /// Random address that does not change until the next power cycle.
+  RandomStatic,
+  /// Random address that peers holding the identity resolving key can resolve.
+  ResolvablePrivate,
+  NonResolvablePrivate,
+  /// Random address with the reserved `0b10` most significant bits.
+  Reserved,
+}
+
+/// Bluetooth Device address.
+///
+/// Equality, hashing and ordering compare the address bytes and whether the address is public
+/// or random. An identity address resolved by the controller ([`BLEAddressType::PublicID`],
+/// [`BLEAddressType::RandomID`]) is equal to the same identity address reported as
+/// [`BLEAddressType::Public`] or [`BLEAddressType::Random`].
 #[repr(transparent)]
 #[derive(Copy, Clone)]
 pub struct BLEAddress {
@@ -29,7 +50,52 @@ impl BLEAddress {
     ret
   }
 
+  /// Create an address from its over-the-air (little-endian) byte order.
+  pub(crate) fn from_le_bytes(val: [u8; 6], addr_type: BLEAddressType) -> Self {
+    Self {
+      value: esp_idf_sys::ble_addr_t {
+        val,
+        type_: addr_type as _,
+      },
+    }
+  }
+
+  /// Generate a static random address.
+  pub fn random_static() -> Self {
+    Self::from_le_bytes(random_bits(0b11), BLEAddressType::Random)
+  }
+
+  /// Generate a non-resolvable private address.
+  pub fn random_non_resolvable() -> Self {
+    Self::from_le_bytes(random_bits(0b00), BLEAddressType::Random)
+  }
+
+  /// Generate a resolvable private address from the identity resolving key `irk`, in the
+  /// NimBLE byte order.
+  pub fn random_resolvable(irk: &[u8; 16]) -> Self {
+    let prand: [u8; 3] = random_bits(0b01);
+    let mut irk = *irk;
+    irk.reverse();
+    let hash = ah(&irk, [prand[2], prand[1], prand[0]]);
+    Self::from_le_bytes(
+      [hash[2], hash[1], hash[0], prand[0], prand[1], prand[2]],
+      BLEAddressType::Random,
+    )
+  }
+
+  #[deprecated(
+    since = "0.6.1",
+    note = "use `str::parse`, or `BLEAddress::parse_with_type` to set the address type"
+  )]
   pub fn from_str(input: &str, addr_type: BLEAddressType) -> Option<Self> {
+    Self::parse_with_type(input, addr_type)
+  }
+
+  /// Parse an address formatted as `XX:XX:XX:XX:XX:XX` or `XX-XX-XX-XX-XX-XX`, most
+  /// significant byte first, with the given type.
+  ///
+  /// Use `str::parse` to read the type from the address, as formatted by `Debug`.
+  pub fn parse_with_type(input: &str, addr_type: BLEAddressType) -> Option<Self> {
     let mut val = [0u8; 6];
 
     let mut nth = 0;
@@ -59,6 +125,90 @@ impl BLEAddress {
   pub fn addr_type(&self) -> BLEAddressType {
     BLEAddressType::try_from(self.value.type_).unwrap()
   }
+
+  fn is_random(&self) -> bool {
+    matches!(
+      self.addr_type(),
+      BLEAddressType::Random | BLEAddressType::RandomID
+    )
+  }
+
+  /// Get the kind of the address; random addresses are classified by their two most
+  /// significant bits.
+  pub fn kind(&self) -> BLEAddressKind {
+    match self.addr_type() {
+      BLEAddressType::Public | BLEAddressType::PublicID => BLEAddressKind::Public,
+      BLEAddressType::Random | BLEAddressType::RandomID => match self.value.val[5] >> 6 {
+        0b11 => BLEAddressKind::RandomStatic,
+        0b01 => BLEAddressKind::ResolvablePrivate,
+        0b00 => BLEAddressKind::NonResolvablePrivate,
+        _ => BLEAddressKind::Reserved,
+      },
+    }
+  }
+
+  pub fn is_resolvable_private(&self) -> bool {
+    self.kind() == BLEAddressKind::ResolvablePrivate
+  }
+
+  /// Resolve a resolvable private address: get the index of the identity resolving key in
+  /// `irks` that generated it.
+  ///
+  /// The keys are in the NimBLE byte order, as in [`SecRecord::irk`](crate::SecRecord::irk).
+  pub fn resolve<'a>(&self, irks: impl IntoIterator<Item = &'a [u8; 16]>) -> Option<usize> {
+    if !self.is_resolvable_private() {
+      return None;
+    }
+
+    let val = self.value.val;
+    let prand = [val[5], val[4], val[3]];
+    let hash = [val[2], val[1], val[0]];
+    irks.into_iter().position(|irk| {
+      let mut irk = *irk;
+      irk.reverse();
+      ah(&irk, prand) == hash
+    })
+  }
+}
+
+/// Random bytes in the NimBLE byte order, with the two most significant bits set to `msb`
+/// and the other bits neither all 0 nor all 1.
+fn random_bits<const N: usize>(msb: u8) -> [u8; N] {
+  loop {
+    let mut val = [0u8; N];
+    unsafe { esp_fill_random(val.as_mut_ptr() as _, N) };
+    val[N - 1] = (val[N - 1] & 0x3f) | (msb << 6);
+
+    let (rest, last) = val.split_at(N - 1);
+    let all_zero = rest.iter().all(|x| *x == 0) && last[0] & 0x3f == 0;
+    let all_one = rest.iter().all(|x| *x == 0xff) && last[0] & 0x3f == 0x3f;
+    if !all_zero && !all_one {
+      return val;
+    }
+  }
+}
+
+/// Parse an address formatted as by `Debug`: `XX:XX:XX:XX:XX:XX`, optionally followed by
+/// `(random)`, `(publicID)` or `(randomID)`. The address type defaults to public.
+impl core::str::FromStr for BLEAddress {
+  type Err = BLEError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || BLEError::convert(BLE_HS_EINVAL).unwrap_err();
+    let (addr, addr_type) = match s.find('(') {
+      Some(idx) => {
+        let addr_type = match &s[idx..] {
+          "(random)" => BLEAddressType::Random,
+          "(publicID)" => BLEAddressType::PublicID,
+          "(randomID)" => BLEAddressType::RandomID,
+          _ => return Err(invalid()),
+        };
+        (&s[..idx], addr_type)
+      }
+      None => (s, BLEAddressType::Public),
+    };
+    Self::parse_with_type(addr, addr_type).ok_or_else(invalid)
+  }
 }
 
 impl From<esp_idf_sys::ble_addr_t> for BLEAddress {
@@ -96,8 +246,112 @@ impl core::fmt::Debug for BLEAddress {
 
 impl PartialEq for BLEAddress {
   fn eq(&self, other: &Self) -> bool {
-    self.value.val == other.value.val
+    self.value.val == other.value.val && self.is_random() == other.is_random()
   }
 }
 
 impl Eq for BLEAddress {}
+
+impl core::hash::Hash for BLEAddress {
+  fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+    self.value.val.hash(state);
+    self.is_random().hash(state);
+  }
+}
+
+/// Public addresses are ordered before random ones, then addresses are ordered as displayed,
+/// most significant byte first.
+impl Ord for BLEAddress {
+  fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+    self.is_random().cmp(&other.is_random()).then_with(|| {
+      self
+        .value
+        .val
+        .iter()
+        .rev()
+        .cmp(other.value.val.iter().rev())
+    })
+  }
+}
+
+impl PartialOrd for BLEAddress {
+  fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::format;
+
+  #[test]
+  fn parse() {
+    let addr: BLEAddress = "C6:05:04:03:02:01(random)".parse().unwrap();
+    assert_eq!(addr.val(), [1, 2, 3, 4, 5, 0xc6]);
+    assert_eq!(addr.addr_type(), BLEAddressType::Random);
+    assert_eq!(format!("{addr:?}"), "C6:05:04:03:02:01(random)");
+
+    let addr: BLEAddress = "c6-05-04-03-02-01".parse().unwrap();
+    assert_eq!(addr.addr_type(), BLEAddressType::Public);
+    assert_eq!(
+      BLEAddress::parse_with_type("C6:05:04:03:02:01", BLEAddressType::RandomID)
+        .unwrap()
+        .addr_type(),
+      BLEAddressType::RandomID
+    );
+
+    for input in [
+      "C6:05:04:03:02",
+      "C6:05:04:03:02:01:00",
+      "C6:05:04:03:02:0G",
+      "C6:05:04:03:02:01(other)",
+    ] {
+      assert!(input.parse::<BLEAddress>().is_err(), "{input}");
+    }
+  }
+
+  #[test]
+  fn identity() {
+    let val = [1, 2, 3, 4, 5, 0xc6];
+    let public = BLEAddress::from_le_bytes(val, BLEAddressType::Public);
+    let random = BLEAddress::from_le_bytes(val, BLEAddressType::Random);
+    assert_ne!(public, random);
+    assert_eq!(
+      public,
+      BLEAddress::from_le_bytes(val, BLEAddressType::PublicID)
+    );
+    assert_eq!(
+      random,
+      BLEAddress::from_le_bytes(val, BLEAddressType::RandomID)
+    );
+
+    let smaller = BLEAddress::from_le_bytes([0xff, 0, 0, 0, 0, 0xc5], BLEAddressType::Random);
+    assert!(smaller < random);
+    assert!(public < smaller);
+  }
+
+  #[test]
+  fn kind() {
+    let addr = |msb| BLEAddress::from_le_bytes([1, 2, 3, 4, 5, msb], BLEAddressType::Random);
+    assert_eq!(addr(0xc6).kind(), BLEAddressKind::RandomStatic);
+    assert_eq!(addr(0x46).kind(), BLEAddressKind::ResolvablePrivate);
+    assert_eq!(addr(0x06).kind(), BLEAddressKind::NonResolvablePrivate);
+    assert_eq!(addr(0x86).kind(), BLEAddressKind::Reserved);
+    assert_eq!(
+      BLEAddress::from_le_bytes([0; 6], BLEAddressType::Public).kind(),
+      BLEAddressKind::Public
+    );
+  }
+
+  #[test]
+  fn resolve() {
+    // Core Vol 3, Part H, D.7: prand 0x708194 and hash 0x0dfbaa.
+    let mut irk = 0xec0234a357c8ad05341010a60a397d9b_u128.to_be_bytes();
+    irk.reverse();
+    let rpa =
+      BLEAddress::from_le_bytes([0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70], BLEAddressType::Random);
+    assert_eq!(rpa.resolve(&[[0; 16], irk]), Some(1));
+    assert_eq!(rpa.resolve(&[[0; 16]]), None);
+  }
+}
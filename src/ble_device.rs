@@ -1,14 +1,16 @@
-use alloc::{ffi::CString, vec::Vec};
+use alloc::{boxed::Box, ffi::CString, vec::Vec};
 use core::{
   ffi::c_void,
   sync::atomic::{AtomicBool, Ordering},
+  time::Duration,
 };
 use esp_idf_sys::{esp, esp_nofail, EspError};
 use once_cell::sync::Lazy;
 
 use crate::{
-  ble, client::BLEScan, enums::*, utilities::mutex::Mutex, BLEAddress, BLEError, BLESecurity,
-  BLEServer,
+  ble, client::BLEScan, decode_bond_blob, encode_bond_blob, enums::*, utilities::mutex::Mutex,
+  BLEAddress, BLEError, BLESecurity, BLEServer, BondInfo, BondRecord, BondStore,
+  BondStoreFullPolicy, PrivateAddressKind, SecRecord,
 };
 
 #[cfg(not(esp_idf_bt_nimble_ext_adv))]
@@ -84,7 +86,7 @@ impl BLEDevice {
         esp_idf_sys::ble_hs_cfg.set_sm_sc(1);
         esp_idf_sys::ble_hs_cfg.sm_our_key_dist = 1;
         esp_idf_sys::ble_hs_cfg.sm_their_key_dist = 3;
-        esp_idf_sys::ble_hs_cfg.store_status_cb = Some(esp_idf_sys::ble_store_util_status_rr);
+        esp_idf_sys::ble_hs_cfg.store_status_cb = Some(crate::bond_store::on_store_status);
 
         ble_store_config_init();
 
@@ -204,8 +206,107 @@ impl BLEDevice {
     Ok(result)
   }
 
+  /// Get the identity address of the bonded peer that generated the resolvable private
+  /// `address`, e.g. to recognize bonded peers in scan results without connecting.
+  pub fn resolve_bonded_address(
+    &self,
+    address: &BLEAddress,
+  ) -> Result<Option<BLEAddress>, BLEError> {
+    let peers: Vec<_> = crate::bond_store::read_bond_records()?
+      .into_iter()
+      .filter_map(|x| match x {
+        BondRecord::PeerSec(SecRecord {
+          peer_addr,
+          irk: Some(irk),
+          ..
+        }) => Some((peer_addr, irk)),
+        _ => None,
+      })
+      .collect();
+    Ok(
+      address
+        .resolve(peers.iter().map(|(_, irk)| irk))
+        .map(|idx| peers[idx].0),
+    )
+  }
+
+  /// Get the bonded peers with their metadata, least recently connected first.
+  pub fn bonds(&self) -> Result<Vec<BondInfo>, BLEError> {
+    crate::bond_store::bonds()
+  }
+
+  /// Set the label of the bond of `address`, up to [`MAX_BOND_LABEL_LEN`](crate::MAX_BOND_LABEL_LEN) bytes.
+  pub fn set_bond_label(
+    &mut self,
+    address: &BLEAddress,
+    label: Option<&str>,
+  ) -> Result<(), BLEError> {
+    crate::bond_store::set_bond_label(address, label)
+  }
+
+  /// Pin the bond of `address`, so that it is never deleted to make room for a new bond.
+  pub fn set_bond_pinned(&mut self, address: &BLEAddress, pinned: bool) -> Result<(), BLEError> {
+    crate::bond_store::set_bond_pinned(address, pinned)
+  }
+
+  /// Set what to do when the bond store has no room for a new bond.
+  /// Defaults to [`BondStoreFullPolicy::EvictLeastRecentlyUsed`].
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # use esp32_nimble::{BLEDevice, BondStoreFullPolicy};
+  /// let device = BLEDevice::take();
+  /// device.set_bond_store_full_policy(BondStoreFullPolicy::Callback(Box::new(|bonds| {
+  ///   bonds.iter().find(|x| x.label.is_none()).map(|x| x.address)
+  /// })));
+  /// ```
+  pub fn set_bond_store_full_policy(&mut self, policy: BondStoreFullPolicy) {
+    crate::bond_store::set_store_full_policy(policy);
+  }
+
+  /// Keep the bonds in `store` instead of the NimBLE store.
+  ///
+  /// Bonds already in the NimBLE store are not migrated to `store`.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # use esp32_nimble::{BLEDevice, BLEError, NvsBondStore};
+  /// # fn run() -> Result<(), BLEError> {
+  /// let device = BLEDevice::take();
+  /// device.set_bond_store(NvsBondStore::new("bonds")?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_bond_store(&mut self, store: impl BondStore + 'static) {
+    crate::bond_store::set_bond_store(Box::new(store));
+  }
+
+  /// Export the bonds (keys, peer identities and CCCD values) as a blob to be imported
+  /// on another device with [`BLEDevice::import_bonds`].
+  ///
+  /// The blob contains the keys of the bonds, keep it confidential.
+  pub fn export_bonds(&self) -> Result<Vec<u8>, BLEError> {
+    Ok(encode_bond_blob(&crate::bond_store::read_bond_records()?))
+  }
+
+  /// Import bonds exported by [`BLEDevice::export_bonds`], replacing the bonds with the same
+  /// keys.
+  ///
+  /// The blob is validated before any bond is written. The peers reconnect without pairing
+  /// only if this device uses the identity address of the exporting device.
+  pub fn import_bonds(&mut self, blob: &[u8]) -> Result<(), BLEError> {
+    for record in decode_bond_blob(blob)? {
+      crate::bond_store::write_bond_record(&record)?;
+    }
+    Ok(())
+  }
+
   /// Deletes all bonding information.
   pub fn delete_all_bonds(&self) -> Result<(), BLEError> {
+    crate::bond_store::forget_bond(None);
+    crate::signed_write::forget_counters(None);
     unsafe { ble!(esp_idf_sys::ble_store_clear()) }
   }
 
@@ -213,6 +314,8 @@ impl BLEDevice {
   ///
   /// * `address`: The address of the peer with which to delete bond info.
   pub fn delete_bond(&self, address: &BLEAddress) -> Result<(), BLEError> {
+    crate::bond_store::forget_bond(Some(address));
+    crate::signed_write::forget_counters(Some(address));
     unsafe { ble!(esp_idf_sys::ble_gap_unpair(&address.value)) }
   }
 
@@ -242,6 +345,7 @@ impl BLEDevice {
 
   #[allow(unused_variables)]
   fn _set_own_addr_type(&mut self, own_addr_type: OwnAddrType, use_nrpa: bool) {
+    crate::own_address::stop_rotation();
     unsafe {
       OWN_ADDR_TYPE = own_addr_type;
       match own_addr_type {
@@ -273,6 +377,48 @@ impl BLEDevice {
     unsafe { ble!(esp_idf_sys::ble_hs_id_set_rnd(addr.as_ptr())) }
   }
 
+  /// Use a static random address, generated on the first call and persisted in NVS so that
+  /// it does not change across reboots.
+  pub fn use_static_random_addr(&mut self) -> Result<BLEAddress, BLEError> {
+    crate::own_address::stop_rotation();
+    let addr = crate::own_address::persisted_static_address()?;
+    crate::own_address::set_random_address(&addr)?;
+    self.use_app_random_addr();
+    Ok(addr)
+  }
+
+  /// Use private addresses generated by the application, changed every `interval`
+  /// (typically 15 minutes). Returns the first address.
+  ///
+  /// This provides privacy on chips without host-based privacy (see
+  /// [`BLEDevice::set_own_addr_type`]). The advertising is restarted when the address changes.
+  pub fn start_address_rotation(
+    &mut self,
+    kind: PrivateAddressKind,
+    interval: Duration,
+  ) -> Result<BLEAddress, BLEError> {
+    let addr = crate::own_address::start_rotation(kind, interval)?;
+    if kind == PrivateAddressKind::Resolvable {
+      self.security().resolve_rpa();
+    }
+    self.use_app_random_addr();
+    Ok(addr)
+  }
+
+  /// Stop changing the address; the current address stays in use.
+  pub fn stop_address_rotation(&mut self) {
+    crate::own_address::stop_rotation();
+  }
+
+  /// Use the random address set by the application, without host-based privacy.
+  fn use_app_random_addr(&mut self) {
+    unsafe {
+      OWN_ADDR_TYPE = OwnAddrType::Random;
+      #[cfg(esp32)]
+      ble_hs_pvcy_rpa_config(NIMBLE_HOST_DISABLE_PRIVACY);
+    }
+  }
+
   #[allow(temporary_cstring_as_ptr)]
   pub fn set_device_name(device_name: &str) -> Result<(), BLEError> {
     unsafe {
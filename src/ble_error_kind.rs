@@ -0,0 +1,498 @@
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// Category of a NimBLE return code, with the decoded reason.
+///
+/// NimBLE reports errors of the host, of the peer and of the controller through a single
+/// return code, split into ranges by a base value per category.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use esp32_nimble::{AttError, BLEClient, BLEError, BLEErrorKind, HciError};
+/// # async fn run(client: &mut BLEClient, err: BLEError) -> Result<(), BLEError> {
+/// match err.kind() {
+///   BLEErrorKind::Att(AttError::InsufficientAuthentication) => client.secure_connection().await?,
+///   BLEErrorKind::Hci(HciError::RemoteUserTerminatedConnection) => ::log::info!("peer left"),
+///   _ => return Err(err),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BLEErrorKind {
+  /// Error of the NimBLE host.
+  Host(HostError),
+  /// ATT error response received from the peer, or sent to it.
+  Att(AttError),
+  /// HCI error reported by the controller, e.g. the reason a connection was terminated.
+  Hci(HciError),
+  /// Pairing failed on the local side.
+  SmUs(SmError),
+  /// Pairing failed on the peer side.
+  SmPeer(SmError),
+  /// L2CAP signaling command rejected.
+  L2cap(L2capError),
+  /// The operation did not complete within the timeout set by the application, see
+  /// [`BLEError::timeout`](crate::BLEError::timeout).
+  Timeout,
+  /// Code outside the known ranges.
+  Unknown(u32),
+}
+
+impl BLEErrorKind {
+  /// Decode a NimBLE return code.
+  pub fn from_code(code: u32) -> Self {
+    if code == crate::ble_error::TIMEOUT_CODE {
+      return Self::Timeout;
+    }
+
+    let (base, reason) = (code & !0xFF, code as u8);
+    match base {
+      0 => Self::Host(HostError::from(reason)),
+      esp_idf_sys::BLE_HS_ERR_ATT_BASE => Self::Att(AttError::from(reason)),
+      esp_idf_sys::BLE_HS_ERR_HCI_BASE => Self::Hci(HciError::from(reason)),
+      esp_idf_sys::BLE_HS_ERR_L2C_BASE => Self::L2cap(L2capError::from(reason)),
+      esp_idf_sys::BLE_HS_ERR_SM_US_BASE => Self::SmUs(SmError::from(reason)),
+      esp_idf_sys::BLE_HS_ERR_SM_PEER_BASE => Self::SmPeer(SmError::from(reason)),
+      _ => Self::Unknown(code),
+    }
+  }
+
+  /// The NimBLE return code.
+  pub fn code(&self) -> u32 {
+    match *self {
+      Self::Host(x) => u8::from(x) as _,
+      Self::Att(x) => esp_idf_sys::BLE_HS_ERR_ATT_BASE + u8::from(x) as u32,
+      Self::Hci(x) => esp_idf_sys::BLE_HS_ERR_HCI_BASE + u8::from(x) as u32,
+      Self::L2cap(x) => esp_idf_sys::BLE_HS_ERR_L2C_BASE + u8::from(x) as u32,
+      Self::SmUs(x) => esp_idf_sys::BLE_HS_ERR_SM_US_BASE + u8::from(x) as u32,
+      Self::SmPeer(x) => esp_idf_sys::BLE_HS_ERR_SM_PEER_BASE + u8::from(x) as u32,
+      Self::Timeout => crate::ble_error::TIMEOUT_CODE,
+      Self::Unknown(code) => code,
+    }
+  }
+
+  /// Text description of the error, if known.
+  pub fn description(&self) -> Option<&'static str> {
+    match self {
+      Self::Host(x) => x.description(),
+      Self::Att(x) => x.description(),
+      Self::Hci(x) => x.description(),
+      Self::SmUs(x) | Self::SmPeer(x) => x.description(),
+      Self::L2cap(x) => x.description(),
+      Self::Timeout => Some("Operation timed out."),
+      Self::Unknown(_) => None,
+    }
+  }
+}
+
+/// Error of the NimBLE host (`BLE_HS_E*`).
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, IntoPrimitive, FromPrimitive)]
+pub enum HostError {
+  Again = 0x01,
+  Already = 0x02,
+  InvalidArgument = 0x03,
+  MessageSize = 0x04,
+  NoEntry = 0x05,
+  NoMemory = 0x06,
+  NotConnected = 0x07,
+  NotSupported = 0x08,
+  Application = 0x09,
+  BadData = 0x0A,
+  Os = 0x0B,
+  Controller = 0x0C,
+  Timeout = 0x0D,
+  Done = 0x0E,
+  Busy = 0x0F,
+  Rejected = 0x10,
+  Unknown = 0x11,
+  Role = 0x12,
+  HciTimeout = 0x13,
+  NoMemoryEvent = 0x14,
+  NoAddress = 0x15,
+  NotSynced = 0x16,
+  InsufficientAuthentication = 0x17,
+  InsufficientAuthorization = 0x18,
+  InsufficientEncryption = 0x19,
+  InsufficientKeySize = 0x1A,
+  StoreCapacity = 0x1B,
+  StoreFailure = 0x1C,
+  Preempted = 0x1D,
+  Disabled = 0x1E,
+  Stalled = 0x1F,
+  #[num_enum(catch_all)]
+  Other(u8),
+}
+
+impl HostError {
+  pub fn description(&self) -> Option<&'static str> {
+    Some(match self {
+      Self::Again => "Temporary failure; try again.",
+      Self::Already => "Operation already in progress or completed.",
+      Self::InvalidArgument => "One or more arguments are invalid.",
+      Self::MessageSize => "The provided buffer is too small.",
+      Self::NoEntry => "No entry matching the specified criteria.",
+      Self::NoMemory => "Operation failed due to resource exhaustion.",
+      Self::NotConnected => "No open connection with the specified handle.",
+      Self::NotSupported => "Operation disabled at compile time.",
+      Self::Application => "Application callback behaved unexpectedly.",
+      Self::BadData => "Command from peer is invalid.",
+      Self::Os => "Mynewt OS error.",
+      Self::Controller => "Event from controller is invalid.",
+      Self::Timeout => "Operation timed out.",
+      Self::Done => "Operation completed successfully.",
+      Self::Busy => "Operation cannot be performed until procedure completes.",
+      Self::Rejected => "Peer rejected a connection parameter update request.",
+      Self::Unknown => "Unexpected failure; catch all.",
+      Self::Role => "Operation requires different role (e.g., central vs. peripheral).",
+      Self::HciTimeout => "HCI request timed out; controller unresponsive.",
+      Self::NoMemoryEvent => {
+        "Controller failed to send event due to memory exhaustion (combined host-controller only)."
+      }
+      Self::NoAddress => "Operation requires an identity address but none configured.",
+      Self::NotSynced => "Attempt to use the host before it is synced with controller.",
+      Self::InsufficientAuthentication => "Insufficient authentication.",
+      Self::InsufficientAuthorization => "Insufficient authorization.",
+      Self::InsufficientEncryption => "Insufficient encryption level.",
+      Self::InsufficientKeySize => "Insufficient key size",
+      Self::StoreCapacity => "Storage at capacity.",
+      Self::StoreFailure => "Storage IO error.",
+      Self::Preempted => "Operation was preempted.",
+      Self::Disabled => "Operation disabled.",
+      Self::Stalled => "Operation stalled.",
+      Self::Other(_) => return None,
+    })
+  }
+}
+
+/// ATT error code (Core Specification Vol 3, Part F, 3.4.1.1).
+///
+/// Codes `0x80..=0x9F` are defined by the application and decode to `Other`.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, IntoPrimitive, FromPrimitive)]
+pub enum AttError {
+  InvalidHandle = 0x01,
+  ReadNotPermitted = 0x02,
+  WriteNotPermitted = 0x03,
+  InvalidPdu = 0x04,
+  InsufficientAuthentication = 0x05,
+  RequestNotSupported = 0x06,
+  InvalidOffset = 0x07,
+  InsufficientAuthorization = 0x08,
+  PrepareQueueFull = 0x09,
+  AttributeNotFound = 0x0A,
+  AttributeNotLong = 0x0B,
+  InsufficientKeySize = 0x0C,
+  InvalidAttributeValueLength = 0x0D,
+  Unlikely = 0x0E,
+  InsufficientEncryption = 0x0F,
+  UnsupportedGroupType = 0x10,
+  InsufficientResources = 0x11,
+  DatabaseOutOfSync = 0x12,
+  ValueNotAllowed = 0x13,
+  /// Application error code, or a code unknown to this crate.
+  #[num_enum(catch_all)]
+  Other(u8),
+  WriteRequestRejected = 0xFC,
+  CccdImproperlyConfigured = 0xFD,
+  ProcedureAlreadyInProgress = 0xFE,
+  OutOfRange = 0xFF,
+}
+
+impl AttError {
+  pub fn description(&self) -> Option<&'static str> {
+    Some(match self {
+      Self::InvalidHandle => "The attribute handle given was not valid on this server.",
+      Self::ReadNotPermitted => "The attribute cannot be read.",
+      Self::WriteNotPermitted => "The attribute cannot be written.",
+      Self::InvalidPdu => "The attribute PDU was invalid.",
+      Self::InsufficientAuthentication => {
+        "The attribute requires authentication before it can be read or written."
+      }
+      Self::RequestNotSupported => {
+        "Attribute server does not support the request received from the client."
+      }
+      Self::InvalidOffset => "Offset specified was past the end of the attribute.",
+      Self::InsufficientAuthorization => {
+        "The attribute requires authorization before it can be read or written."
+      }
+      Self::PrepareQueueFull => "Too many prepare writes have been queued.",
+      Self::AttributeNotFound => "No attribute found within the given attribute handle range.",
+      Self::AttributeNotLong => {
+        "The attribute cannot be read or written using the Read Blob Request."
+      }
+      Self::InsufficientKeySize => {
+        "The Encryption Key Size used for encrypting this link is insufficient."
+      }
+      Self::InvalidAttributeValueLength => {
+        "The attribute value length is invalid for the operation."
+      }
+      Self::Unlikely => {
+        "The attribute request has encountered an error that was unlikely, could not be completed as requested."
+      }
+      Self::InsufficientEncryption => {
+        "The attribute requires encryption before it can be read or written."
+      }
+      Self::UnsupportedGroupType => {
+        "The attribute type is not a supported grouping attribute as defined by a higher layer specification."
+      }
+      Self::InsufficientResources => "Insufficient Resources to complete the request.",
+      Self::DatabaseOutOfSync => "The server requests the client to rediscover the database.",
+      Self::ValueNotAllowed => "The attribute parameter value was not allowed.",
+      Self::WriteRequestRejected => "The write request was rejected.",
+      Self::CccdImproperlyConfigured => {
+        "The Client Characteristic Configuration Descriptor is improperly configured."
+      }
+      Self::ProcedureAlreadyInProgress => "A request is already in progress.",
+      Self::OutOfRange => "The attribute value is out of range.",
+      Self::Other(_) => return None,
+    })
+  }
+}
+
+/// HCI error code (Core Specification Vol 1, Part F).
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, IntoPrimitive, FromPrimitive)]
+pub enum HciError {
+  UnknownCommand = 0x01,
+  UnknownConnectionId = 0x02,
+  HardwareFailure = 0x03,
+  PageTimeout = 0x04,
+  AuthenticationFailure = 0x05,
+  PinOrKeyMissing = 0x06,
+  MemoryCapacityExceeded = 0x07,
+  ConnectionTimeout = 0x08,
+  ConnectionLimitExceeded = 0x09,
+  SynchronousConnectionLimitExceeded = 0x0A,
+  ConnectionAlreadyExists = 0x0B,
+  CommandDisallowed = 0x0C,
+  RejectedLimitedResources = 0x0D,
+  RejectedSecurityReasons = 0x0E,
+  RejectedUnacceptableAddress = 0x0F,
+  ConnectionAcceptTimeout = 0x10,
+  UnsupportedFeature = 0x11,
+  InvalidCommandParameters = 0x12,
+  RemoteUserTerminatedConnection = 0x13,
+  RemoteLowResources = 0x14,
+  RemotePowerOff = 0x15,
+  ConnectionTerminatedByLocalHost = 0x16,
+  RepeatedAttempts = 0x17,
+  PairingNotAllowed = 0x18,
+  UnknownLmpPdu = 0x19,
+  UnsupportedRemoteFeature = 0x1A,
+  ScoOffsetRejected = 0x1B,
+  ScoIntervalRejected = 0x1C,
+  ScoAirModeRejected = 0x1D,
+  InvalidLlParameters = 0x1E,
+  Unspecified = 0x1F,
+  UnsupportedLlParameterValue = 0x20,
+  RoleChangeNotAllowed = 0x21,
+  LlResponseTimeout = 0x22,
+  LlProcedureCollision = 0x23,
+  LmpPduNotAllowed = 0x24,
+  EncryptionModeNotAcceptable = 0x25,
+  LinkKeyCannotBeChanged = 0x26,
+  QosNotSupported = 0x27,
+  InstantPassed = 0x28,
+  PairingWithUnitKeyNotSupported = 0x29,
+  DifferentTransactionCollision = 0x2A,
+  QosUnacceptableParameter = 0x2C,
+  QosRejected = 0x2D,
+  ChannelClassificationNotSupported = 0x2E,
+  InsufficientSecurity = 0x2F,
+  ParameterOutOfRange = 0x30,
+  RoleSwitchPending = 0x32,
+  ReservedSlotViolation = 0x34,
+  RoleSwitchFailed = 0x35,
+  ExtendedInquiryResponseTooLarge = 0x36,
+  SecureSimplePairingNotSupported = 0x37,
+  HostBusyPairing = 0x38,
+  NoSuitableChannel = 0x39,
+  ControllerBusy = 0x3A,
+  UnacceptableConnectionParameters = 0x3B,
+  AdvertisingTimeout = 0x3C,
+  ConnectionTerminatedMicFailure = 0x3D,
+  ConnectionFailedToBeEstablished = 0x3E,
+  CoarseClockAdjustmentRejected = 0x40,
+  Type0SubmapNotDefined = 0x41,
+  UnknownAdvertisingIdentifier = 0x42,
+  LimitReached = 0x43,
+  OperationCancelledByHost = 0x44,
+  PacketTooLong = 0x45,
+  #[num_enum(catch_all)]
+  Other(u8),
+}
+
+impl HciError {
+  pub fn description(&self) -> Option<&'static str> {
+    Some(match self {
+      Self::UnknownCommand => "Unknown HCI Command",
+      Self::UnknownConnectionId => "Unknown Connection Identifier",
+      Self::AuthenticationFailure => "Authentication Failure",
+      Self::PinOrKeyMissing => "PIN or Key Missing",
+      Self::ConnectionTimeout => "Connection Timeout",
+      Self::InvalidCommandParameters => "Invalid HCI Command Parameters",
+      Self::RemoteUserTerminatedConnection => "Remote User Terminated Connection",
+      Self::ConnectionTerminatedByLocalHost => "Connection Terminated By Local Host",
+      Self::UnacceptableConnectionParameters => "Unacceptable Connection Parameters",
+      Self::ConnectionTerminatedMicFailure => "Connection Terminated due to MIC Failure",
+      Self::ConnectionFailedToBeEstablished => "Connection Failed to be Established.",
+      _ => return None,
+    })
+  }
+}
+
+/// Pairing failed reason (Core Specification Vol 3, Part H, 3.5.5).
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, IntoPrimitive, FromPrimitive)]
+pub enum SmError {
+  PasskeyEntryFailed = 0x01,
+  OobNotAvailable = 0x02,
+  AuthenticationRequirements = 0x03,
+  ConfirmValueFailed = 0x04,
+  PairingNotSupported = 0x05,
+  EncryptionKeySize = 0x06,
+  CommandNotSupported = 0x07,
+  UnspecifiedReason = 0x08,
+  RepeatedAttempts = 0x09,
+  InvalidParameters = 0x0A,
+  DhKeyCheckFailed = 0x0B,
+  NumericComparisonFailed = 0x0C,
+  BrEdrPairingInProgress = 0x0D,
+  CrossTransportKeyNotAllowed = 0x0E,
+  KeyRejected = 0x0F,
+  #[num_enum(catch_all)]
+  Other(u8),
+}
+
+impl SmError {
+  pub fn description(&self) -> Option<&'static str> {
+    Some(match self {
+      Self::PasskeyEntryFailed => {
+        "The user input of passkey failed, for example, the user cancelled the operation."
+      }
+      Self::OobNotAvailable => "The OOB data is not available.",
+      Self::AuthenticationRequirements => {
+        "The pairing procedure cannot be performed as authentication requirements cannot be met due to IO capabilities of one or both devices."
+      }
+      Self::ConfirmValueFailed => "The confirm value does not match the calculated compare value.",
+      Self::PairingNotSupported => "Pairing is not supported by the device.",
+      Self::EncryptionKeySize => {
+        "The resultant encryption key size is insufficient for the security requirements of this device."
+      }
+      Self::CommandNotSupported => "The SMP command received is not supported on this device.",
+      Self::UnspecifiedReason => "Pairing failed due to an unspecified reason.",
+      Self::RepeatedAttempts => {
+        "Pairing or authentication procedure is disallowed because too little time has elapsed since last pairing request or security request."
+      }
+      Self::InvalidParameters => {
+        "The Invalid Parameters error code indicates that the command length is invalid or that a parameter is outside of the specified range."
+      }
+      Self::DhKeyCheckFailed => {
+        "Indicates to the remote device that the DHKey Check value received doesn’t match the one calculated by the local device."
+      }
+      Self::NumericComparisonFailed => {
+        "Indicates that the confirm values in the numeric comparison protocol do not match."
+      }
+      Self::BrEdrPairingInProgress => {
+        "Indicates that the pairing over the LE transport failed due to a Pairing Request sent over the BR/EDR transport in process."
+      }
+      Self::CrossTransportKeyNotAllowed => {
+        "Indicates that the BR/EDR Link Key generated on the BR/EDR transport cannot be used to derive and distribute keys for the LE transport."
+      }
+      Self::KeyRejected => "The device chose not to accept a distributed key.",
+      Self::Other(_) => return None,
+    })
+  }
+}
+
+/// L2CAP command reject reason (Core Specification Vol 3, Part A, 4.1).
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, IntoPrimitive, FromPrimitive)]
+pub enum L2capError {
+  CommandNotUnderstood = 0x00,
+  SignalingMtuExceeded = 0x01,
+  InvalidCid = 0x02,
+  #[num_enum(catch_all)]
+  Other(u8),
+}
+
+impl L2capError {
+  pub fn description(&self) -> Option<&'static str> {
+    Some(match self {
+      Self::CommandNotUnderstood => "Invalid or unsupported incoming L2CAP sig command.",
+      Self::SignalingMtuExceeded => "Incoming packet too large.",
+      Self::InvalidCid => "No channel with specified ID.",
+      Self::Other(_) => return None,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use esp_idf_sys::{
+    BLE_HS_ERR_ATT_BASE, BLE_HS_ERR_HCI_BASE, BLE_HS_ERR_L2C_BASE, BLE_HS_ERR_SM_PEER_BASE,
+    BLE_HS_ERR_SM_US_BASE,
+  };
+
+  #[test]
+  fn decodes_ranges() {
+    let cases = [
+      (0x0d, BLEErrorKind::Host(HostError::Timeout)),
+      (0x1b, BLEErrorKind::Host(HostError::StoreCapacity)),
+      (
+        BLE_HS_ERR_ATT_BASE + 0x05,
+        BLEErrorKind::Att(AttError::InsufficientAuthentication),
+      ),
+      (
+        BLE_HS_ERR_ATT_BASE + 0x0e,
+        BLEErrorKind::Att(AttError::Unlikely),
+      ),
+      (
+        BLE_HS_ERR_HCI_BASE + 0x13,
+        BLEErrorKind::Hci(HciError::RemoteUserTerminatedConnection),
+      ),
+      (
+        BLE_HS_ERR_SM_US_BASE + 0x05,
+        BLEErrorKind::SmUs(SmError::PairingNotSupported),
+      ),
+      (
+        BLE_HS_ERR_SM_PEER_BASE + 0x05,
+        BLEErrorKind::SmPeer(SmError::PairingNotSupported),
+      ),
+      (
+        BLE_HS_ERR_L2C_BASE + 0x02,
+        BLEErrorKind::L2cap(L2capError::InvalidCid),
+      ),
+      (crate::ble_error::TIMEOUT_CODE, BLEErrorKind::Timeout),
+    ];
+    for (code, kind) in cases {
+      assert_eq!(BLEErrorKind::from_code(code), kind, "code 0x{code:X}");
+      assert_eq!(kind.code(), code);
+      assert!(kind.description().is_some());
+    }
+  }
+
+  #[test]
+  fn unknown_reasons() {
+    let kind = BLEErrorKind::from_code(BLE_HS_ERR_ATT_BASE + 0x80);
+    assert_eq!(kind, BLEErrorKind::Att(AttError::Other(0x80)));
+    assert_eq!(kind.code(), BLE_HS_ERR_ATT_BASE + 0x80);
+    assert_eq!(kind.description(), None);
+
+    let kind = BLEErrorKind::from_code(0x7001);
+    assert_eq!(kind, BLEErrorKind::Unknown(0x7001));
+    assert_eq!(kind.code(), 0x7001);
+    assert_eq!(kind.description(), None);
+  }
+
+  #[test]
+  fn application_timeout() {
+    let err = crate::BLEError::timeout();
+    assert_eq!(err.kind(), BLEErrorKind::Timeout);
+    assert!(err.is_timeout());
+    assert_ne!(err.kind(), BLEErrorKind::Host(HostError::Timeout));
+  }
+}
@@ -1,25 +1,127 @@
-use crate::enums;
+use core::time::Duration;
+use esp_idf_sys::*;
+
+use crate::{
+  ble, enums, pairing_agent, pairing_window, BLEError, PairingAgent, PairingWindowEvent,
+};
+
+/// LE Secure Connections out-of-band data: the confirmation and random values, in the
+/// little-endian byte order of NimBLE.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScOobData {
+  pub confirm: [u8; 16],
+  pub random: [u8; 16],
+}
+
+impl From<ScOobData> for ble_sm_sc_oob_data {
+  fn from(data: ScOobData) -> Self {
+    Self {
+      r: data.random,
+      c: data.confirm,
+    }
+  }
+}
 
 pub struct BLESecurity {
   passkey: u32,
+  // NimBLE keeps pointers to the SC OOB data until the pairing completes.
+  oob_local: Option<ble_sm_sc_oob_data>,
+  oob_remote: Option<ble_sm_sc_oob_data>,
+  oob_tk: Option<[u8; 16]>,
 }
 
 impl BLESecurity {
   pub(crate) fn new() -> Self {
-    Self { passkey: 0 }
+    Self {
+      passkey: 0,
+      oob_local: None,
+      oob_remote: None,
+      oob_tk: None,
+    }
   }
 
   /// Set the authorization mode for this device.
   pub fn set_auth(&mut self, auth_req: enums::AuthReq) -> &mut Self {
     unsafe {
-      esp_idf_sys::ble_hs_cfg.set_sm_bonding(auth_req.contains(enums::AuthReq::Bond) as _);
       esp_idf_sys::ble_hs_cfg.set_sm_mitm(auth_req.contains(enums::AuthReq::Mitm) as _);
       esp_idf_sys::ble_hs_cfg.set_sm_sc(auth_req.contains(enums::AuthReq::Sc) as _);
     }
+    pairing_window::set_bonding(auth_req.contains(enums::AuthReq::Bond));
 
     self
   }
 
+  /// Accept new bonds only during a pairing window of `duration`, opened with
+  /// [`BLESecurity::open_pairing_window`]. Bonded peers can reconnect at any time.
+  /// A zero `duration` disables the pairing window.
+  ///
+  /// While the window is closed:
+  /// * the server and the clients refuse to pair with peers that are not bonded, at the
+  ///   passkey request or at the repeated pairing of a bonded peer, before keys are
+  ///   exchanged; Just Works pairing does not bond and the peer is disconnected once
+  ///   encrypted,
+  /// * the advertising is not discoverable and accepts connections from bonded peers only,
+  ///   or is not connectable if there are none.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # use core::time::Duration;
+  /// # use esp32_nimble::{enums::AuthReq, BLEDevice, BLEError};
+  /// # fn run() -> Result<(), BLEError> {
+  /// let device = BLEDevice::take();
+  /// device
+  ///   .security()
+  ///   .set_auth(AuthReq::Bond | AuthReq::Sc)
+  ///   .pairing_window(Duration::from_secs(120))
+  ///   .on_pairing_window(|event| ::log::info!("pairing window {:?}", event));
+  ///
+  /// // On a button press:
+  /// device.security().open_pairing_window()?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn pairing_window(&mut self, duration: Duration) -> &mut Self {
+    pairing_window::enable(duration);
+    self
+  }
+
+  /// Open the pairing window, or extend it if it is open.
+  pub fn open_pairing_window(&mut self) -> Result<(), BLEError> {
+    pairing_window::open()
+  }
+
+  /// Close the pairing window before it expires.
+  pub fn close_pairing_window(&mut self) {
+    pairing_window::close();
+  }
+
+  pub fn is_pairing_window_open(&self) -> bool {
+    pairing_window::state() == Some(true)
+  }
+
+  /// Set a callback invoked when the pairing window opens or closes.
+  pub fn on_pairing_window(
+    &mut self,
+    callback: impl FnMut(PairingWindowEvent) + Send + Sync + 'static,
+  ) -> &mut Self {
+    pairing_window::set_on_event(callback);
+    self
+  }
+
+  /// Answer the passkey, numeric comparison and OOB requests of every connection with
+  /// `agent`, in place of the `on_passkey_request` and `on_confirm_pin` callbacks of
+  /// [`crate::BLEServer`] and [`crate::BLEClient`].
+  pub fn set_pairing_agent(&mut self, agent: impl PairingAgent + 'static) -> Result<(), BLEError> {
+    pairing_agent::set_agent(agent)
+  }
+
+  /// Go back to the `on_passkey_request` and `on_confirm_pin` callbacks.
+  pub fn clear_pairing_agent(&mut self) -> &mut Self {
+    pairing_agent::clear_agent();
+    self
+  }
+
   /// Get the current passkey used for pairing.
   pub fn get_passkey(&self) -> u32 {
     self.passkey
@@ -38,6 +140,75 @@ impl BLESecurity {
     self
   }
 
+  /// Generate the LE Secure Connections OOB data of this device, to be passed to the peer
+  /// (e.g. in an NFC tag or a QR code) and used for the next pairings.
+  ///
+  /// Generate new data for each pairing when possible.
+  pub fn generate_sc_oob_data(&mut self) -> Result<ScOobData, BLEError> {
+    let mut data = ble_sm_sc_oob_data::default();
+    unsafe { ble!(ble_sm_sc_oob_generate_data(&mut data))? };
+    self.oob_local = Some(data);
+    Ok(ScOobData {
+      confirm: data.c,
+      random: data.r,
+    })
+  }
+
+  /// Set the LE Secure Connections OOB data received from the peer, `None` to clear it.
+  pub fn set_peer_sc_oob_data(&mut self, data: Option<ScOobData>) -> &mut Self {
+    self.oob_remote = data.map(Into::into);
+    self.update_oob_flag();
+    self
+  }
+
+  /// Set the temporary key of legacy OOB pairing, shared with the peer (e.g. over NFC),
+  /// `None` to clear it.
+  pub fn set_oob_tk(&mut self, tk: Option<[u8; 16]>) -> &mut Self {
+    self.oob_tk = tk;
+    self.update_oob_flag();
+    self
+  }
+
+  /// Tell the peer whether we have OOB data for it.
+  fn update_oob_flag(&mut self) {
+    let present = self.oob_remote.is_some() || self.oob_tk.is_some();
+    unsafe { ble_hs_cfg.set_sm_oob_data_flag(present as _) };
+  }
+
+  /// Inject the OOB data requested by the `BLE_SM_IOACT_OOB` and `BLE_SM_IOACT_OOB_SC`
+  /// passkey actions, terminating the connection if there is none.
+  pub(crate) fn inject_oob(&mut self, conn_handle: u16, action: u8) -> i32 {
+    let mut pkey = ble_sm_io {
+      action,
+      ..Default::default()
+    };
+
+    if action == BLE_SM_IOACT_OOB as _ {
+      let Some(tk) = self.oob_tk else {
+        ::log::warn!("no OOB temporary key set");
+        return unsafe { ble_gap_terminate(conn_handle, ble_error_codes_BLE_ERR_AUTH_FAIL as _) };
+      };
+      pkey.__bindgen_anon_1.oob = tk;
+    } else {
+      if self.oob_local.is_none() && self.oob_remote.is_none() {
+        ::log::warn!("no SC OOB data set");
+        return unsafe { ble_gap_terminate(conn_handle, ble_error_codes_BLE_ERR_AUTH_FAIL as _) };
+      }
+      pkey.__bindgen_anon_1.oob_sc_data = ble_sm_io__bindgen_ty_1__bindgen_ty_1 {
+        local: self
+          .oob_local
+          .as_mut()
+          .map_or(core::ptr::null_mut(), |x| x as *mut _),
+        remote: self
+          .oob_remote
+          .as_mut()
+          .map_or(core::ptr::null_mut(), |x| x as *mut _),
+      };
+    }
+
+    unsafe { ble_sm_inject_io(conn_handle, &mut pkey) }
+  }
+
   /// Set the Input/Output capabilities of this device.
   pub fn set_io_cap(&mut self, iocap: enums::SecurityIOCap) -> &mut Self {
     unsafe { esp_idf_sys::ble_hs_cfg.sm_io_cap = iocap as _ };
@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+
+use super::{decode_bond_records, encode_bond_records, BondRecord};
+use crate::BLEError;
+
+const MAGIC: [u8; 4] = *b"NBND";
+const BLOB_VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+const CRC_LEN: usize = 4;
+
+/// Encode records as the blob of [`BLEDevice::export_bonds`](crate::BLEDevice::export_bonds).
+///
+/// The blob is the magic `NBND`, the blob version, the encoded records and the CRC-32 of all
+/// the preceding bytes.
+pub fn encode_bond_blob(records: &[BondRecord]) -> Vec<u8> {
+  let mut blob = Vec::new();
+  blob.extend_from_slice(&MAGIC);
+  blob.push(BLOB_VERSION);
+  blob.extend_from_slice(&encode_bond_records(records));
+  let crc = crc32(&blob);
+  blob.extend_from_slice(&crc.to_le_bytes());
+  blob
+}
+
+/// Decode a blob encoded by [`encode_bond_blob`].
+///
+/// Fails with `BLE_HS_EINVAL` if `blob` is not a bond blob, `BLE_HS_ENOTSUP` if it is of
+/// an unknown version and `BLE_HS_EBADDATA` if it is corrupted.
+pub fn decode_bond_blob(blob: &[u8]) -> Result<Vec<BondRecord>, BLEError> {
+  let error = |code| BLEError::convert(code).unwrap_err();
+
+  if blob.len() < HEADER_LEN + CRC_LEN || blob[..MAGIC.len()] != MAGIC {
+    return Err(error(esp_idf_sys::BLE_HS_EINVAL));
+  }
+  if blob[MAGIC.len()] != BLOB_VERSION {
+    return Err(error(esp_idf_sys::BLE_HS_ENOTSUP));
+  }
+
+  let (data, crc) = blob.split_at(blob.len() - CRC_LEN);
+  if crc32(data).to_le_bytes() != crc {
+    return Err(error(esp_idf_sys::BLE_HS_EBADDATA));
+  }
+  decode_bond_records(&data[HEADER_LEN..]).ok_or_else(|| error(esp_idf_sys::BLE_HS_EBADDATA))
+}
+
+/// CRC-32 (IEEE 802.3).
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFF_FFFFu32;
+  for &byte in data {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+    }
+  }
+  !crc
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{BLEAddress, BLEAddressType, CccdRecord};
+
+  fn records() -> [BondRecord; 1] {
+    [BondRecord::Cccd(CccdRecord {
+      peer_addr: BLEAddress::from_le_bytes([1, 2, 3, 4, 5, 6], BLEAddressType::Public),
+      chr_val_handle: 0x10,
+      flags: 1,
+      value_changed: false,
+    })]
+  }
+
+  fn error_code(blob: &[u8]) -> u32 {
+    decode_bond_blob(blob).unwrap_err().code()
+  }
+
+  #[test]
+  fn crc() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+  }
+
+  #[test]
+  fn round_trip() {
+    let blob = encode_bond_blob(&records());
+    assert_eq!(blob[..5], *b"NBND\x01");
+    assert_eq!(
+      blob[5..blob.len() - CRC_LEN],
+      encode_bond_records(&records())
+    );
+    let crc = crc32(&blob[..blob.len() - CRC_LEN]);
+    assert_eq!(blob[blob.len() - CRC_LEN..], crc.to_le_bytes());
+    assert_eq!(decode_bond_blob(&blob).unwrap(), records());
+  }
+
+  #[test]
+  fn rejects_invalid_blobs() {
+    let blob = encode_bond_blob(&records());
+
+    assert_eq!(
+      error_code(&blob[..HEADER_LEN + CRC_LEN - 1]),
+      esp_idf_sys::BLE_HS_EINVAL
+    );
+    let mut magic = blob.clone();
+    magic[0] = b'X';
+    assert_eq!(error_code(&magic), esp_idf_sys::BLE_HS_EINVAL);
+
+    let mut version = blob.clone();
+    version[MAGIC.len()] = BLOB_VERSION + 1;
+    assert_eq!(error_code(&version), esp_idf_sys::BLE_HS_ENOTSUP);
+
+    for i in HEADER_LEN..blob.len() {
+      let mut corrupted = blob.clone();
+      corrupted[i] ^= 0x01;
+      assert_eq!(error_code(&corrupted), esp_idf_sys::BLE_HS_EBADDATA);
+    }
+
+    // Valid CRC over records that do not decode.
+    let mut truncated = blob[..blob.len() - CRC_LEN - 1].to_vec();
+    let crc = crc32(&truncated);
+    truncated.extend_from_slice(&crc.to_le_bytes());
+    assert_eq!(error_code(&truncated), esp_idf_sys::BLE_HS_EBADDATA);
+  }
+}
@@ -0,0 +1,318 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::ffi::{c_int, c_void, CStr};
+use esp_idf_sys::*;
+
+use super::{
+  bond_record::{encode_addr, Reader},
+  read_bond_records, record_from_raw, BondRecord, SecRecord,
+};
+use crate::{
+  utilities::{mutex::Mutex, nvs_load, nvs_open_namespace, nvs_store},
+  BLEAddress, BLEConnDesc, BLEError,
+};
+
+/// Longest label accepted by [`BLEDevice::set_bond_label`](crate::BLEDevice::set_bond_label),
+/// in bytes.
+pub const MAX_BOND_LABEL_LEN: usize = 64;
+
+const NAMESPACE: &str = "ble_bond_meta";
+const KEY: &CStr = c"meta";
+const FORMAT_VERSION: u8 = 1;
+
+const META_PINNED: u8 = 0x01;
+const META_LAST_CONNECTED: u8 = 0x02;
+
+/// Security level of a bond, as defined for LE security mode 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BondSecurityLevel {
+  /// Level 2: encryption with unauthenticated pairing.
+  Unauthenticated,
+  /// Level 3: encryption with authenticated pairing.
+  Authenticated,
+  /// Level 4: encryption with authenticated LE Secure Connections pairing and a 128-bit key.
+  SecureConnections,
+}
+
+impl From<&SecRecord> for BondSecurityLevel {
+  fn from(sec: &SecRecord) -> Self {
+    if sec.authenticated && sec.sc && sec.key_size == 16 {
+      Self::SecureConnections
+    } else if sec.authenticated {
+      Self::Authenticated
+    } else {
+      Self::Unauthenticated
+    }
+  }
+}
+
+/// A bonded peer and its metadata.
+#[derive(Clone, Debug)]
+pub struct BondInfo {
+  /// Identity address of the peer.
+  pub address: BLEAddress,
+  /// Label set with [`BLEDevice::set_bond_label`](crate::BLEDevice::set_bond_label).
+  pub label: Option<String>,
+  /// Time of the last encrypted connection, in seconds since the Unix epoch once the system
+  /// time is set (e.g. by SNTP), in seconds since boot before.
+  pub last_connected: Option<u64>,
+  pub security_level: BondSecurityLevel,
+  /// Pinned bonds are never deleted to make room for a new bond.
+  pub pinned: bool,
+}
+
+/// What to do when the bond store has no room for a new bond.
+#[allow(clippy::type_complexity)]
+#[derive(Default)]
+pub enum BondStoreFullPolicy {
+  /// Delete the least recently connected bond that is not pinned.
+  #[default]
+  EvictLeastRecentlyUsed,
+  /// Keep the existing bonds; the new bond is not stored.
+  RejectNew,
+  /// Delete the bond whose address the callback returns, or reject the new bond if it returns
+  /// `None`. The callback gets the bonds that can be deleted, least recently connected first.
+  ///
+  /// The callback runs in the NimBLE host task.
+  Callback(Box<dyn FnMut(&[BondInfo]) -> Option<BLEAddress> + Send>),
+}
+
+struct BondMetadata {
+  address: BLEAddress,
+  label: Option<String>,
+  last_connected: Option<u64>,
+  /// Orders the bonds by their last connection even if the system time is not set.
+  connection_seq: u32,
+  pinned: bool,
+}
+
+/// The metadata of the bonds, persisted in NVS if the namespace can be opened.
+struct Metadata {
+  handle: Option<nvs_handle_t>,
+  entries: Vec<BondMetadata>,
+}
+
+static METADATA: Mutex<Option<Metadata>> = Mutex::new(None);
+static STORE_FULL_POLICY: Mutex<BondStoreFullPolicy> =
+  Mutex::new(BondStoreFullPolicy::EvictLeastRecentlyUsed);
+
+impl Metadata {
+  fn open() -> Self {
+    let handle = nvs_open_namespace(NAMESPACE).ok();
+    let entries = match handle.map(|x| nvs_load(x, KEY)) {
+      Some(Ok(Some(data))) => decode_metadata(&data).unwrap_or_else(|| {
+        ::log::warn!("discarding undecodable bond metadata");
+        Vec::new()
+      }),
+      _ => Vec::new(),
+    };
+    Self { handle, entries }
+  }
+
+  fn entry(&mut self, address: &BLEAddress) -> &mut BondMetadata {
+    let idx = match self.entries.iter().position(|x| x.address == *address) {
+      Some(idx) => idx,
+      None => {
+        self.entries.push(BondMetadata {
+          address: *address,
+          label: None,
+          last_connected: None,
+          connection_seq: 0,
+          pinned: false,
+        });
+        self.entries.len() - 1
+      }
+    };
+    &mut self.entries[idx]
+  }
+
+  fn persist(&self) -> Result<(), BLEError> {
+    match self.handle {
+      Some(handle) => nvs_store(handle, KEY, &encode_metadata(&self.entries)),
+      None => Ok(()),
+    }
+  }
+}
+
+fn with_metadata<R>(f: impl FnOnce(&mut Metadata) -> R) -> R {
+  let mut metadata = METADATA.lock();
+  f(metadata.get_or_insert_with(Metadata::open))
+}
+
+/// The bonds of the bond store in use, least recently connected first.
+pub(crate) fn bonds() -> Result<Vec<BondInfo>, BLEError> {
+  let records = read_bond_records()?;
+  let mut bonds: Vec<(u32, BondInfo)> = Vec::new();
+
+  with_metadata(|metadata| {
+    for sec in records.iter().filter_map(|x| match x {
+      BondRecord::OurSec(x) => Some(x),
+      _ => None,
+    }) {
+      if bonds.iter().any(|(_, x)| x.address == sec.peer_addr) {
+        continue;
+      }
+      let peer_sec = records
+        .iter()
+        .find_map(|x| match x {
+          BondRecord::PeerSec(x) if x.peer_addr == sec.peer_addr => Some(x),
+          _ => None,
+        })
+        .unwrap_or(sec);
+      let meta = metadata.entries.iter().find(|x| x.address == sec.peer_addr);
+
+      bonds.push((
+        meta.map_or(0, |x| x.connection_seq),
+        BondInfo {
+          address: sec.peer_addr,
+          label: meta.and_then(|x| x.label.clone()),
+          last_connected: meta.and_then(|x| x.last_connected),
+          security_level: peer_sec.into(),
+          pinned: meta.is_some_and(|x| x.pinned),
+        },
+      ));
+    }
+  });
+
+  bonds.sort_by_key(|(seq, _)| *seq);
+  Ok(bonds.into_iter().map(|(_, x)| x).collect())
+}
+
+/// Record an encrypted connection to a peer.
+pub(crate) fn on_bond_encrypted(desc: &BLEConnDesc) {
+  if !desc.bonded() {
+    return;
+  }
+
+  let address = desc.id_address();
+  let now = unsafe { time(core::ptr::null_mut()) };
+  with_metadata(|metadata| {
+    let seq = metadata
+      .entries
+      .iter()
+      .map(|x| x.connection_seq)
+      .max()
+      .unwrap_or(0)
+      + 1;
+    let entry = metadata.entry(&address);
+    entry.last_connected = u64::try_from(now).ok();
+    entry.connection_seq = seq;
+    metadata.persist().ok();
+  });
+}
+
+pub(crate) fn set_bond_label(address: &BLEAddress, label: Option<&str>) -> Result<(), BLEError> {
+  if label.is_some_and(|x| x.len() > MAX_BOND_LABEL_LEN) {
+    return BLEError::convert(BLE_HS_EINVAL);
+  }
+  update_bond(address, |x| x.label = label.map(String::from))
+}
+
+pub(crate) fn set_bond_pinned(address: &BLEAddress, pinned: bool) -> Result<(), BLEError> {
+  update_bond(address, |x| x.pinned = pinned)
+}
+
+fn update_bond(address: &BLEAddress, f: impl FnOnce(&mut BondMetadata)) -> Result<(), BLEError> {
+  if !bonds()?.iter().any(|x| x.address == *address) {
+    return BLEError::convert(BLE_HS_ENOENT);
+  }
+  with_metadata(|metadata| {
+    f(metadata.entry(address));
+    metadata.persist()
+  })
+}
+
+/// Forget the metadata of `address`, or of all the peers if `None`.
+pub(crate) fn forget_bond(address: Option<&BLEAddress>) {
+  with_metadata(|metadata| {
+    metadata
+      .entries
+      .retain(|x| address.is_some_and(|address| x.address != *address));
+    metadata.persist().ok();
+  });
+}
+
+pub(crate) fn set_store_full_policy(policy: BondStoreFullPolicy) {
+  *STORE_FULL_POLICY.lock() = policy;
+}
+
+/// `ble_hs_cfg.store_status_cb` applying the [`BondStoreFullPolicy`].
+pub(crate) extern "C" fn on_store_status(
+  event: *mut ble_store_status_event,
+  _arg: *mut c_void,
+) -> c_int {
+  let event = unsafe { &*event };
+  if event.event_code != BLE_STORE_EVENT_OVERFLOW as _ {
+    return 0;
+  }
+
+  // The record being written must not make room for itself.
+  let overflow = unsafe { &event.__bindgen_anon_1.overflow };
+  let new_peer =
+    unsafe { record_from_raw(overflow.obj_type, &*overflow.value) }.map(|x| x.peer_addr());
+
+  let Ok(mut candidates) = bonds() else {
+    return BLE_HS_ESTORE_CAP as _;
+  };
+  candidates.retain(|x| !x.pinned && Some(x.address) != new_peer);
+
+  let victim = match &mut *STORE_FULL_POLICY.lock() {
+    BondStoreFullPolicy::EvictLeastRecentlyUsed => candidates.first().map(|x| x.address),
+    BondStoreFullPolicy::RejectNew => None,
+    BondStoreFullPolicy::Callback(callback) => {
+      callback(&candidates).filter(|address| candidates.iter().any(|x| x.address == *address))
+    }
+  };
+  let Some(victim) = victim else {
+    ::log::warn!("bond store full; rejecting the bond of {:?}", new_peer);
+    return BLE_HS_ESTORE_CAP as _;
+  };
+
+  ::log::info!("bond store full; deleting the bond of {}", victim);
+  forget_bond(Some(&victim));
+  unsafe { ble_gap_unpair(&victim.value) }
+}
+
+fn encode_metadata(entries: &[BondMetadata]) -> Vec<u8> {
+  let mut out = Vec::new();
+  out.push(FORMAT_VERSION);
+  out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+  for entry in entries {
+    encode_addr(&entry.address, &mut out);
+    out.push(
+      (entry.pinned as u8 * META_PINNED)
+        | (entry.last_connected.is_some() as u8 * META_LAST_CONNECTED),
+    );
+    out.extend_from_slice(&entry.last_connected.unwrap_or(0).to_le_bytes());
+    out.extend_from_slice(&entry.connection_seq.to_le_bytes());
+    let label = entry.label.as_deref().unwrap_or("");
+    out.push(label.len() as u8);
+    out.extend_from_slice(label.as_bytes());
+  }
+  out
+}
+
+fn decode_metadata(data: &[u8]) -> Option<Vec<BondMetadata>> {
+  let mut reader = Reader(data);
+  if reader.u8()? != FORMAT_VERSION {
+    return None;
+  }
+  let count = u16::from_le_bytes(reader.array()?);
+
+  let mut entries = Vec::with_capacity(count as _);
+  for _ in 0..count {
+    let address = reader.addr()?;
+    let flags = reader.u8()?;
+    let last_connected = u64::from_le_bytes(reader.array()?);
+    let connection_seq = u32::from_le_bytes(reader.array()?);
+    let len = reader.u8()?;
+    let label = core::str::from_utf8(reader.bytes(len as _)?).ok()?;
+    entries.push(BondMetadata {
+      address,
+      label: (!label.is_empty()).then(|| label.into()),
+      last_connected: (flags & META_LAST_CONNECTED != 0).then_some(last_connected),
+      connection_seq,
+      pinned: flags & META_PINNED != 0,
+    });
+  }
+  reader.0.is_empty().then_some(entries)
+}
@@ -0,0 +1,388 @@
+use alloc::vec::Vec;
+
+use crate::{BLEAddress, BLEAddressType};
+
+/// Security keys of a bond, distributed by us or by the peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SecRecord {
+  pub peer_addr: BLEAddress,
+  pub key_size: u8,
+  pub ediv: u16,
+  pub rand_num: u64,
+  pub ltk: Option<[u8; 16]>,
+  pub irk: Option<[u8; 16]>,
+  pub csrk: Option<[u8; 16]>,
+  /// The keys were distributed over an authenticated (MITM protected) link.
+  pub authenticated: bool,
+  /// The keys were generated with LE Secure Connections.
+  pub sc: bool,
+}
+
+/// Client characteristic configuration written by a bonded peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CccdRecord {
+  pub peer_addr: BLEAddress,
+  pub chr_val_handle: u16,
+  pub flags: u16,
+  pub value_changed: bool,
+}
+
+/// Record of a [`BondStore`](crate::BondStore).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BondRecord {
+  /// Keys we distributed to the peer.
+  OurSec(SecRecord),
+  /// Keys the peer distributed to us.
+  PeerSec(SecRecord),
+  Cccd(CccdRecord),
+}
+
+/// Selects the security records of a peer. `None` fields match any record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SecKey {
+  pub peer_addr: Option<BLEAddress>,
+  pub ediv_rand: Option<(u16, u64)>,
+}
+
+/// Selects the CCCD records of a peer. `None` fields match any record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CccdKey {
+  pub peer_addr: Option<BLEAddress>,
+  pub chr_val_handle: Option<u16>,
+}
+
+/// Key selecting records of a [`BondStore`](crate::BondStore).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BondKey {
+  OurSec(SecKey),
+  PeerSec(SecKey),
+  Cccd(CccdKey),
+}
+
+impl BondRecord {
+  pub fn peer_addr(&self) -> BLEAddress {
+    match self {
+      Self::OurSec(x) | Self::PeerSec(x) => x.peer_addr,
+      Self::Cccd(x) => x.peer_addr,
+    }
+  }
+
+  /// The key identifying the record. Writing a record replaces the stored record with the
+  /// same key.
+  pub fn key(&self) -> BondKey {
+    let sec_key = |x: &SecRecord| SecKey {
+      peer_addr: Some(x.peer_addr),
+      ediv_rand: Some((x.ediv, x.rand_num)),
+    };
+    match self {
+      Self::OurSec(x) => BondKey::OurSec(sec_key(x)),
+      Self::PeerSec(x) => BondKey::PeerSec(sec_key(x)),
+      Self::Cccd(x) => BondKey::Cccd(CccdKey {
+        peer_addr: Some(x.peer_addr),
+        chr_val_handle: Some(x.chr_val_handle),
+      }),
+    }
+  }
+
+  /// Append the binary encoding of the record to `out`.
+  pub fn encode(&self, out: &mut Vec<u8>) {
+    match self {
+      Self::OurSec(x) | Self::PeerSec(x) => {
+        out.push(if matches!(self, Self::OurSec(_)) {
+          TAG_OUR_SEC
+        } else {
+          TAG_PEER_SEC
+        });
+        encode_addr(&x.peer_addr, out);
+        let flags = (x.ltk.is_some() as u8 * SEC_LTK)
+          | (x.irk.is_some() as u8 * SEC_IRK)
+          | (x.csrk.is_some() as u8 * SEC_CSRK)
+          | (x.authenticated as u8 * SEC_AUTHENTICATED)
+          | (x.sc as u8 * SEC_SC);
+        out.push(x.key_size);
+        out.extend_from_slice(&x.ediv.to_le_bytes());
+        out.extend_from_slice(&x.rand_num.to_le_bytes());
+        out.push(flags);
+        for key in [x.ltk, x.irk, x.csrk].iter().flatten() {
+          out.extend_from_slice(key);
+        }
+      }
+      Self::Cccd(x) => {
+        out.push(TAG_CCCD);
+        encode_addr(&x.peer_addr, out);
+        out.extend_from_slice(&x.chr_val_handle.to_le_bytes());
+        out.extend_from_slice(&x.flags.to_le_bytes());
+        out.push(x.value_changed as u8);
+      }
+    }
+  }
+
+  /// Decode a record from the start of `data`, returning it and the number of bytes read.
+  pub fn decode(data: &[u8]) -> Option<(Self, usize)> {
+    let mut reader = Reader(data);
+    let tag = reader.u8()?;
+    let peer_addr = reader.addr()?;
+
+    let record = match tag {
+      TAG_OUR_SEC | TAG_PEER_SEC => {
+        let key_size = reader.u8()?;
+        let ediv = u16::from_le_bytes(reader.array()?);
+        let rand_num = u64::from_le_bytes(reader.array()?);
+        let flags = reader.u8()?;
+        let mut key = |flag| match flags & flag {
+          0 => Some(None),
+          _ => reader.array().map(Some),
+        };
+        let sec = SecRecord {
+          peer_addr,
+          key_size,
+          ediv,
+          rand_num,
+          ltk: key(SEC_LTK)?,
+          irk: key(SEC_IRK)?,
+          csrk: key(SEC_CSRK)?,
+          authenticated: flags & SEC_AUTHENTICATED != 0,
+          sc: flags & SEC_SC != 0,
+        };
+        if tag == TAG_OUR_SEC {
+          Self::OurSec(sec)
+        } else {
+          Self::PeerSec(sec)
+        }
+      }
+      TAG_CCCD => Self::Cccd(CccdRecord {
+        peer_addr,
+        chr_val_handle: u16::from_le_bytes(reader.array()?),
+        flags: u16::from_le_bytes(reader.array()?),
+        value_changed: reader.u8()? != 0,
+      }),
+      _ => return None,
+    };
+
+    Some((record, data.len() - reader.0.len()))
+  }
+}
+
+impl BondKey {
+  /// Whether the key selects `record`.
+  pub fn matches(&self, record: &BondRecord) -> bool {
+    match (self, record) {
+      (Self::OurSec(key), BondRecord::OurSec(x)) | (Self::PeerSec(key), BondRecord::PeerSec(x)) => {
+        addr_matches(key.peer_addr, &x.peer_addr)
+          && key
+            .ediv_rand
+            .is_none_or(|(ediv, rand_num)| ediv == x.ediv && rand_num == x.rand_num)
+      }
+      (Self::Cccd(key), BondRecord::Cccd(x)) => {
+        addr_matches(key.peer_addr, &x.peer_addr)
+          && key
+            .chr_val_handle
+            .is_none_or(|handle| handle == x.chr_val_handle)
+      }
+      _ => false,
+    }
+  }
+}
+
+const FORMAT_VERSION: u8 = 1;
+
+const TAG_OUR_SEC: u8 = 1;
+const TAG_PEER_SEC: u8 = 2;
+const TAG_CCCD: u8 = 3;
+
+const SEC_LTK: u8 = 0x01;
+const SEC_IRK: u8 = 0x02;
+const SEC_CSRK: u8 = 0x04;
+const SEC_AUTHENTICATED: u8 = 0x08;
+const SEC_SC: u8 = 0x10;
+
+/// Encode records in the format of [`decode_bond_records`].
+pub fn encode_bond_records(records: &[BondRecord]) -> Vec<u8> {
+  let mut out = Vec::new();
+  out.push(FORMAT_VERSION);
+  out.extend_from_slice(&(records.len() as u16).to_le_bytes());
+  for record in records {
+    record.encode(&mut out);
+  }
+  out
+}
+
+/// Decode records encoded by [`encode_bond_records`].
+///
+/// Returns `None` if the data is truncated, malformed or of an unknown format version.
+pub fn decode_bond_records(data: &[u8]) -> Option<Vec<BondRecord>> {
+  let mut reader = Reader(data);
+  if reader.u8()? != FORMAT_VERSION {
+    return None;
+  }
+  let count = u16::from_le_bytes(reader.array()?);
+
+  let mut records = Vec::with_capacity(count as _);
+  for _ in 0..count {
+    let (record, len) = BondRecord::decode(reader.0)?;
+    reader.0 = &reader.0[len..];
+    records.push(record);
+  }
+  reader.0.is_empty().then_some(records)
+}
+
+/// Compare the exact address type, unlike `BLEAddress::eq` which equates identity types.
+fn addr_matches(key: Option<BLEAddress>, addr: &BLEAddress) -> bool {
+  key.is_none_or(|x| x.value.type_ == addr.value.type_ && x.value.val == addr.value.val)
+}
+
+pub(super) fn encode_addr(addr: &BLEAddress, out: &mut Vec<u8>) {
+  out.push(addr.value.type_);
+  out.extend_from_slice(&addr.value.val);
+}
+
+pub(super) struct Reader<'a>(pub(super) &'a [u8]);
+
+impl<'a> Reader<'a> {
+  pub(super) fn u8(&mut self) -> Option<u8> {
+    let (x, rest) = self.0.split_first()?;
+    self.0 = rest;
+    Some(*x)
+  }
+
+  pub(super) fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+    if self.0.len() < N {
+      return None;
+    }
+    let (x, rest) = self.0.split_at(N);
+    self.0 = rest;
+    x.try_into().ok()
+  }
+
+  pub(super) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+    if self.0.len() < len {
+      return None;
+    }
+    let (x, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Some(x)
+  }
+
+  pub(super) fn addr(&mut self) -> Option<BLEAddress> {
+    let addr_type = BLEAddressType::try_from(self.u8()?).ok()?;
+    Some(BLEAddress::from_le_bytes(self.array()?, addr_type))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+
+  fn addr(addr_type: BLEAddressType) -> BLEAddress {
+    BLEAddress::from_le_bytes([1, 2, 3, 4, 5, 0xc6], addr_type)
+  }
+
+  fn sec(ltk: Option<[u8; 16]>, csrk: Option<[u8; 16]>) -> SecRecord {
+    SecRecord {
+      peer_addr: addr(BLEAddressType::Random),
+      key_size: 16,
+      ediv: 0x1234,
+      rand_num: 0x0102_0304_0506_0708,
+      ltk,
+      irk: None,
+      csrk,
+      authenticated: true,
+      sc: false,
+    }
+  }
+
+  #[test]
+  fn cccd_encoding() {
+    let record = BondRecord::Cccd(CccdRecord {
+      peer_addr: addr(BLEAddressType::Public),
+      chr_val_handle: 0x002a,
+      flags: 0x0002,
+      value_changed: true,
+    });
+    let mut out = vec![];
+    record.encode(&mut out);
+    assert_eq!(out[..2], [TAG_CCCD, BLEAddressType::Public as u8]);
+    assert_eq!(out[2..], [1, 2, 3, 4, 5, 0xc6, 0x2a, 0x00, 0x02, 0x00, 1]);
+    assert_eq!(BondRecord::decode(&out), Some((record, out.len())));
+  }
+
+  #[test]
+  fn sec_encoding() {
+    let record = BondRecord::PeerSec(sec(Some([0xaa; 16]), Some([0xcc; 16])));
+    let mut out = vec![];
+    record.encode(&mut out);
+    assert_eq!(out.len(), 1 + 7 + 1 + 2 + 8 + 1 + 32);
+    assert_eq!(out[0], TAG_PEER_SEC);
+    assert_eq!(out[8..11], [16, 0x34, 0x12]);
+    assert_eq!(out[19], SEC_LTK | SEC_CSRK | SEC_AUTHENTICATED);
+    assert_eq!(out[20..36], [0xaa; 16]);
+    assert_eq!(out[36..], [0xcc; 16]);
+    assert_eq!(BondRecord::decode(&out), Some((record, out.len())));
+
+    // Truncated keys.
+    assert_eq!(BondRecord::decode(&out[..out.len() - 1]), None);
+    // Unknown tag.
+    out[0] = 0x7f;
+    assert_eq!(BondRecord::decode(&out), None);
+  }
+
+  #[test]
+  fn records_round_trip() {
+    let records = [
+      BondRecord::OurSec(sec(Some([0x11; 16]), None)),
+      BondRecord::PeerSec(sec(None, None)),
+      BondRecord::Cccd(CccdRecord {
+        peer_addr: addr(BLEAddressType::Random),
+        chr_val_handle: 3,
+        flags: 1,
+        value_changed: false,
+      }),
+    ];
+    let data = encode_bond_records(&records);
+    assert_eq!(data[..3], [FORMAT_VERSION, 3, 0]);
+    assert_eq!(decode_bond_records(&data).unwrap(), records);
+
+    assert_eq!(decode_bond_records(&data[..data.len() - 1]), None);
+    let mut trailing = data.clone();
+    trailing.push(0);
+    assert_eq!(decode_bond_records(&trailing), None);
+    let mut version = data;
+    version[0] = FORMAT_VERSION + 1;
+    assert_eq!(decode_bond_records(&version), None);
+    assert_eq!(decode_bond_records(&encode_bond_records(&[])), Some(vec![]));
+  }
+
+  #[test]
+  fn keys() {
+    let record = BondRecord::OurSec(sec(None, None));
+    assert_eq!(
+      record.key(),
+      BondKey::OurSec(SecKey {
+        peer_addr: Some(addr(BLEAddressType::Random)),
+        ediv_rand: Some((0x1234, 0x0102_0304_0506_0708)),
+      })
+    );
+    assert!(record.key().matches(&record));
+
+    let any = SecKey {
+      peer_addr: None,
+      ediv_rand: None,
+    };
+    assert!(BondKey::OurSec(any).matches(&record));
+    assert!(!BondKey::PeerSec(any).matches(&record));
+
+    // The address type is part of the identity.
+    let public = SecKey {
+      peer_addr: Some(addr(BLEAddressType::Public)),
+      ediv_rand: None,
+    };
+    assert!(!BondKey::OurSec(public).matches(&record));
+
+    let other_rand = SecKey {
+      peer_addr: None,
+      ediv_rand: Some((0x1234, 0)),
+    };
+    assert!(!BondKey::OurSec(other_rand).matches(&record));
+  }
+}
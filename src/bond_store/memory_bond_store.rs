@@ -0,0 +1,94 @@
+use alloc::vec::Vec;
+
+use super::{BondKey, BondRecord, BondStore};
+use crate::BLEError;
+
+/// Bond store keeping the records in RAM. The bonds are lost on reset.
+///
+/// Records are kept oldest first, so that NimBLE deletes the oldest bond when the store is
+/// full.
+pub struct MemoryBondStore {
+  pub(crate) records: Vec<BondRecord>,
+  max_bonds: usize,
+  max_cccds: usize,
+}
+
+impl MemoryBondStore {
+  /// Create a store with the capacity of the NimBLE configuration.
+  pub fn new() -> Self {
+    Self::with_capacity(
+      esp_idf_sys::MYNEWT_VAL_BLE_STORE_MAX_BONDS as _,
+      esp_idf_sys::MYNEWT_VAL_BLE_STORE_MAX_CCCDS as _,
+    )
+  }
+
+  /// Create a store holding the keys of up to `max_bonds` peers and `max_cccds` CCCD values.
+  ///
+  /// NimBLE can not handle more bonds than `MYNEWT_VAL_BLE_STORE_MAX_BONDS`, so `max_bonds`
+  /// is limited to it.
+  pub fn with_capacity(max_bonds: usize, max_cccds: usize) -> Self {
+    Self {
+      records: Vec::new(),
+      max_bonds: max_bonds.min(esp_idf_sys::MYNEWT_VAL_BLE_STORE_MAX_BONDS as _),
+      max_cccds,
+    }
+  }
+
+  /// The stored records, oldest first.
+  pub fn records(&self) -> &[BondRecord] {
+    &self.records
+  }
+
+  fn is_full(&self, record: &BondRecord) -> bool {
+    let same_kind =
+      |x: &&BondRecord| core::mem::discriminant(*x) == core::mem::discriminant(record);
+    let count = self.records.iter().filter(same_kind).count();
+    match record {
+      BondRecord::OurSec(_) | BondRecord::PeerSec(_) => count >= self.max_bonds,
+      BondRecord::Cccd(_) => count >= self.max_cccds,
+    }
+  }
+}
+
+impl Default for MemoryBondStore {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl BondStore for MemoryBondStore {
+  fn read(&mut self, key: &BondKey, skip: usize) -> Result<Option<BondRecord>, BLEError> {
+    Ok(
+      self
+        .records
+        .iter()
+        .filter(|x| key.matches(x))
+        .nth(skip)
+        .copied(),
+    )
+  }
+
+  fn write(&mut self, record: &BondRecord) -> Result<(), BLEError> {
+    let key = record.key();
+    if let Some(x) = self.records.iter_mut().find(|x| key.matches(x)) {
+      *x = *record;
+      return Ok(());
+    }
+
+    if self.is_full(record) {
+      return BLEError::convert(esp_idf_sys::BLE_HS_ESTORE_CAP);
+    }
+    self.records.push(*record);
+    Ok(())
+  }
+
+  fn delete(&mut self, key: &BondKey) -> Result<bool, BLEError> {
+    match self.records.iter().position(|x| key.matches(x)) {
+      Some(idx) => {
+        self.records.remove(idx);
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+}
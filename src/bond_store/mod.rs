@@ -0,0 +1,246 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::ffi::c_int;
+use esp_idf_sys::*;
+
+use crate::{ble, utilities::mutex::Mutex, BLEAddress, BLEError};
+
+mod bond_blob;
+pub use self::bond_blob::*;
+
+mod bond_manager;
+pub(crate) use self::bond_manager::{
+  bonds, forget_bond, on_bond_encrypted, on_store_status, set_bond_label, set_bond_pinned,
+  set_store_full_policy,
+};
+pub use self::bond_manager::{
+  BondInfo, BondSecurityLevel, BondStoreFullPolicy, MAX_BOND_LABEL_LEN,
+};
+
+mod bond_record;
+pub use self::bond_record::*;
+
+mod memory_bond_store;
+pub use self::memory_bond_store::MemoryBondStore;
+
+mod nvs_bond_store;
+pub use self::nvs_bond_store::NvsBondStore;
+
+/// Storage of the bonds: the keys exchanged with bonded peers and their CCCD values.
+///
+/// NimBLE keeps the bonds in its own NVS layout unless a store is set with
+/// [`BLEDevice::set_bond_store`](crate::BLEDevice::set_bond_store). Implementations
+/// other than [`MemoryBondStore`] and [`NvsBondStore`] can keep them in encrypted flash or a
+/// secure element, and [`encode_bond_records`] can be used for their serialization.
+///
+/// The methods are called in the NimBLE host task.
+pub trait BondStore: Send {
+  /// Read the `skip`-th record, oldest first, matching `key`.
+  fn read(&mut self, key: &BondKey, skip: usize) -> Result<Option<BondRecord>, BLEError>;
+
+  /// Store a record, replacing the record with the same [key](BondRecord::key).
+  ///
+  /// Return `BLE_HS_ESTORE_CAP` if the store is full; a bond is then deleted as chosen by
+  /// [`BLEDevice::set_bond_store_full_policy`](crate::BLEDevice::set_bond_store_full_policy)
+  /// and the record written again, unless the policy rejects the new bond.
+  fn write(&mut self, record: &BondRecord) -> Result<(), BLEError>;
+
+  /// Delete the oldest record matching `key`, returning whether a record was deleted.
+  fn delete(&mut self, key: &BondKey) -> Result<bool, BLEError>;
+}
+
+static BOND_STORE: Mutex<Option<Box<dyn BondStore>>> = Mutex::new(None);
+
+/// Use `store` for the bonds in place of the NimBLE store.
+pub(crate) fn set_bond_store(store: Box<dyn BondStore>) {
+  *BOND_STORE.lock() = Some(store);
+  unsafe {
+    ble_hs_cfg.store_read_cb = Some(on_store_read);
+    ble_hs_cfg.store_write_cb = Some(on_store_write);
+    ble_hs_cfg.store_delete_cb = Some(on_store_delete);
+  }
+}
+
+const OBJ_TYPES: [u32; 3] = [
+  BLE_STORE_OBJ_TYPE_OUR_SEC,
+  BLE_STORE_OBJ_TYPE_PEER_SEC,
+  BLE_STORE_OBJ_TYPE_CCCD,
+];
+
+/// Read all records of the bond store in use, whether NimBLE's or a [`BondStore`].
+pub(crate) fn read_bond_records() -> Result<Vec<BondRecord>, BLEError> {
+  let mut records = Vec::new();
+  for obj_type in OBJ_TYPES {
+    for idx in 0..=u8::MAX {
+      let mut key: ble_store_key = unsafe { core::mem::zeroed() };
+      if obj_type == BLE_STORE_OBJ_TYPE_CCCD {
+        key.cccd.idx = idx;
+      } else {
+        key.sec.idx = idx;
+      }
+
+      let mut value: ble_store_value = unsafe { core::mem::zeroed() };
+      let rc = unsafe { ble_store_read(obj_type as _, &key, &mut value) };
+      if rc == BLE_HS_ENOENT as _ {
+        break;
+      }
+      ble!(rc)?;
+      records.extend(unsafe { record_from_raw(obj_type as _, &value) });
+    }
+  }
+  Ok(records)
+}
+
+/// Write a record to the bond store in use.
+pub(crate) fn write_bond_record(record: &BondRecord) -> Result<(), BLEError> {
+  let obj_type = match record {
+    BondRecord::OurSec(_) => BLE_STORE_OBJ_TYPE_OUR_SEC,
+    BondRecord::PeerSec(_) => BLE_STORE_OBJ_TYPE_PEER_SEC,
+    BondRecord::Cccd(_) => BLE_STORE_OBJ_TYPE_CCCD,
+  };
+  unsafe { ble!(ble_store_write(obj_type as _, &record_to_raw(record))) }
+}
+
+extern "C" fn on_store_read(
+  obj_type: c_int,
+  key: *const ble_store_key,
+  dst: *mut ble_store_value,
+) -> c_int {
+  let Some((key, skip)) = (unsafe { key_from_raw(obj_type, &*key) }) else {
+    return BLE_HS_ENOTSUP as _;
+  };
+
+  let mut store = BOND_STORE.lock();
+  match store.as_mut().map(|x| x.read(&key, skip)) {
+    Some(Ok(Some(record))) => {
+      unsafe { *dst = record_to_raw(&record) };
+      0
+    }
+    Some(Err(err)) => err.code() as _,
+    _ => BLE_HS_ENOENT as _,
+  }
+}
+
+extern "C" fn on_store_write(obj_type: c_int, val: *const ble_store_value) -> c_int {
+  let Some(record) = (unsafe { record_from_raw(obj_type, &*val) }) else {
+    return BLE_HS_ENOTSUP as _;
+  };
+
+  let mut store = BOND_STORE.lock();
+  match store.as_mut().map(|x| x.write(&record)) {
+    Some(Ok(())) => 0,
+    Some(Err(err)) => err.code() as _,
+    None => BLE_HS_ESTORE_FAIL as _,
+  }
+}
+
+extern "C" fn on_store_delete(obj_type: c_int, key: *const ble_store_key) -> c_int {
+  let Some((key, _)) = (unsafe { key_from_raw(obj_type, &*key) }) else {
+    return BLE_HS_ENOTSUP as _;
+  };
+
+  let mut store = BOND_STORE.lock();
+  match store.as_mut().map(|x| x.delete(&key)) {
+    Some(Ok(true)) => 0,
+    Some(Err(err)) => err.code() as _,
+    _ => BLE_HS_ENOENT as _,
+  }
+}
+
+/// `BLE_ADDR_ANY` matches any peer.
+fn peer_from_raw(addr: &ble_addr_t) -> Option<BLEAddress> {
+  (addr.type_ != 0 || addr.val != [0; 6]).then_some(BLEAddress::from(*addr))
+}
+
+unsafe fn key_from_raw(obj_type: c_int, key: &ble_store_key) -> Option<(BondKey, usize)> {
+  match obj_type as u32 {
+    BLE_STORE_OBJ_TYPE_OUR_SEC | BLE_STORE_OBJ_TYPE_PEER_SEC => {
+      let key_sec = &key.sec;
+      let sec = SecKey {
+        peer_addr: peer_from_raw(&key_sec.peer_addr),
+        ediv_rand: (key_sec.ediv_rand_present() != 0).then_some((key_sec.ediv, key_sec.rand_num)),
+      };
+      let key = if obj_type as u32 == BLE_STORE_OBJ_TYPE_OUR_SEC {
+        BondKey::OurSec(sec)
+      } else {
+        BondKey::PeerSec(sec)
+      };
+      Some((key, key_sec.idx as _))
+    }
+    BLE_STORE_OBJ_TYPE_CCCD => {
+      let key_cccd = &key.cccd;
+      let key = BondKey::Cccd(CccdKey {
+        peer_addr: peer_from_raw(&key_cccd.peer_addr),
+        chr_val_handle: (key_cccd.chr_val_handle != 0).then_some(key_cccd.chr_val_handle),
+      });
+      Some((key, key_cccd.idx as _))
+    }
+    _ => None,
+  }
+}
+
+unsafe fn record_from_raw(obj_type: c_int, value: &ble_store_value) -> Option<BondRecord> {
+  match obj_type as u32 {
+    BLE_STORE_OBJ_TYPE_OUR_SEC | BLE_STORE_OBJ_TYPE_PEER_SEC => {
+      let value = &value.sec;
+      let sec = SecRecord {
+        peer_addr: BLEAddress::from(value.peer_addr),
+        key_size: value.key_size,
+        ediv: value.ediv,
+        rand_num: value.rand_num,
+        ltk: (value.ltk_present() != 0).then_some(value.ltk),
+        irk: (value.irk_present() != 0).then_some(value.irk),
+        csrk: (value.csrk_present() != 0).then_some(value.csrk),
+        authenticated: value.authenticated() != 0,
+        sc: value.sc() != 0,
+      };
+      if obj_type as u32 == BLE_STORE_OBJ_TYPE_OUR_SEC {
+        Some(BondRecord::OurSec(sec))
+      } else {
+        Some(BondRecord::PeerSec(sec))
+      }
+    }
+    BLE_STORE_OBJ_TYPE_CCCD => {
+      let value = &value.cccd;
+      Some(BondRecord::Cccd(CccdRecord {
+        peer_addr: BLEAddress::from(value.peer_addr),
+        chr_val_handle: value.chr_val_handle,
+        flags: value.flags,
+        value_changed: value.value_changed() != 0,
+      }))
+    }
+    _ => None,
+  }
+}
+
+fn record_to_raw(record: &BondRecord) -> ble_store_value {
+  match record {
+    BondRecord::OurSec(x) | BondRecord::PeerSec(x) => {
+      let mut sec = ble_store_value_sec {
+        peer_addr: x.peer_addr.value,
+        key_size: x.key_size,
+        ediv: x.ediv,
+        rand_num: x.rand_num,
+        ltk: x.ltk.unwrap_or_default(),
+        irk: x.irk.unwrap_or_default(),
+        csrk: x.csrk.unwrap_or_default(),
+        ..Default::default()
+      };
+      sec.set_ltk_present(x.ltk.is_some() as _);
+      sec.set_irk_present(x.irk.is_some() as _);
+      sec.set_csrk_present(x.csrk.is_some() as _);
+      sec.set_authenticated(x.authenticated as _);
+      sec.set_sc(x.sc as _);
+      ble_store_value { sec }
+    }
+    BondRecord::Cccd(x) => {
+      let mut cccd = ble_store_value_cccd {
+        peer_addr: x.peer_addr.value,
+        chr_val_handle: x.chr_val_handle,
+        flags: x.flags,
+        ..Default::default()
+      };
+      cccd.set_value_changed(x.value_changed as _);
+      ble_store_value { cccd }
+    }
+  }
+}
@@ -0,0 +1,73 @@
+use core::ffi::CStr;
+use esp_idf_sys::*;
+
+use super::{
+  decode_bond_records, encode_bond_records, BondKey, BondRecord, BondStore, MemoryBondStore,
+};
+use crate::{
+  utilities::{nvs_load, nvs_open_namespace, nvs_store},
+  BLEError,
+};
+
+const KEY: &CStr = c"bonds";
+
+/// Bond store persisting the records in a namespace of the default NVS partition.
+///
+/// The records are cached in RAM and the namespace is rewritten on every change.
+pub struct NvsBondStore {
+  handle: nvs_handle_t,
+  cache: MemoryBondStore,
+}
+
+impl NvsBondStore {
+  /// Open the store in `namespace` (up to 15 characters), loading the stored records.
+  ///
+  /// Records that cannot be decoded are discarded.
+  pub fn new(namespace: &str) -> Result<Self, BLEError> {
+    let mut ret = Self {
+      handle: nvs_open_namespace(namespace)?,
+      cache: MemoryBondStore::new(),
+    };
+    match nvs_load(ret.handle, KEY)?
+      .as_deref()
+      .map(decode_bond_records)
+    {
+      Some(Some(records)) => ret.cache.records = records,
+      Some(None) => ::log::warn!("discarding undecodable bonds"),
+      None => {}
+    }
+    Ok(ret)
+  }
+
+  fn persist(&mut self) -> Result<(), BLEError> {
+    nvs_store(self.handle, KEY, &encode_bond_records(self.cache.records()))
+  }
+}
+
+impl Drop for NvsBondStore {
+  fn drop(&mut self) {
+    unsafe { nvs_close(self.handle) };
+  }
+}
+
+impl BondStore for NvsBondStore {
+  fn read(&mut self, key: &BondKey, skip: usize) -> Result<Option<BondRecord>, BLEError> {
+    self.cache.read(key, skip)
+  }
+
+  fn write(&mut self, record: &BondRecord) -> Result<(), BLEError> {
+    if self.cache.read(&record.key(), 0)?.as_ref() == Some(record) {
+      return Ok(());
+    }
+    self.cache.write(record)?;
+    self.persist()
+  }
+
+  fn delete(&mut self, key: &BondKey) -> Result<bool, BLEError> {
+    let deleted = self.cache.delete(key)?;
+    if deleted {
+      self.persist()?;
+    }
+    Ok(deleted)
+  }
+}
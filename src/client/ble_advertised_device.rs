@@ -1,9 +1,12 @@
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use bstr::{BStr, BString};
+use core::time::Duration;
 
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+use crate::enums::{AdvDataStatus, Phy};
 use crate::enums::{AdvFlag, AdvType};
-use crate::utilities::BleUuid;
+use crate::utilities::{AdStructure, BleUuid};
 use crate::BLEAddress;
 
 #[derive(Debug, Clone)]
@@ -31,23 +34,68 @@ pub struct BLEAdvertisedDevice {
   rssi: i32,
   service_uuids: Vec<BleUuid>,
   service_data_list: Vec<BLEServiceData>,
-  tx_power: Option<u8>,
+  tx_power: Option<i8>,
   manufacture_data: Option<Vec<u8>>,
+  solicitation_uuids: Vec<BleUuid>,
+  target_addresses: Vec<BLEAddress>,
+  adv_interval: Option<u32>,
+  peripheral_conn_interval_range: Option<(u16, u16)>,
+  uri: Option<Vec<u8>>,
+  le_role: Option<u8>,
+  last_seen: Duration,
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  ext: ExtAdvReport,
+}
+
+/// Fields of an extended advertisement report.
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExtAdvReport {
+  pub(crate) legacy: bool,
+  pub(crate) sid: u8,
+  pub(crate) primary_phy: u8,
+  pub(crate) secondary_phy: u8,
+  pub(crate) periodic_adv_itvl: u16,
+  pub(crate) tx_power: i8,
+  pub(crate) data_status: AdvDataStatus,
+}
+
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+impl ExtAdvReport {
+  /// Fields of a legacy advertisement report.
+  pub(crate) const LEGACY: Self = Self {
+    legacy: true,
+    sid: 0xFF,
+    primary_phy: esp_idf_sys::BLE_HCI_LE_PHY_1M as _,
+    secondary_phy: 0,
+    periodic_adv_itvl: 0,
+    tx_power: 127,
+    data_status: AdvDataStatus::Complete,
+  };
 }
 
 impl BLEAdvertisedDevice {
-  pub(crate) fn new(param: &esp_idf_sys::ble_gap_disc_desc) -> Self {
+  pub(crate) fn new(addr: esp_idf_sys::ble_addr_t, event_type: u8, rssi: i8) -> Self {
     Self {
-      addr: param.addr.into(),
-      adv_type: AdvType::try_from(param.event_type).unwrap(),
+      addr: addr.into(),
+      adv_type: AdvType::try_from(event_type).unwrap(),
       adv_flags: None,
       appearance: None,
       name: BString::default(),
-      rssi: param.rssi as _,
+      rssi: rssi as _,
       service_uuids: Vec::new(),
       service_data_list: Vec::new(),
       tx_power: None,
       manufacture_data: None,
+      solicitation_uuids: Vec::new(),
+      target_addresses: Vec::new(),
+      adv_interval: None,
+      peripheral_conn_interval_range: None,
+      uri: None,
+      le_role: None,
+      last_seen: crate::utilities::now(),
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      ext: ExtAdvReport::LEGACY,
     }
   }
 
@@ -74,6 +122,68 @@ impl BLEAdvertisedDevice {
     self.rssi
   }
 
+  /// Get the time since boot at which the device was last seen.
+  pub fn last_seen(&self) -> Duration {
+    self.last_seen
+  }
+
+  pub(crate) fn seen(&mut self, rssi: i8) {
+    self.rssi = rssi as _;
+    self.last_seen = crate::utilities::now();
+  }
+
+  #[cfg(test)]
+  pub(crate) fn set_last_seen(&mut self, last_seen: Duration) {
+    self.last_seen = last_seen;
+  }
+
+  /// Returns whether the device was found with a legacy advertisement.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn is_legacy_advertisement(&self) -> bool {
+    self.ext.legacy
+  }
+
+  /// Get the advertising set ID, `None` for legacy advertisements.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn sid(&self) -> Option<u8> {
+    (self.ext.sid <= 0x0F).then_some(self.ext.sid)
+  }
+
+  /// Get the PHY of the primary advertising channels.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn primary_phy(&self) -> Phy {
+    Phy::try_from(self.ext.primary_phy).unwrap_or(Phy::Le1M)
+  }
+
+  /// Get the PHY of the secondary advertising channels, `None` if no secondary channel is used.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn secondary_phy(&self) -> Option<Phy> {
+    Phy::try_from(self.ext.secondary_phy).ok()
+  }
+
+  /// Get the periodic advertising interval (in 1.25ms units), `None` if there is no periodic advertising.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn periodic_adv_interval(&self) -> Option<u16> {
+    (self.ext.periodic_adv_itvl != 0).then_some(self.ext.periodic_adv_itvl)
+  }
+
+  /// Get the transmission power reported by the controller (dBm).
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn adv_tx_power(&self) -> Option<i8> {
+    (self.ext.tx_power != 127).then_some(self.ext.tx_power)
+  }
+
+  /// Get whether the advertising data is complete or was truncated.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn data_status(&self) -> AdvDataStatus {
+    self.ext.data_status
+  }
+
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub(crate) fn set_ext_report(&mut self, ext: ExtAdvReport) {
+    self.ext = ext;
+  }
+
   pub fn get_service_uuids(&self) -> core::slice::Iter<'_, BleUuid> {
     self.service_uuids.iter()
   }
@@ -94,116 +204,132 @@ impl BLEAdvertisedDevice {
     self.manufacture_data.as_deref()
   }
 
-  pub(crate) fn parse_advertisement(&mut self, payload: &[u8]) {
-    let mut payload = payload;
-
-    loop {
-      let Some(length) = payload.first() else {
-        return;
-      };
-      let length = *length as usize;
-
-      if length != 0 {
-        let Some(type_) = payload.get(1) else { return };
-        let type_ = *type_ as u32;
-
-        let Some(data) = payload.get(2..(length + 1)) else {
-          return;
-        };
-
-        match type_ {
-          esp_idf_sys::BLE_HS_ADV_TYPE_FLAGS => {
-            let Some(ad_flag) = data.first() else { return };
-            self.adv_flags = AdvFlag::from_bits(*ad_flag);
-          }
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_NAME | esp_idf_sys::BLE_HS_ADV_TYPE_COMP_NAME => {
-            self.name = BString::new(data.to_vec());
-          }
-          esp_idf_sys::BLE_HS_ADV_TYPE_TX_PWR_LVL => {
-            let Some(tx_power) = data.first() else { return };
-            self.tx_power = Some(*tx_power);
-          }
+  /// Get the advertised appearance.
+  pub fn appearance(&self) -> Option<u16> {
+    self.appearance
+  }
 
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS16
-          | esp_idf_sys::BLE_HS_ADV_TYPE_COMP_UUIDS16 => {
-            let mut data = data;
-            while data.len() >= 2 {
-              let (uuid, data_) = data.split_at(2);
-              self.push_service_uuid(BleUuid::from_uuid16(u16::from_le_bytes(
-                uuid.try_into().unwrap(),
-              )));
-              data = data_;
-            }
-          }
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS32
-          | esp_idf_sys::BLE_HS_ADV_TYPE_COMP_UUIDS32 => {
-            let mut data = data;
-            while data.len() >= 4 {
-              let (uuid, data_) = data.split_at(4);
-              self.push_service_uuid(BleUuid::from_uuid32(u32::from_le_bytes(
-                uuid.try_into().unwrap(),
-              )));
-              data = data_;
-            }
-          }
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS128
-          | esp_idf_sys::BLE_HS_ADV_TYPE_COMP_UUIDS128 => {
-            if let Ok(data) = data.try_into() {
-              self.push_service_uuid(BleUuid::Uuid128(data));
-            }
-          }
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID16 => {
-            if length < 2 {
-              ::log::error!("Length too small for BLE_HS_ADV_TYPE_SVC_DATA_UUID16");
-            } else {
-              let (uuid, service_data) = data.split_at(2);
-              let uuid = BleUuid::from_uuid16(u16::from_le_bytes(uuid.try_into().unwrap()));
-              self.push_service_data(uuid, service_data);
-            }
+  /// Get the advertised transmission power level (dBm).
+  pub fn tx_power(&self) -> Option<i8> {
+    self.tx_power
+  }
+
+  /// Get the service solicitation UUIDs.
+  pub fn get_solicitation_uuids(&self) -> core::slice::Iter<'_, BleUuid> {
+    self.solicitation_uuids.iter()
+  }
+
+  /// Get the public and random target addresses.
+  pub fn get_target_addresses(&self) -> core::slice::Iter<'_, BLEAddress> {
+    self.target_addresses.iter()
+  }
+
+  /// Get the advertised advertising interval (in 0.625ms units).
+  pub fn adv_interval(&self) -> Option<u32> {
+    self.adv_interval
+  }
+
+  /// Get the advertised peripheral connection interval range (min, max) in 1.25ms units.
+  pub fn peripheral_conn_interval_range(&self) -> Option<(u16, u16)> {
+    self.peripheral_conn_interval_range
+  }
+
+  /// Get the advertised URI.
+  pub fn uri(&self) -> Option<&[u8]> {
+    self.uri.as_deref()
+  }
+
+  /// Get the advertised LE role.
+  pub fn le_role(&self) -> Option<u8> {
+    self.le_role
+  }
+
+  pub(crate) fn parse_advertisement(&mut self, payload: &[u8]) {
+    for ad in AdStructure::parse(payload) {
+      match ad {
+        AdStructure::Flags(flags) => {
+          self.adv_flags = Some(flags);
+        }
+        AdStructure::ShortenedLocalName(name) | AdStructure::CompleteLocalName(name) => {
+          self.name = name;
+        }
+        AdStructure::TxPowerLevel(tx_power) => {
+          self.tx_power = Some(tx_power);
+        }
+        AdStructure::ServiceUuids16 { uuids, .. } => {
+          for uuid in uuids {
+            self.push_service_uuid(BleUuid::from_uuid16(uuid));
           }
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID32 => {
-            if length < 4 {
-              ::log::error!("Length too small for BLE_HS_ADV_TYPE_SVC_DATA_UUID32");
-            } else {
-              let (uuid, service_data) = data.split_at(4);
-              let uuid = BleUuid::from_uuid32(u32::from_le_bytes(uuid.try_into().unwrap()));
-              self.push_service_data(uuid, service_data);
-            }
+        }
+        AdStructure::ServiceUuids32 { uuids, .. } => {
+          for uuid in uuids {
+            self.push_service_uuid(BleUuid::from_uuid32(uuid));
           }
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID128 => {
-            if length < 16 {
-              ::log::error!("Length too small for BLE_HS_ADV_TYPE_SVC_DATA_UUID128");
-            } else {
-              let (uuid, service_data) = data.split_at(16);
-              let uuid = BleUuid::from_uuid128(uuid.try_into().unwrap());
-              self.push_service_data(uuid, service_data);
-            }
+        }
+        AdStructure::ServiceUuids128 { uuids, .. } => {
+          for uuid in uuids {
+            self.push_service_uuid(BleUuid::from_uuid128(uuid));
           }
-          esp_idf_sys::BLE_HS_ADV_TYPE_APPEARANCE => {
-            if let Ok(appearance) = data.try_into() {
-              self.appearance = Some(u16::from_le_bytes(appearance));
-            }
+        }
+        AdStructure::ServiceSolicitationUuids16(uuids) => {
+          for uuid in uuids {
+            push_unique(&mut self.solicitation_uuids, BleUuid::from_uuid16(uuid));
           }
-          esp_idf_sys::BLE_HS_ADV_TYPE_MFG_DATA => {
-            self.manufacture_data = Some(data.to_vec());
+        }
+        AdStructure::ServiceSolicitationUuids32(uuids) => {
+          for uuid in uuids {
+            push_unique(&mut self.solicitation_uuids, BleUuid::from_uuid32(uuid));
           }
-          esp_idf_sys::BLE_HS_ADV_TYPE_SLAVE_ITVL_RANGE => {
-            // DO NOTHING
+        }
+        AdStructure::ServiceSolicitationUuids128(uuids) => {
+          for uuid in uuids {
+            push_unique(&mut self.solicitation_uuids, BleUuid::from_uuid128(uuid));
           }
-          _ => {
-            ::log::info!("Unhandled type: adType: 0x{:X}", type_);
+        }
+        AdStructure::ServiceData16 { uuid, data } => {
+          self.push_service_data(BleUuid::from_uuid16(uuid), &data);
+        }
+        AdStructure::ServiceData32 { uuid, data } => {
+          self.push_service_data(BleUuid::from_uuid32(uuid), &data);
+        }
+        AdStructure::ServiceData128 { uuid, data } => {
+          self.push_service_data(BleUuid::from_uuid128(uuid), &data);
+        }
+        AdStructure::Appearance(appearance) => {
+          self.appearance = Some(appearance);
+        }
+        AdStructure::ManufacturerSpecificData(data) => {
+          self.manufacture_data = Some(data);
+        }
+        AdStructure::PeripheralConnectionIntervalRange { min, max } => {
+          self.peripheral_conn_interval_range = Some((min, max));
+        }
+        AdStructure::PublicTargetAddress(addrs) | AdStructure::RandomTargetAddress(addrs) => {
+          for addr in addrs {
+            push_unique(&mut self.target_addresses, addr);
           }
         }
+        AdStructure::AdvertisingInterval(itvl) => {
+          self.adv_interval = Some(itvl as _);
+        }
+        AdStructure::AdvertisingIntervalLong(itvl) => {
+          self.adv_interval = Some(itvl);
+        }
+        AdStructure::Uri(uri) => {
+          self.uri = Some(uri);
+        }
+        AdStructure::LeRole(role) => {
+          self.le_role = Some(role);
+        }
+        ad => {
+          ::log::info!("Unhandled type: adType: 0x{:X}", ad.ad_type());
+        }
       }
-      payload = &payload[(1 + length)..];
     }
   }
 
   fn push_service_uuid(&mut self, uuid: BleUuid) {
-    let is_present = self.service_uuids.iter().any(|x| x == &uuid);
-    if !is_present {
-      self.service_uuids.push(uuid);
-    }
+    push_unique(&mut self.service_uuids, uuid);
   }
 
   fn push_service_data(&mut self, uuid: BleUuid, data: &[u8]) {
@@ -219,3 +345,10 @@ impl BLEAdvertisedDevice {
     }
   }
 }
+
+fn push_unique<T: PartialEq>(list: &mut Vec<T>, value: T) {
+  let is_present = list.iter().any(|x| x == &value);
+  if !is_present {
+    list.push(value);
+  }
+}
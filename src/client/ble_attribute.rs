@@ -1,3 +1,5 @@
+use core::time::Duration;
+
 use crate::BLEClient;
 
 pub(crate) trait BLEAttribute {
@@ -9,4 +11,9 @@ pub(crate) trait BLEAttribute {
       None => esp_idf_sys::BLE_HS_CONN_HANDLE_NONE as _,
     }
   }
+
+  /// Default timeout of the operations on the attribute, inherited from the client.
+  fn timeout(&self) -> Option<Duration> {
+    self.get_client().and_then(|x| x.timeout())
+  }
 }
@@ -2,11 +2,14 @@ use crate::{
   ble,
   ble_device::OWN_ADDR_TYPE,
   ble_error::return_code_to_string,
-  utilities::{voidp_to_ref, ArcUnsafeCell, BleUuid},
-  BLEAddress, BLEDevice, BLEError, BLERemoteService, Signal,
+  enums::SecurityRetry,
+  utilities::{
+    with_conn_timeout, with_timeout, ArcUnsafeCell, BleUuid, Completion, WeakUnsafeCell,
+  },
+  AttError, BLEAddress, BLEDevice, BLEError, BLEErrorKind, BLERemoteService, Signal,
 };
 use alloc::{boxed::Box, string::ToString, vec::Vec};
-use core::{cell::UnsafeCell, ffi::c_void};
+use core::{cell::UnsafeCell, ffi::c_void, future::Future, mem::ManuallyDrop, time::Duration};
 use esp_idf_sys::*;
 
 #[allow(clippy::type_complexity)]
@@ -16,6 +19,8 @@ pub(crate) struct BLEClientState {
   services: Option<Vec<BLERemoteService>>,
   signal: Signal<u32>,
   connect_timeout_ms: u32,
+  timeout: Option<Duration>,
+  security_retry: SecurityRetry,
   ble_gap_conn_params: ble_gap_conn_params,
   on_passkey_request: Option<Box<dyn Fn() -> u32 + Send + Sync>>,
   on_confirm_pin: Option<Box<dyn Fn(u32) -> bool + Send + Sync>>,
@@ -23,6 +28,9 @@ pub(crate) struct BLEClientState {
   on_disconnect: Option<Box<dyn Fn(i32) + Send + Sync>>,
 }
 
+/// Client whose services are discovered, and the discovered services.
+type ServiceDisc = (WeakUnsafeCell<BLEClientState>, Vec<BLERemoteService>);
+
 pub struct BLEClient {
   state: ArcUnsafeCell<BLEClientState>,
 }
@@ -35,6 +43,8 @@ impl BLEClient {
         conn_handle: esp_idf_sys::BLE_HS_CONN_HANDLE_NONE as _,
         services: None,
         connect_timeout_ms: 30000,
+        timeout: None,
+        security_retry: SecurityRetry::Disabled,
         ble_gap_conn_params: ble_gap_conn_params {
           scan_itvl: 16,
           scan_window: 16,
@@ -62,6 +72,77 @@ impl BLEClient {
     self.state.conn_handle
   }
 
+  /// Set the default timeout of the asynchronous operations of the client and its attributes.
+  ///
+  /// Operations without an explicit timeout wait forever if this is `None` (the default).
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+    self.state.timeout = timeout;
+    self
+  }
+
+  pub fn timeout(&self) -> Option<Duration> {
+    self.state.timeout
+  }
+
+  /// Set how reads and writes of remote attributes recover from an insufficient
+  /// authentication or encryption error. Disabled by default.
+  pub fn set_security_retry(&mut self, policy: SecurityRetry) -> &mut Self {
+    self.state.security_retry = policy;
+    self
+  }
+
+  /// Run an attribute operation of the client, securing the connection and running it again
+  /// if it failed for lack of security and the security retry policy allows it.
+  ///
+  /// A failure to secure the connection is returned in place of the original error.
+  pub(crate) async fn retry_secured<T, F: Future<Output = Result<T, BLEError>>>(
+    client: Option<BLEClient>,
+    mut op: impl FnMut() -> F,
+  ) -> Result<T, BLEError> {
+    let err = match op().await {
+      Err(err) => err,
+      ret => return ret,
+    };
+
+    match client {
+      Some(mut client) if client.should_secure(err) => {
+        ::log::info!("{:?}, securing the connection", err);
+        client.secure_connection().await?;
+        op().await
+      }
+      _ => Err(err),
+    }
+  }
+
+  fn should_secure(&self, err: BLEError) -> bool {
+    let BLEErrorKind::Att(reason) = err.kind() else {
+      return false;
+    };
+    if !matches!(
+      reason,
+      AttError::InsufficientAuthentication | AttError::InsufficientEncryption
+    ) {
+      return false;
+    }
+    let Ok(desc) = crate::utilities::ble_gap_conn_find(self.conn_handle()) else {
+      return false;
+    };
+
+    match self.state.security_retry {
+      SecurityRetry::Disabled => false,
+      SecurityRetry::Encrypt => {
+        !desc.encrypted()
+          && BLEDevice::take()
+            .bonded_addresses()
+            .is_ok_and(|x| x.contains(&desc.id_address()))
+      }
+      SecurityRetry::Pair => {
+        !desc.encrypted()
+          || (reason == AttError::InsufficientAuthentication && !desc.authenticated())
+      }
+    }
+  }
+
   pub fn on_passkey_request(
     &mut self,
     callback: impl Fn() -> u32 + Send + Sync + 'static,
@@ -89,23 +170,48 @@ impl BLEClient {
   }
 
   pub async fn connect(&mut self, addr: &BLEAddress) -> Result<(), BLEError> {
+    self.connect_with_timeout(addr, self.state.timeout).await
+  }
+
+  /// Connect to the peer, cancelling the connection if it is not established within `timeout`.
+  pub async fn connect_with_timeout(
+    &mut self,
+    addr: &BLEAddress,
+    timeout: Option<Duration>,
+  ) -> Result<(), BLEError> {
     unsafe {
       if esp_idf_sys::ble_gap_conn_find_by_addr(&addr.value, core::ptr::null_mut()) == 0 {
         ::log::warn!("A connection to {:?} already exists", addr);
         return BLEError::fail();
       }
 
-      ble!(esp_idf_sys::ble_gap_connect(
+      // The connection callback owns a reference to the state until the connection ends.
+      self.state.signal.reset();
+      let arg = ArcUnsafeCell::into_raw(self.state.clone());
+      let rc = esp_idf_sys::ble_gap_connect(
         OWN_ADDR_TYPE as _,
         &addr.value,
         self.state.connect_timeout_ms as _,
         &self.state.ble_gap_conn_params,
         Some(Self::handle_gap_event),
-        self as *mut Self as _,
-      ))?;
+        arg,
+      );
+      if rc != 0 {
+        drop(ArcUnsafeCell::<BLEClientState>::from_raw(arg));
+      }
+      ble!(rc)?;
     }
 
-    ble!(self.state.signal.wait().await)?;
+    match with_timeout(timeout, self.state.signal.wait()).await {
+      Ok(rc) => ble!(rc)?,
+      Err(err) => {
+        // Either the connection is pending, or it is established but not yet ready.
+        if unsafe { esp_idf_sys::ble_gap_conn_cancel() } != 0 {
+          let _ = self.disconnect();
+        }
+        return Err(err);
+      }
+    }
     self.state.address = Some(*addr);
 
     let client = UnsafeCell::new(self);
@@ -119,12 +225,24 @@ impl BLEClient {
   }
 
   pub async fn secure_connection(&mut self) -> Result<(), BLEError> {
+    self
+      .secure_connection_with_timeout(self.state.timeout)
+      .await
+  }
+
+  /// Secure the connection, disconnecting if pairing does not complete within `timeout`.
+  pub async fn secure_connection_with_timeout(
+    &mut self,
+    timeout: Option<Duration>,
+  ) -> Result<(), BLEError> {
+    self.state.signal.reset();
     unsafe {
       ble!(esp_idf_sys::ble_gap_security_initiate(
         self.state.conn_handle
       ))?;
     }
-    ble!(self.state.signal.wait().await)?;
+    let conn_handle = self.state.conn_handle;
+    ble!(with_conn_timeout(conn_handle, timeout, self.state.signal.wait()).await?)?;
 
     ::log::info!("secure_connection: success");
 
@@ -223,19 +341,51 @@ impl BLEClient {
     Ok(rssi)
   }
 
+  /// Transfer a periodic sync to the peer.
+  ///
+  /// * `service_data`: value identifying the periodic advertising train to the peer.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn transfer_periodic_sync(
+    &self,
+    sync: &crate::PeriodicSync,
+    service_data: u16,
+  ) -> Result<(), BLEError> {
+    sync.transfer(self.conn_handle(), service_data)
+  }
+
+  /// Accept a periodic sync transferred by the peer.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn receive_periodic_sync(
+    &self,
+    params: crate::PeriodicSyncParams,
+  ) -> Result<crate::PeriodicSync, BLEError> {
+    crate::PeriodicSync::receive(self.conn_handle(), params)
+  }
+
   pub async fn get_services(
     &mut self,
+  ) -> Result<core::slice::IterMut<'_, BLERemoteService>, BLEError> {
+    self.get_services_with_timeout(self.state.timeout).await
+  }
+
+  /// Discover the services of the peer, disconnecting if discovery does not complete within
+  /// `timeout`.
+  pub async fn get_services_with_timeout(
+    &mut self,
+    timeout: Option<Duration>,
   ) -> Result<core::slice::IterMut<'_, BLERemoteService>, BLEError> {
     if self.state.services.is_none() {
-      self.state.services = Some(Vec::new());
-      unsafe {
+      let completion = Completion::new((ArcUnsafeCell::downgrade(&self.state), Vec::new()));
+      completion.start(|arg| unsafe {
         esp_idf_sys::ble_gattc_disc_all_svcs(
           self.state.conn_handle,
           Some(Self::service_discovered_cb),
-          self as *mut Self as _,
-        );
-      }
-      ble!(self.state.signal.wait().await)?;
+          arg,
+        )
+      })?;
+
+      ble!(with_conn_timeout(self.state.conn_handle, timeout, completion.wait()).await?)?;
+      self.state.services = Some(core::mem::take(&mut completion.data().1));
     }
 
     Ok(self.state.services.as_mut().unwrap().iter_mut())
@@ -250,7 +400,7 @@ impl BLEClient {
 
   extern "C" fn handle_gap_event(event: *mut esp_idf_sys::ble_gap_event, arg: *mut c_void) -> i32 {
     let event = unsafe { &*event };
-    let client = unsafe { voidp_to_ref::<Self>(arg) };
+    let mut client = ManuallyDrop::new(Self::from_state(unsafe { ArcUnsafeCell::from_raw(arg) }));
 
     match event.type_ as _ {
       BLE_GAP_EVENT_CONNECT => {
@@ -269,15 +419,25 @@ impl BLEClient {
           ::log::info!("connect_status {}", connect.status);
           client.state.conn_handle = esp_idf_sys::BLE_HS_CONN_HANDLE_NONE as _;
           client.state.signal.signal(connect.status as _);
+          // The connection attempt ended, release the callback argument.
+          unsafe { ManuallyDrop::drop(&mut client) };
         }
       }
       BLE_GAP_EVENT_DISCONNECT => {
+        // This is the last event of the connection, release the callback argument.
+        let mut client = unsafe { ManuallyDrop::take(&mut client) };
         let disconnect = unsafe { &event.__bindgen_anon_1.disconnect };
         if client.state.conn_handle != disconnect.conn.conn_handle {
           return 0;
         }
         client.state.conn_handle = esp_idf_sys::BLE_HS_CONN_HANDLE_NONE as _;
 
+        for service in client.state.services.iter_mut().flatten() {
+          for characteristic in service.state.characteristics.iter_mut().flatten() {
+            characteristic.close_notifications();
+          }
+        }
+
         ::log::info!(
           "Disconnected: {}",
           return_code_to_string(disconnect.reason as _)
@@ -299,10 +459,27 @@ impl BLEClient {
         {
           let desc = crate::utilities::ble_gap_conn_find(enc_change.conn_handle).unwrap();
           unsafe { esp_idf_sys::ble_store_util_delete_peer(&desc.0.peer_id_addr) };
+        } else if enc_change.status == 0 {
+          if let Ok(desc) = crate::utilities::ble_gap_conn_find(enc_change.conn_handle) {
+            crate::bond_store::on_bond_encrypted(&desc);
+            // Just Works pairing has no passkey action to refuse it earlier; no keys were
+            // distributed, since bonding is disabled while the pairing window is closed.
+            if !desc.bonded() && crate::pairing_window::refuse_pairing(enc_change.conn_handle) {
+              client.state.signal.signal(BLE_HS_EAUTHEN as _);
+              return 0;
+            }
+          }
         }
 
         client.state.signal.signal(enc_change.status as _);
       }
+      BLE_GAP_EVENT_REPEAT_PAIRING => {
+        let repeat_pairing = unsafe { &event.__bindgen_anon_1.repeat_pairing };
+        if client.state.conn_handle != repeat_pairing.conn_handle {
+          return BLE_GAP_REPEAT_PAIRING_IGNORE as _;
+        }
+        return crate::pairing_window::on_repeat_pairing(repeat_pairing.conn_handle);
+      }
       BLE_GAP_EVENT_MTU => {
         let mtu = unsafe { &event.__bindgen_anon_1.mtu };
         if client.state.conn_handle != mtu.conn_handle {
@@ -361,41 +538,13 @@ impl BLEClient {
         if client.state.conn_handle != passkey.conn_handle {
           return 0;
         }
-        let mut pkey = esp_idf_sys::ble_sm_io {
-          action: passkey.params.action,
-          ..Default::default()
-        };
-        match passkey.params.action as _ {
-          esp_idf_sys::BLE_SM_IOACT_DISP => {
-            pkey.__bindgen_anon_1.passkey = BLEDevice::take().security().get_passkey();
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_DISP; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_NUMCMP => {
-            if let Some(callback) = &client.state.on_confirm_pin {
-              pkey.__bindgen_anon_1.numcmp_accept = callback(passkey.params.numcmp) as _;
-            } else {
-              ::log::warn!("on_passkey_request is not setted");
-            }
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_NUMCMP; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_INPUT => {
-            if let Some(callback) = &client.state.on_passkey_request {
-              pkey.__bindgen_anon_1.passkey = callback();
-            } else {
-              ::log::warn!("on_passkey_request is not setted");
-            }
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_INPUT; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_NONE => {
-            ::log::debug!("BLE_SM_IOACT_NONE; No passkey action required");
-          }
-          action => {
-            todo!("implementation required: {}", action);
-          }
-        }
+        crate::pairing_agent::on_passkey_action(
+          passkey.conn_handle,
+          &passkey.params,
+          None,
+          client.state.on_passkey_request.as_deref(),
+          client.state.on_confirm_pin.as_deref(),
+        );
       }
       _ => {
         ::log::warn!("unhandled event: {}", event.type_);
@@ -405,23 +554,18 @@ impl BLEClient {
   }
 
   extern "C" fn service_discovered_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     service: *const esp_idf_sys::ble_gatt_svc,
     arg: *mut c_void,
   ) -> i32 {
-    let client = unsafe { voidp_to_ref::<Self>(arg) };
-    if client.state.conn_handle != conn_handle {
-      return 0;
-    }
-
     let error = unsafe { &*error };
-    let service = unsafe { &*service };
 
     if error.status == 0 {
       // Found a service - add it to the vector
-      let service = BLERemoteService::new(ArcUnsafeCell::downgrade(&client.state), service);
-      client.state.services.as_mut().unwrap().push(service);
+      let mut data = unsafe { Completion::<ServiceDisc>::data_of(arg) };
+      let service = BLERemoteService::new(data.0.clone(), unsafe { &*service });
+      data.1.push(service);
       return 0;
     }
 
@@ -431,7 +575,7 @@ impl BLEClient {
       error.status as _
     };
 
-    client.state.signal.signal(ret);
+    unsafe { Completion::<ServiceDisc>::complete(arg, ret) };
     ret as _
   }
 }
@@ -1,12 +1,15 @@
 use alloc::vec::Vec;
-use core::ffi::c_void;
+use core::{ffi::c_void, time::Duration};
 
-use crate::{ble, utilities::voidp_to_ref, BLEError, Signal};
+use crate::{
+  ble,
+  utilities::{with_conn_timeout, Completion},
+  BLEError,
+};
 
 pub struct BLEReader {
   conn_handle: u16,
   handle: u16,
-  signal: Signal<u32>,
 }
 
 impl BLEReader {
@@ -14,51 +17,45 @@ impl BLEReader {
     Self {
       conn_handle,
       handle,
-      signal: Signal::new(),
     }
   }
 
-  pub async fn read_value(&mut self) -> Result<Vec<u8>, BLEError> {
-    let data = Vec::<u8>::new();
-    let mut arg = (self, data);
+  pub async fn read_value(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, BLEError> {
+    let completion = Completion::new(Vec::<u8>::new());
 
-    unsafe {
-      ble!(esp_idf_sys::ble_gattc_read_long(
-        arg.0.conn_handle,
-        arg.0.handle,
+    completion.start(|arg| unsafe {
+      esp_idf_sys::ble_gattc_read_long(
+        self.conn_handle,
+        self.handle,
         0,
         Some(Self::on_read_cb),
-        core::ptr::addr_of_mut!(arg) as _,
-      ))?;
-    }
+        arg,
+      )
+    })?;
 
-    ble!(arg.0.signal.wait().await)?;
-    Ok(arg.1)
+    ble!(with_conn_timeout(self.conn_handle, timeout, completion.wait()).await?)?;
+    let data = core::mem::take(&mut *completion.data());
+    Ok(data)
   }
 
   extern "C" fn on_read_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     attr: *mut esp_idf_sys::ble_gatt_attr,
     arg: *mut c_void,
   ) -> i32 {
-    let (reader, data) = unsafe { voidp_to_ref::<(&mut Self, Vec<u8>)>(arg) };
-    if conn_handle != reader.conn_handle {
-      return 0;
-    }
-
     let error = unsafe { &*error };
 
     if error.status == 0 {
       if let Some(attr) = unsafe { attr.as_ref() } {
         let om_data =
           unsafe { core::slice::from_raw_parts((*attr.om).om_data, (*attr.om).om_len as _) };
-        data.extend_from_slice(om_data);
+        unsafe { Completion::<Vec<u8>>::data_of(arg) }.extend_from_slice(om_data);
         return 0;
       }
     }
 
-    reader.signal.signal(error.status as _);
+    unsafe { Completion::<Vec<u8>>::complete(arg, error.status as _) };
     error.status as _
   }
 }
@@ -1,14 +1,17 @@
-use core::borrow::Borrow;
+use core::{borrow::Borrow, time::Duration};
 
 use super::ble_remote_service::BLERemoteServiceState;
 use super::{BLEReader, BLEWriter};
 use crate::{
   ble,
-  utilities::{voidp_to_ref, ArcUnsafeCell, BleUuid, WeakUnsafeCell},
-  BLEError, BLERemoteDescriptor, Signal,
+  utilities::{
+    ble_gap_conn_find, with_conn_timeout, ArcUnsafeCell, BleUuid, Completion, OverflowPolicy,
+    Queue, WeakUnsafeCell,
+  },
+  BLEError, BLERemoteDescriptor,
 };
 use crate::{BLEAttribute, BLEClient};
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use bitflags::bitflags;
 use core::ffi::c_void;
 
@@ -35,8 +38,8 @@ pub struct BLERemoteCharacteristicState {
   end_handle: u16,
   properties: GattCharacteristicProperties,
   descriptors: Option<Vec<BLERemoteDescriptor>>,
-  signal: Signal<u32>,
   on_notify: Option<Box<dyn FnMut(&[u8]) + Send + Sync>>,
+  notifications: Option<Arc<Queue<Vec<u8>>>>,
 }
 
 impl BLEAttribute for BLERemoteCharacteristicState {
@@ -57,6 +60,106 @@ impl BLERemoteCharacteristicState {
   }
 }
 
+/// Parameters of [`BLERemoteCharacteristic::notifications`].
+#[derive(Copy, Clone, Debug)]
+pub struct NotificationStreamParams {
+  /// Subscribe to indications instead of notifications.
+  pub indications: bool,
+  /// Number of values buffered until the consumer catches up.
+  pub capacity: usize,
+  /// What to do with a new value when the buffer is full.
+  pub overflow: OverflowPolicy,
+}
+
+impl Default for NotificationStreamParams {
+  fn default() -> Self {
+    Self {
+      indications: false,
+      capacity: 16,
+      overflow: OverflowPolicy::DropOldest,
+    }
+  }
+}
+
+/// Stream of notified values returned by [`BLERemoteCharacteristic::notifications`].
+///
+/// The characteristic is unsubscribed when the stream is dropped.
+pub struct BLENotificationStream {
+  characteristic: BLERemoteCharacteristic,
+  cccd_handle: u16,
+  queue: Arc<Queue<Vec<u8>>>,
+}
+
+impl BLENotificationStream {
+  /// Wait for the next notified value.
+  ///
+  /// Returns `None` once the connection is closed and all buffered values were received.
+  pub async fn next(&mut self) -> Option<Vec<u8>> {
+    self.queue.pop().await
+  }
+}
+
+impl Drop for BLENotificationStream {
+  fn drop(&mut self) {
+    let state = &mut self.characteristic.state;
+    if state
+      .notifications
+      .as_ref()
+      .is_some_and(|x| Arc::ptr_eq(x, &self.queue))
+    {
+      state.notifications = None;
+    }
+
+    if self.queue.is_closed() {
+      return;
+    }
+
+    // Drop can not wait for the response, so the write is not awaited.
+    let value = 0u16.to_le_bytes();
+    let rc = unsafe {
+      esp_idf_sys::ble_gattc_write_flat(
+        state.conn_handle(),
+        self.cccd_handle,
+        value.as_ptr() as _,
+        value.len() as _,
+        None,
+        core::ptr::null_mut(),
+      )
+    };
+    if rc != 0 {
+      ::log::warn!("failed to unsubscribe: {:?}", BLEError::convert(rc as _));
+    }
+  }
+}
+
+/// Removes the queue of [`BLERemoteCharacteristic::notifications`] when dropped, unless the
+/// subscription completed, so that a failed or cancelled subscription does not keep buffering.
+struct RemoveQueueOnDrop<'a> {
+  characteristic: BLERemoteCharacteristic,
+  queue: &'a Arc<Queue<Vec<u8>>>,
+  subscribed: bool,
+}
+
+impl Drop for RemoveQueueOnDrop<'_> {
+  fn drop(&mut self) {
+    let state = &mut self.characteristic.state;
+    if !self.subscribed
+      && state
+        .notifications
+        .as_ref()
+        .is_some_and(|x| Arc::ptr_eq(x, self.queue))
+    {
+      state.notifications = None;
+    }
+  }
+}
+
+/// Characteristic whose descriptors are discovered, and the discovered descriptors.
+type DescriptorDisc = (
+  WeakUnsafeCell<BLERemoteCharacteristicState>,
+  Vec<BLERemoteDescriptor>,
+);
+
 #[derive(Clone)]
 pub struct BLERemoteCharacteristic {
   state: ArcUnsafeCell<BLERemoteCharacteristicState>,
@@ -75,8 +178,8 @@ impl BLERemoteCharacteristic {
         end_handle: 0,
         properties: GattCharacteristicProperties::from_bits_truncate(chr.properties),
         descriptors: None,
-        signal: Signal::new(),
         on_notify: None,
+        notifications: None,
       }),
     }
   }
@@ -97,34 +200,42 @@ impl BLERemoteCharacteristic {
     &mut self,
   ) -> Result<core::slice::IterMut<'_, BLERemoteDescriptor>, BLEError> {
     if self.state.descriptors.is_none() {
-      self.state.descriptors = Some(Vec::new());
-
+      let (conn_handle, timeout) = (self.state.conn_handle(), self.state.timeout());
       if self.state.end_handle == 0 {
-        unsafe {
-          ble!(esp_idf_sys::ble_gattc_disc_all_chrs(
+        // The characteristic ends before the next one, or at the end of the service.
+        let service_end_handle = self.state.service.upgrade().unwrap().end_handle;
+        let completion = Completion::new(service_end_handle);
+        completion.start(|arg| unsafe {
+          esp_idf_sys::ble_gattc_disc_all_chrs(
             self.state.conn_handle(),
             self.state.handle,
-            self.state.service.upgrade().unwrap().end_handle,
+            service_end_handle,
             Some(Self::next_char_cb),
-            self as *mut Self as _,
-          ))?;
-        }
+            arg,
+          )
+        })?;
 
-        ble!(self.state.signal.wait().await)?;
+        ble!(with_conn_timeout(conn_handle, timeout, completion.wait()).await?)?;
+        self.state.end_handle = *completion.data();
       }
 
+      let mut descriptors = Vec::new();
       if self.state.handle != self.state.end_handle {
-        unsafe {
-          ble!(esp_idf_sys::ble_gattc_disc_all_dscs(
+        let completion = Completion::new((ArcUnsafeCell::downgrade(&self.state), Vec::new()));
+        completion.start(|arg| unsafe {
+          esp_idf_sys::ble_gattc_disc_all_dscs(
             self.state.conn_handle(),
             self.state.handle,
             self.state.end_handle,
             Some(Self::descriptor_disc_cb),
-            self as *mut Self as _,
-          ))?;
-        }
-        ble!(self.state.signal.wait().await)?;
+            arg,
+          )
+        })?;
+
+        ble!(with_conn_timeout(conn_handle, timeout, completion.wait()).await?)?;
+        descriptors = core::mem::take(&mut completion.data().1);
       }
+      self.state.descriptors = Some(descriptors);
     }
 
     Ok(self.state.descriptors.as_mut().unwrap().iter_mut())
@@ -141,66 +252,105 @@ impl BLERemoteCharacteristic {
   }
 
   extern "C" fn next_char_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     chr: *const esp_idf_sys::ble_gatt_chr,
     arg: *mut c_void,
   ) -> i32 {
-    let characteristic = unsafe { voidp_to_ref::<Self>(arg) };
-    if characteristic.state.conn_handle() != conn_handle {
-      return 0;
-    }
-
     let error = unsafe { &*error };
     if error.status == 0 {
-      characteristic.state.end_handle = unsafe { (*chr).def_handle - 1 };
-    } else if error.status == esp_idf_sys::BLE_HS_EDONE as _ {
-      characteristic.state.end_handle = characteristic.state.service.upgrade().unwrap().end_handle;
+      *unsafe { Completion::<u16>::data_of(arg) } = unsafe { (*chr).def_handle - 1 };
     }
 
-    characteristic.state.signal.signal(error.status as _);
+    unsafe { Completion::<u16>::complete(arg, error.status as _) };
     esp_idf_sys::BLE_HS_EDONE as _
   }
 
   extern "C" fn descriptor_disc_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     _chr_val_handle: u16,
     dsc: *const esp_idf_sys::ble_gatt_dsc,
     arg: *mut c_void,
   ) -> i32 {
-    let characteristic = unsafe { voidp_to_ref::<Self>(arg) };
-    if characteristic.state.conn_handle() != conn_handle {
-      return 0;
-    }
-
     let error = unsafe { &*error };
-    let dsc = unsafe { &*dsc };
 
     if error.status == 0 {
-      let descriptor =
-        BLERemoteDescriptor::new(ArcUnsafeCell::downgrade(&characteristic.state), dsc);
-      characteristic
-        .state
-        .descriptors
-        .as_mut()
-        .unwrap()
-        .push(descriptor);
+      let mut data = unsafe { Completion::<DescriptorDisc>::data_of(arg) };
+      let descriptor = BLERemoteDescriptor::new(data.0.clone(), unsafe { &*dsc });
+      data.1.push(descriptor);
       return 0;
     }
 
-    characteristic.state.signal.signal(error.status as _);
+    unsafe { Completion::<DescriptorDisc>::complete(arg, error.status as _) };
     esp_idf_sys::BLE_HS_EDONE as _
   }
 
   pub async fn read_value(&mut self) -> Result<Vec<u8>, BLEError> {
-    let mut reader = BLEReader::new(self.state.conn_handle(), self.state.handle);
-    reader.read_value().await
+    self.read_value_with_timeout(self.state.timeout()).await
+  }
+
+  /// Read the value, disconnecting if the read does not complete within `timeout`.
+  pub async fn read_value_with_timeout(
+    &mut self,
+    timeout: Option<Duration>,
+  ) -> Result<Vec<u8>, BLEError> {
+    let (conn_handle, handle) = (self.state.conn_handle(), self.state.handle);
+    BLEClient::retry_secured(self.state.get_client(), || async move {
+      BLEReader::new(conn_handle, handle)
+        .read_value(timeout)
+        .await
+    })
+    .await
   }
 
   pub async fn write_value(&mut self, data: &[u8], response: bool) -> Result<(), BLEError> {
-    let mut writer = BLEWriter::new(self.state.conn_handle(), self.state.handle);
-    writer.write_value(data, response).await
+    self
+      .write_value_with_timeout(data, response, self.state.timeout())
+      .await
+  }
+
+  /// Write the value, disconnecting if the write does not complete within `timeout`.
+  pub async fn write_value_with_timeout(
+    &mut self,
+    data: &[u8],
+    response: bool,
+    timeout: Option<Duration>,
+  ) -> Result<(), BLEError> {
+    let (conn_handle, handle) = (self.state.conn_handle(), self.state.handle);
+    BLEClient::retry_secured(self.state.get_client(), || async move {
+      BLEWriter::new(conn_handle, handle)
+        .write_value(data, response, timeout)
+        .await
+    })
+    .await
+  }
+
+  /// Write the value without response, signed with the CSRK this device distributed when
+  /// bonding (see [`PairKeyDist::SIGN`](crate::enums::PairKeyDist::SIGN)) and a sign counter
+  /// that is never reused.
+  ///
+  /// On an encrypted connection the value is written unsigned, as the specification
+  /// requires, without reporting it.
+  ///
+  /// The signature is computed by this crate as for an ATT Signed Write Command (the sign
+  /// counter and the 64-bit MAC, see [`SIGNATURE_LEN`](crate::SIGNATURE_LEN)) and appended to
+  /// the value of a Write Without Response. A server of this crate verifies it for
+  /// characteristics with [`NimbleProperties::AUTH_SIGN_WRITE`](crate::NimbleProperties::AUTH_SIGN_WRITE);
+  /// other servers receive the signature as part of the value.
+  pub async fn write_signed(&mut self, data: &[u8]) -> Result<(), BLEError> {
+    let (conn_handle, handle) = (self.state.conn_handle(), self.state.handle);
+    let desc = ble_gap_conn_find(conn_handle)?;
+    let signed;
+    let data = if desc.encrypted() {
+      data
+    } else {
+      signed = crate::signed_write::sign(&desc, handle, data)?;
+      &signed
+    };
+    BLEWriter::new(conn_handle, handle)
+      .write_value(data, false, self.state.timeout())
+      .await
   }
 
   pub async fn subscribe_notify(&mut self, response: bool) -> Result<(), BLEError> {
@@ -220,6 +370,53 @@ impl BLERemoteCharacteristic {
     desc.write_value(&val.to_ne_bytes(), response).await
   }
 
+  /// Subscribe to the characteristic and stream the notified values.
+  ///
+  /// Values are received in the NimBLE host task and buffered until they are consumed, so the
+  /// consumer does not block the host. Values are also passed to [`Self::on_notify`].
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # use esp32_nimble::{BLEError, BLERemoteCharacteristic, NotificationStreamParams};
+  /// # async fn run(characteristic: &mut BLERemoteCharacteristic) -> Result<(), BLEError> {
+  /// let mut stream = characteristic
+  ///   .notifications(NotificationStreamParams::default())
+  ///   .await?;
+  /// while let Some(value) = stream.next().await {
+  ///   ::log::info!("Notified: {:?}", value);
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub async fn notifications(
+    &mut self,
+    params: NotificationStreamParams,
+  ) -> Result<BLENotificationStream, BLEError> {
+    let val: u16 = if params.indications { 0x02 } else { 0x01 };
+    let queue = Arc::new(Queue::new(params.capacity, params.overflow));
+
+    // Buffer the values notified as soon as the peer accepts the subscription.
+    self.state.notifications = Some(queue.clone());
+    let mut guard = RemoveQueueOnDrop {
+      characteristic: self.clone(),
+      queue: &queue,
+      subscribed: false,
+    };
+
+    let desc = self.get_descriptor(BleUuid::from_uuid16(0x2902)).await?;
+    desc.write_value(&val.to_ne_bytes(), true).await?;
+    let cccd_handle = desc.handle();
+    guard.subscribed = true;
+    drop(guard);
+
+    Ok(BLENotificationStream {
+      characteristic: self.clone(),
+      cccd_handle,
+      queue,
+    })
+  }
+
   pub fn on_notify(&mut self, callback: impl FnMut(&[u8]) + Send + Sync + 'static) -> &mut Self {
     self.state.on_notify = Some(Box::new(callback));
     self
@@ -262,10 +459,22 @@ impl BLERemoteCharacteristic {
   }
 
   pub(crate) unsafe fn notify(&mut self, om: *mut esp_idf_sys::os_mbuf) {
+    let data = unsafe { core::slice::from_raw_parts((*om).om_data, (*om).om_len as _) };
     if let Some(no_notify) = self.state.on_notify.as_mut() {
-      let data = unsafe { core::slice::from_raw_parts((*om).om_data, (*om).om_len as _) };
       no_notify(data);
     }
+    if let Some(queue) = &self.state.notifications {
+      if !queue.push(data.to_vec()) {
+        ::log::debug!("notification buffer full, dropped a value");
+      }
+    }
+  }
+
+  /// End the notification stream, as the connection is closed.
+  pub(crate) fn close_notifications(&mut self) {
+    if let Some(queue) = self.state.notifications.take() {
+      queue.close();
+    }
   }
 }
 
@@ -1,10 +1,11 @@
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use super::ble_remote_characteristic::BLERemoteCharacteristicState;
 use super::{BLEReader, BLEWriter};
 use crate::{
   utilities::{BleUuid, WeakUnsafeCell},
-  BLEError,
+  BLEAttribute, BLEClient, BLEError,
 };
 
 #[derive(Clone)]
@@ -30,6 +31,10 @@ impl BLERemoteDescriptor {
     self.uuid
   }
 
+  pub(crate) fn handle(&self) -> u16 {
+    self.handle
+  }
+
   fn conn_handle(&self) -> u16 {
     match self.characteristic.upgrade() {
       Some(x) => x.conn_handle(),
@@ -37,13 +42,51 @@ impl BLERemoteDescriptor {
     }
   }
 
+  fn timeout(&self) -> Option<Duration> {
+    self.characteristic.upgrade().and_then(|x| x.timeout())
+  }
+
+  fn get_client(&self) -> Option<BLEClient> {
+    self.characteristic.upgrade().and_then(|x| x.get_client())
+  }
+
   pub async fn read_value(&mut self) -> Result<Vec<u8>, BLEError> {
-    let mut reader = BLEReader::new(self.conn_handle(), self.handle);
-    reader.read_value().await
+    self.read_value_with_timeout(self.timeout()).await
+  }
+
+  /// Read the value, disconnecting if the read does not complete within `timeout`.
+  pub async fn read_value_with_timeout(
+    &mut self,
+    timeout: Option<Duration>,
+  ) -> Result<Vec<u8>, BLEError> {
+    let (conn_handle, handle) = (self.conn_handle(), self.handle);
+    BLEClient::retry_secured(self.get_client(), || async move {
+      BLEReader::new(conn_handle, handle)
+        .read_value(timeout)
+        .await
+    })
+    .await
   }
 
   pub async fn write_value(&mut self, data: &[u8], response: bool) -> Result<(), BLEError> {
-    let mut writer = BLEWriter::new(self.conn_handle(), self.handle);
-    writer.write_value(data, response).await
+    self
+      .write_value_with_timeout(data, response, self.timeout())
+      .await
+  }
+
+  /// Write the value, disconnecting if the write does not complete within `timeout`.
+  pub async fn write_value_with_timeout(
+    &mut self,
+    data: &[u8],
+    response: bool,
+    timeout: Option<Duration>,
+  ) -> Result<(), BLEError> {
+    let (conn_handle, handle) = (self.conn_handle(), self.handle);
+    BLEClient::retry_secured(self.get_client(), || async move {
+      BLEWriter::new(conn_handle, handle)
+        .write_value(data, response, timeout)
+        .await
+    })
+    .await
   }
 }
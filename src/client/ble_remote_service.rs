@@ -1,8 +1,8 @@
 use super::ble_client::BLEClientState;
 use crate::{
   ble,
-  utilities::{voidp_to_ref, ArcUnsafeCell, BleUuid, WeakUnsafeCell},
-  BLEAttribute, BLEClient, BLEError, BLERemoteCharacteristic, Signal,
+  utilities::{with_conn_timeout, ArcUnsafeCell, BleUuid, Completion, WeakUnsafeCell},
+  BLEAttribute, BLEClient, BLEError, BLERemoteCharacteristic,
 };
 use alloc::vec::Vec;
 use core::ffi::c_void;
@@ -13,9 +13,14 @@ pub struct BLERemoteServiceState {
   start_handle: u16,
   pub(crate) end_handle: u16,
   pub(crate) characteristics: Option<Vec<BLERemoteCharacteristic>>,
-  signal: Signal<u32>,
 }
 
+/// Service whose characteristics are discovered, and the discovered characteristics.
+type CharacteristicDisc = (
+  WeakUnsafeCell<BLERemoteServiceState>,
+  Vec<BLERemoteCharacteristic>,
+);
+
 impl BLEAttribute for BLERemoteServiceState {
   fn get_client(&self) -> Option<BLEClient> {
     self.client.upgrade().map(BLEClient::from_state)
@@ -39,7 +44,6 @@ impl BLERemoteService {
         start_handle: service.start_handle,
         end_handle: service.end_handle,
         characteristics: None,
-        signal: Signal::new(),
       }),
     }
   }
@@ -53,17 +57,20 @@ impl BLERemoteService {
     &mut self,
   ) -> Result<core::slice::IterMut<'_, BLERemoteCharacteristic>, BLEError> {
     if self.state.characteristics.is_none() {
-      self.state.characteristics = Some(Vec::new());
-      unsafe {
-        ble!(esp_idf_sys::ble_gattc_disc_all_chrs(
+      let completion = Completion::new((ArcUnsafeCell::downgrade(&self.state), Vec::new()));
+      completion.start(|arg| unsafe {
+        esp_idf_sys::ble_gattc_disc_all_chrs(
           self.state.conn_handle(),
           self.state.start_handle,
           self.state.end_handle,
           Some(Self::characteristic_disc_cb),
-          self as *mut Self as _,
-        ))?;
-      }
-      ble!(self.state.signal.wait().await)?;
+          arg,
+        )
+      })?;
+
+      let (conn_handle, timeout) = (self.state.conn_handle(), self.state.timeout());
+      ble!(with_conn_timeout(conn_handle, timeout, completion.wait()).await?)?;
+      self.state.characteristics = Some(core::mem::take(&mut completion.data().1));
     }
 
     Ok(self.state.characteristics.as_mut().unwrap().iter_mut())
@@ -81,25 +88,21 @@ impl BLERemoteService {
   }
 
   extern "C" fn characteristic_disc_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     chr: *const esp_idf_sys::ble_gatt_chr,
     arg: *mut c_void,
   ) -> i32 {
-    let service = unsafe { voidp_to_ref::<Self>(arg) };
-    if service.state.conn_handle() != conn_handle {
-      return 0;
-    }
     let error = unsafe { &*error };
-    let chr = unsafe { &*chr };
 
     if error.status == 0 {
-      let chr = BLERemoteCharacteristic::new(ArcUnsafeCell::downgrade(&service.state), chr);
-      service.state.characteristics.as_mut().unwrap().push(chr);
+      let mut data = unsafe { Completion::<CharacteristicDisc>::data_of(arg) };
+      let chr = BLERemoteCharacteristic::new(data.0.clone(), unsafe { &*chr });
+      data.1.push(chr);
       return 0;
     }
 
-    service.state.signal.signal(error.status as _);
+    unsafe { Completion::<CharacteristicDisc>::complete(arg, error.status as _) };
     error.status as _
   }
 }
@@ -1,23 +1,194 @@
-use crate::utilities::mutex::Mutex;
-use crate::{ble, enums::*, utilities::voidp_to_ref, BLEAdvertisedDevice, BLEError, Signal};
+use crate::{
+  ble,
+  enums::*,
+  utilities::{voidp_to_ref, OverflowPolicy, Queue},
+  BLEAddress, BLEAdvertisedDevice, BLEError, Signal,
+};
+use alloc::boxed::Box;
 use alloc::sync::Arc;
-use alloc::{boxed::Box, vec::Vec};
 use core::ffi::c_void;
+use core::time::Duration;
+
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+use super::{ExtAdvReport, ReportAssembler};
+use super::{PendingAdvertisements, ScanCache, ScanFilter};
+#[cfg(esp_idf_bt_nimble_enable_periodic_sync)]
+use super::{PeriodicSync, PeriodicSyncParams};
+
+/// Duration of an extended scan started with a duration of 0, the `BLE_GAP_DISC_DUR_DFLT` of
+/// legacy scans.
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+const DEFAULT_DISC_DURATION_MS: i32 = 10240;
 
 pub struct BLEScan {
   #[allow(clippy::type_complexity)]
   on_result: Option<Box<dyn FnMut(&mut Self, &BLEAdvertisedDevice) + Send + Sync>>,
   on_completed: Option<Box<dyn FnMut() + Send + Sync>>,
   scan_params: esp_idf_sys::ble_gap_disc_params,
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  coded_params: esp_idf_sys::ble_gap_ext_disc_params,
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  phys: (bool, bool),
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  reports: ReportAssembler,
   stopped: bool,
-  scan_results: Vec<BLEAdvertisedDevice>,
+  scan_results: ScanCache,
+  filter: Option<ScanFilter>,
+  pending: PendingAdvertisements,
   signal: Signal<()>,
+  queue: Option<Arc<Queue<BLEAdvertisedDevice>>>,
+}
+
+/// Parameters of [`BLEScan::stream`].
+#[derive(Copy, Clone, Debug)]
+pub struct ScanStreamParams {
+  /// Scan duration in milliseconds, `i32::MAX` to scan until the stream is dropped.
+  pub duration_ms: i32,
+  /// Number of reports buffered until the consumer catches up.
+  pub capacity: usize,
+  /// What to do with a new report when the buffer is full.
+  pub overflow: OverflowPolicy,
 }
 
-type CbArgType<'a> = (
-  &'a mut BLEScan,
-  Option<&'a mut (dyn FnMut(&mut BLEScan, &BLEAdvertisedDevice) + Send + Sync)>,
-);
+impl Default for ScanStreamParams {
+  fn default() -> Self {
+    Self {
+      duration_ms: i32::MAX,
+      capacity: 16,
+      overflow: OverflowPolicy::DropOldest,
+    }
+  }
+}
+
+/// Stream of advertisement reports returned by [`BLEScan::stream`].
+///
+/// Scanning stops when the stream is dropped.
+pub struct BLEScanStream<'a> {
+  scan: &'a mut BLEScan,
+  queue: Arc<Queue<BLEAdvertisedDevice>>,
+}
+
+impl BLEScanStream<'_> {
+  /// Wait for the next advertisement report.
+  ///
+  /// Returns `None` once the scan has completed and all buffered reports were received.
+  pub async fn next(&mut self) -> Option<BLEAdvertisedDevice> {
+    self.queue.pop().await
+  }
+
+  /// Stop scanning, returning the error that dropping the stream would only log.
+  pub fn stop(self) -> Result<(), BLEError> {
+    if self.scan.stopped {
+      return Ok(());
+    }
+    self.scan.stop()
+  }
+}
+
+impl Drop for BLEScanStream<'_> {
+  fn drop(&mut self) {
+    if !self.scan.stopped {
+      if let Err(err) = self.scan.stop() {
+        ::log::warn!("failed to stop scan: {:?}", err);
+      }
+    }
+    self.scan.queue = None;
+  }
+}
+
+/// Advertisement report of a legacy or an extended discovery event.
+struct Report<'a> {
+  addr: esp_idf_sys::ble_addr_t,
+  event_type: u8,
+  is_scan_rsp: bool,
+  /// The advertiser answers scan requests, an active scan waits for its scan response.
+  scannable: bool,
+  rssi: i8,
+  data: &'a [u8],
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  ext: ExtAdvReport,
+}
+
+impl<'a> Report<'a> {
+  fn from_disc(disc: &'a esp_idf_sys::ble_gap_disc_desc) -> Self {
+    Self {
+      addr: disc.addr,
+      event_type: disc.event_type,
+      is_scan_rsp: disc.event_type == esp_idf_sys::BLE_HCI_ADV_RPT_EVTYPE_SCAN_RSP as u8,
+      scannable: is_scannable(disc.event_type),
+      rssi: disc.rssi,
+      data: unsafe { core::slice::from_raw_parts(disc.data, disc.length_data as _) },
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      ext: ExtAdvReport::LEGACY,
+    }
+  }
+
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  fn from_ext_disc(
+    disc: &esp_idf_sys::ble_gap_ext_disc_desc,
+    data: &'a [u8],
+    data_status: AdvDataStatus,
+  ) -> Self {
+    let props = disc.props as u32;
+    let legacy = (props & esp_idf_sys::BLE_HCI_ADV_LEGACY_MASK) != 0;
+
+    let (event_type, is_scan_rsp, scannable) = if legacy {
+      (
+        disc.legacy_event_type,
+        disc.legacy_event_type == esp_idf_sys::BLE_HCI_ADV_RPT_EVTYPE_SCAN_RSP as u8,
+        is_scannable(disc.legacy_event_type),
+      )
+    } else {
+      let adv_type = if (props & esp_idf_sys::BLE_HCI_ADV_DIRECT_MASK) != 0 {
+        AdvType::DirectInd
+      } else if (props & esp_idf_sys::BLE_HCI_ADV_CONN_MASK) != 0 {
+        AdvType::Ind
+      } else if (props & esp_idf_sys::BLE_HCI_ADV_SCAN_MASK) != 0 {
+        AdvType::ScanInd
+      } else {
+        AdvType::NonconnInd
+      };
+      // Extended connectable advertisements are never scannable, so their type does not
+      // tell whether a scan response follows.
+      (
+        adv_type as u8,
+        (props & esp_idf_sys::BLE_HCI_ADV_SCAN_RSP_MASK) != 0,
+        (props & esp_idf_sys::BLE_HCI_ADV_SCAN_MASK) != 0,
+      )
+    };
+
+    Self {
+      addr: disc.addr,
+      event_type,
+      is_scan_rsp,
+      scannable,
+      rssi: disc.rssi,
+      data,
+      ext: ExtAdvReport {
+        legacy,
+        sid: disc.sid,
+        primary_phy: disc.prim_phy,
+        secondary_phy: disc.sec_phy,
+        periodic_adv_itvl: disc.periodic_adv_itvl,
+        tx_power: disc.tx_power,
+        data_status,
+      },
+    }
+  }
+}
+
+/// Stops the scan when dropped, if it is still running.
+struct StopOnDrop<'a>(&'a mut BLEScan);
+
+impl Drop for StopOnDrop<'_> {
+  fn drop(&mut self) {
+    if !self.0.stopped {
+      if let Err(err) = self.0.stop() {
+        ::log::warn!("failed to stop scan: {:?}", err);
+      }
+    }
+  }
+}
 
 impl BLEScan {
   pub(crate) fn new() -> Self {
@@ -31,13 +202,24 @@ impl BLEScan {
         _bitfield_align_1: [0; 0],
         _bitfield_1: esp_idf_sys::__BindgenBitfieldUnit::new([0; 1]),
       },
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      coded_params: Default::default(),
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      phys: (true, false),
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      reports: ReportAssembler::new(),
       stopped: true,
-      scan_results: Vec::new(),
+      scan_results: ScanCache::new(),
+      filter: None,
+      pending: PendingAdvertisements::new(),
       signal: Signal::new(),
+      queue: None,
     };
     ret.limited(false);
     ret.filter_duplicates(true);
     ret.active_scan(false).interval(100).window(100);
+    #[cfg(esp_idf_bt_nimble_ext_adv)]
+    ret.coded_interval(100).coded_window(100);
     ret
   }
 
@@ -76,6 +258,64 @@ impl BLEScan {
     self
   }
 
+  /// Set the PHYs to scan on.
+  ///
+  /// The LE 1M PHY (enabled by default) uses [`BLEScan::interval`] and [`BLEScan::window`],
+  /// the LE Coded PHY uses [`BLEScan::coded_interval`] and [`BLEScan::coded_window`].
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn scan_phys(&mut self, le_1m: bool, coded: bool) -> &mut Self {
+    self.phys = (le_1m, coded);
+    self
+  }
+
+  /// Set the interval to scan on the LE Coded PHY.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn coded_interval(&mut self, interval_msecs: u16) -> &mut Self {
+    self.coded_params.itvl = ((interval_msecs as f32) / 0.625) as u16;
+    self
+  }
+
+  /// Set the window to scan on the LE Coded PHY.
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  pub fn coded_window(&mut self, window_msecs: u16) -> &mut Self {
+    self.coded_params.window = ((window_msecs as f32) / 0.625) as u16;
+    self
+  }
+
+  /// Set the maximum number of scan results to keep (at least 1, 64 by default).
+  ///
+  /// When the limit is reached, the least recently seen device is removed.
+  pub fn max_results(&mut self, max_results: usize) -> &mut Self {
+    self.scan_results.set_capacity(max_results);
+    self
+  }
+
+  /// Set how long a device is kept in the scan results after it was last seen.
+  ///
+  /// `None` (the default) keeps devices until they are evicted by [`BLEScan::max_results`].
+  pub fn max_result_age(&mut self, max_age: Option<Duration>) -> &mut Self {
+    self.scan_results.set_max_age(max_age);
+    self
+  }
+
+  /// Set the filter applied to advertisement reports.
+  ///
+  /// Reports of devices that are not in the scan results yet are dropped unless they match the
+  /// filter, before anything is allocated or any callback runs. For scannable advertisements
+  /// in an active scan, the filter is evaluated again with the scan response data appended.
+  pub fn filter(&mut self, filter: ScanFilter) -> &mut Self {
+    self.filter = Some(filter);
+    self.pending.clear();
+    self
+  }
+
+  /// Remove the filter set by [`BLEScan::filter`].
+  pub fn clear_filter(&mut self) -> &mut Self {
+    self.filter = None;
+    self.pending.clear();
+    self
+  }
+
   /// Set a callback to be called when a new scan result is detected.
   /// * callback first parameter: The reference to `Self`
   /// * callback second parameter: Newly found device
@@ -107,49 +347,151 @@ impl BLEScan {
     duration_ms: i32,
     callback: impl Fn(&BLEAdvertisedDevice) -> bool + Send + Sync,
   ) -> Result<Option<BLEAdvertisedDevice>, BLEError> {
-    let result = Arc::new(Mutex::new(Result::Ok(None)));
+    // The callback runs in this future, so it can borrow from the caller.
+    let mut stream = self.stream(ScanStreamParams {
+      duration_ms,
+      ..Default::default()
+    })?;
 
-    let result_clone = result.clone();
-    let mut on_result = move |scan: &mut Self, device: &BLEAdvertisedDevice| {
-      if callback(device) {
-        *result_clone.lock() = scan.stop().and(Ok(Some(device.clone())));
+    while let Some(device) = stream.next().await {
+      if callback(&device) {
+        stream.stop()?;
+        return Ok(Some(device));
       }
-    };
-
-    self.start_core(duration_ms, Some(&mut on_result)).await?;
-    let result = &*result.lock();
-    result.clone()
+    }
+    Ok(None)
   }
 
+  /// Scan for `duration_ms`, calling [`BLEScan::on_result`] for each report.
+  ///
+  /// Scanning stops if the future is dropped before the scan completes.
   pub async fn start(&mut self, duration_ms: i32) -> Result<(), BLEError> {
-    unsafe {
-      let scan = self as *mut Self;
+    // The scan is a static owned by BLEDevice, so it outlives the scan procedure.
+    self.signal.reset();
+    let arg = self as *mut Self as _;
+    self.disc(duration_ms, Some(Self::handle_gap_event), arg)?;
+    self.stopped = false;
+
+    let guard = StopOnDrop(self);
+    guard.0.signal.wait().await;
+    Ok(())
+  }
+
+  /// Start scanning and receive the advertisement reports as an async stream.
+  ///
+  /// Reports are delivered under the same conditions as [`BLEScan::on_result`], and are also
+  /// kept in the scan results.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # use esp32_nimble::{BLEDevice, BLEError, ScanStreamParams};
+  /// # async fn run() -> Result<(), BLEError> {
+  /// let ble_scan = BLEDevice::take().get_scan();
+  /// let mut stream = ble_scan.stream(ScanStreamParams::default())?;
+  /// while let Some(device) = stream.next().await {
+  ///   ::log::info!("Advertised Device: {:?}", device);
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn stream(&mut self, params: ScanStreamParams) -> Result<BLEScanStream<'_>, BLEError> {
+    let queue = Arc::new(Queue::new(params.capacity, params.overflow));
+    self.queue = Some(queue.clone());
 
-      let callback = (*scan).on_result.as_deref_mut(); // .as_ref().map(|x| x.deref_mut());
-      (*scan).start_core(duration_ms, callback).await
+    // The scan is a static owned by BLEDevice, so it outlives the scan procedure.
+    let arg = self as *mut Self as _;
+    if let Err(err) = self.disc(params.duration_ms, Some(Self::handle_stream_gap_event), arg) {
+      self.queue = None;
+      return Err(err);
     }
+    self.stopped = false;
+
+    Ok(BLEScanStream { scan: self, queue })
   }
 
-  #[allow(clippy::type_complexity)]
-  async fn start_core(
+  #[cfg(not(esp_idf_bt_nimble_ext_adv))]
+  fn disc(
     &mut self,
     duration_ms: i32,
-    callback: Option<&mut (dyn FnMut(&mut Self, &BLEAdvertisedDevice) + Send + Sync)>,
+    cb: esp_idf_sys::ble_gap_event_fn,
+    arg: *mut c_void,
   ) -> Result<(), BLEError> {
-    let cb_arg = (self, callback);
     unsafe {
       ble!(esp_idf_sys::ble_gap_disc(
         crate::ble_device::OWN_ADDR_TYPE as _,
         duration_ms,
-        &cb_arg.0.scan_params,
-        Some(Self::handle_gap_event),
-        core::ptr::addr_of!(cb_arg) as _,
-      ))?;
+        &self.scan_params,
+        cb,
+        arg,
+      ))
+    }
+  }
+
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  fn disc(
+    &mut self,
+    duration_ms: i32,
+    cb: esp_idf_sys::ble_gap_event_fn,
+    arg: *mut c_void,
+  ) -> Result<(), BLEError> {
+    let passive = self.scan_params.passive();
+    let mut uncoded_params = esp_idf_sys::ble_gap_ext_disc_params {
+      itvl: self.scan_params.itvl,
+      window: self.scan_params.window,
+      ..Default::default()
+    };
+    uncoded_params.set_passive(passive);
+    self.coded_params.set_passive(passive);
+
+    // The duration is in 10ms units, 0 scans until cancelled. As with `ble_gap_disc`,
+    // `i32::MAX` (`BLE_HS_FOREVER`) scans until cancelled, 0 scans for the default duration
+    // and negative durations are rejected.
+    let duration = match duration_ms {
+      i32::MAX => 0,
+      0 => (DEFAULT_DISC_DURATION_MS / 10) as u16,
+      x if x < 0 => return BLEError::convert(esp_idf_sys::BLE_HS_EINVAL),
+      x => ((x + 9) / 10).min(u16::MAX as i32) as u16,
+    };
+
+    self.reports.clear();
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_ext_disc(
+        crate::ble_device::OWN_ADDR_TYPE as _,
+        duration,
+        0,
+        self.scan_params.filter_duplicates(),
+        self.scan_params.filter_policy,
+        self.scan_params.limited(),
+        if self.phys.0 {
+          &uncoded_params
+        } else {
+          core::ptr::null()
+        },
+        if self.phys.1 {
+          &self.coded_params
+        } else {
+          core::ptr::null()
+        },
+        cb,
+        arg,
+      ))
     }
-    cb_arg.0.stopped = false;
+  }
 
-    cb_arg.0.signal.wait().await;
-    Ok(())
+  /// Synchronize with the periodic advertising train of a device found by the scan.
+  ///
+  /// The sync is established while scanning.
+  #[cfg(esp_idf_bt_nimble_enable_periodic_sync)]
+  pub fn periodic_sync(
+    &mut self,
+    device: &BLEAdvertisedDevice,
+    params: PeriodicSyncParams,
+  ) -> Result<PeriodicSync, BLEError> {
+    let Some(sid) = device.sid() else {
+      return Err(BLEError::convert(esp_idf_sys::BLE_HS_EINVAL).unwrap_err());
+    };
+    PeriodicSync::create(device.addr(), sid, &params)
   }
 
   pub fn stop(&mut self) -> Result<(), BLEError> {
@@ -163,14 +505,24 @@ impl BLEScan {
       callback();
     }
     self.signal.signal(());
+    if let Some(queue) = &self.queue {
+      queue.close();
+    }
 
     Ok(())
   }
 
-  pub fn get_results(&mut self) -> core::slice::Iter<'_, BLEAdvertisedDevice> {
+  /// Get the scan results, from the least to the most recently seen device.
+  pub fn get_results(&mut self) -> alloc::collections::vec_deque::Iter<'_, BLEAdvertisedDevice> {
+    self.purge_results();
     self.scan_results.iter()
   }
 
+  /// Remove the devices older than [`BLEScan::max_result_age`] from the scan results.
+  pub fn purge_results(&mut self) {
+    self.scan_results.purge(crate::utilities::now());
+  }
+
   pub fn clear_results(&mut self) {
     self.scan_results.clear();
   }
@@ -180,54 +532,213 @@ impl BLEScan {
     self.on_completed = None;
   }
 
+  /// Handle a discovery event.
+  /// Returns the device if the report should be delivered to the application.
+  fn on_gap_disc_event(
+    &mut self,
+    event: &esp_idf_sys::ble_gap_event,
+  ) -> Option<&BLEAdvertisedDevice> {
+    match event.type_ as u32 {
+      esp_idf_sys::BLE_GAP_EVENT_DISC => {
+        let disc = unsafe { &event.__bindgen_anon_1.disc };
+        self.on_disc(&Report::from_disc(disc))
+      }
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      esp_idf_sys::BLE_GAP_EVENT_EXT_DISC => {
+        let disc = unsafe { &event.__bindgen_anon_1.ext_disc };
+        let data = if disc.length_data == 0 {
+          &[]
+        } else {
+          unsafe { core::slice::from_raw_parts(disc.data, disc.length_data as _) }
+        };
+        let status = AdvDataStatus::try_from(disc.data_status).unwrap_or(AdvDataStatus::Complete);
+
+        let (data, status) = self.reports.push(&disc.addr, disc.sid, status, data)?;
+        self.on_disc(&Report::from_ext_disc(disc, &data, status))
+      }
+      _ => None,
+    }
+  }
+
+  /// Update the scan results with a report.
+  /// Returns the device if the report should be delivered to the application.
+  fn on_disc(&mut self, report: &Report) -> Option<&BLEAdvertisedDevice> {
+    let passive = self.scan_params.passive() != 0;
+    let is_scan_rsp = report.is_scan_rsp;
+    let data = report.data;
+    ::log::debug!("DATA: {:X?}", data);
+
+    if self.scan_results.touch(&report.addr).is_some() {
+      let advertised_device = self.scan_results.last_mut()?;
+      advertised_device.seen(report.rssi);
+      #[cfg(esp_idf_bt_nimble_ext_adv)]
+      if !is_scan_rsp {
+        advertised_device.set_ext_report(report.ext);
+      }
+      advertised_device.parse_advertisement(data);
+    } else if let Some(filter) = &self.filter {
+      let addr = BLEAddress::from(report.addr);
+      let combined;
+      let (event_type, adv_data) = if is_scan_rsp {
+        // The advertisement was rejected, check again with the scan response.
+        let (event_type, mut adv_data) = self.pending.take(&report.addr)?;
+        adv_data.extend_from_slice(data);
+        combined = adv_data;
+        (event_type, combined.as_slice())
+      } else {
+        (report.event_type, data)
+      };
+
+      if !filter.matches(&addr, report.rssi, adv_data) {
+        if !passive && !is_scan_rsp && report.scannable {
+          self.pending.push(report.addr, report.event_type, data);
+        }
+        return None;
+      }
+
+      self.insert_result(report, event_type, adv_data);
+    } else {
+      if is_scan_rsp {
+        return None;
+      }
+      self.insert_result(report, report.event_type, data);
+    }
+    let advertised_device = self.scan_results.last_mut()?;
+
+    if passive || !report.scannable || is_scan_rsp {
+      Some(advertised_device)
+    } else {
+      None
+    }
+  }
+
+  fn insert_result(&mut self, report: &Report, event_type: u8, data: &[u8]) {
+    self.scan_results.purge(crate::utilities::now());
+    let mut device = BLEAdvertisedDevice::new(report.addr, event_type, report.rssi);
+    #[cfg(esp_idf_bt_nimble_ext_adv)]
+    device.set_ext_report(report.ext);
+    device.parse_advertisement(data);
+    self.scan_results.insert(device);
+  }
+
+  fn on_disc_complete(&mut self) {
+    self.stopped = true;
+    if let Some(callback) = self.on_completed.as_mut() {
+      callback();
+    }
+    self.signal.signal(());
+  }
+
   extern "C" fn handle_gap_event(event: *mut esp_idf_sys::ble_gap_event, arg: *mut c_void) -> i32 {
     let event = unsafe { &*event };
-    let (scan, on_result) = unsafe { voidp_to_ref::<CbArgType>(arg) };
+    let scan = unsafe { voidp_to_ref::<Self>(arg) };
 
     match event.type_ as u32 {
       esp_idf_sys::BLE_GAP_EVENT_EXT_DISC | esp_idf_sys::BLE_GAP_EVENT_DISC => {
-        let disc = unsafe { &event.__bindgen_anon_1.disc };
-
-        let mut advertised_device = scan
-          .scan_results
-          .iter_mut()
-          .find(|x| x.addr().value.val == disc.addr.val);
-
-        if advertised_device.is_none() {
-          if disc.event_type != esp_idf_sys::BLE_HCI_ADV_RPT_EVTYPE_SCAN_RSP as _ {
-            let device = BLEAdvertisedDevice::new(disc);
-            scan.scan_results.push(device);
-            advertised_device = scan.scan_results.last_mut();
-          } else {
-            return 0;
+        if let Some(advertised_device) = scan.on_gap_disc_event(event) {
+          let on_result = unsafe { voidp_to_ref::<Self>(arg) }
+            .on_result
+            .as_deref_mut();
+          if let Some(callback) = on_result {
+            callback(unsafe { voidp_to_ref::<Self>(arg) }, advertised_device);
           }
         }
+      }
+      esp_idf_sys::BLE_GAP_EVENT_DISC_COMPLETE => {
+        scan.on_disc_complete();
+      }
+      _ => {}
+    }
+    0
+  }
 
-        let advertised_device = advertised_device.unwrap();
-
-        let data = unsafe { core::slice::from_raw_parts(disc.data, disc.length_data as _) };
-        ::log::debug!("DATA: {:X?}", data);
-        advertised_device.parse_advertisement(data);
+  extern "C" fn handle_stream_gap_event(
+    event: *mut esp_idf_sys::ble_gap_event,
+    arg: *mut c_void,
+  ) -> i32 {
+    let event = unsafe { &*event };
+    let scan = unsafe { voidp_to_ref::<Self>(arg) };
+    let Some(queue) = scan.queue.clone() else {
+      return 0;
+    };
 
-        if let Some(callback) = on_result {
-          if scan.scan_params.passive() != 0
-            || (advertised_device.adv_type() != AdvType::Ind
-              && advertised_device.adv_type() != AdvType::ScanInd)
-            || disc.event_type == esp_idf_sys::BLE_HCI_ADV_RPT_EVTYPE_SCAN_RSP as _
-          {
-            let (scan, _) = unsafe { voidp_to_ref::<CbArgType>(arg) };
-            callback(scan, advertised_device);
+    match event.type_ as u32 {
+      esp_idf_sys::BLE_GAP_EVENT_EXT_DISC | esp_idf_sys::BLE_GAP_EVENT_DISC => {
+        if let Some(advertised_device) = scan.on_gap_disc_event(event) {
+          if !queue.push(advertised_device.clone()) {
+            ::log::debug!("scan stream overflow");
           }
         }
       }
       esp_idf_sys::BLE_GAP_EVENT_DISC_COMPLETE => {
-        if let Some(callback) = scan.on_completed.as_mut() {
-          callback();
-        }
-        scan.signal.signal(());
+        scan.on_disc_complete();
+        queue.close();
       }
       _ => {}
     }
     0
   }
 }
+
+fn is_scannable(event_type: u8) -> bool {
+  event_type == AdvType::Ind as u8 || event_type == AdvType::ScanInd as u8
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ADDR: esp_idf_sys::ble_addr_t = esp_idf_sys::ble_addr_t {
+    type_: 1,
+    val: [1, 2, 3, 4, 5, 0xc6],
+  };
+  const DATA: [u8; 3] = [0x02, 0x01, 0x06];
+
+  fn active_scan() -> BLEScan {
+    let mut scan = BLEScan::new();
+    scan.active_scan(true);
+    scan
+  }
+
+  fn legacy_disc(event_type: u8) -> esp_idf_sys::ble_gap_disc_desc {
+    esp_idf_sys::ble_gap_disc_desc {
+      event_type,
+      length_data: DATA.len() as _,
+      addr: ADDR,
+      data: DATA.as_ptr(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn active_scan_waits_for_legacy_scan_response() {
+    let mut scan = active_scan();
+    let disc = legacy_disc(AdvType::Ind as _);
+    assert!(scan.on_disc(&Report::from_disc(&disc)).is_none());
+
+    let disc = legacy_disc(esp_idf_sys::BLE_HCI_ADV_RPT_EVTYPE_SCAN_RSP as _);
+    let device = scan.on_disc(&Report::from_disc(&disc)).unwrap();
+    assert_eq!(device.adv_type(), AdvType::Ind);
+
+    let disc = legacy_disc(AdvType::NonconnInd as _);
+    let mut scan = active_scan();
+    assert!(scan.on_disc(&Report::from_disc(&disc)).is_some());
+  }
+
+  #[cfg(esp_idf_bt_nimble_ext_adv)]
+  #[test]
+  fn active_scan_delivers_extended_connectable_report() {
+    let mut scan = active_scan();
+    let disc = esp_idf_sys::ble_gap_ext_disc_desc {
+      props: esp_idf_sys::BLE_HCI_ADV_CONN_MASK as _,
+      addr: ADDR,
+      ..Default::default()
+    };
+    let report = Report::from_ext_disc(&disc, &DATA, AdvDataStatus::Complete);
+    assert!(!report.scannable);
+
+    let device = scan.on_disc(&report).unwrap();
+    assert_eq!(device.adv_type(), AdvType::Ind);
+    assert!(!device.is_legacy_advertisement());
+  }
+}
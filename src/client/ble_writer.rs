@@ -1,9 +1,14 @@
-use crate::{ble, utilities::voidp_to_ref, BLEError, Signal};
+use core::time::Duration;
+
+use crate::{
+  ble,
+  utilities::{with_conn_timeout, Completion},
+  BLEError,
+};
 
 pub struct BLEWriter {
   conn_handle: u16,
   handle: u16,
-  signal: Signal<u32>,
 }
 
 impl BLEWriter {
@@ -11,11 +16,15 @@ impl BLEWriter {
     Self {
       conn_handle,
       handle,
-      signal: Signal::new(),
     }
   }
 
-  pub async fn write_value(&mut self, data: &[u8], response: bool) -> Result<(), BLEError> {
+  pub async fn write_value(
+    &mut self,
+    data: &[u8],
+    response: bool,
+    timeout: Option<Duration>,
+  ) -> Result<(), BLEError> {
     unsafe {
       // ble_att_mtu() returns 0 for a closed connection
       let mtu = esp_idf_sys::ble_att_mtu(self.conn_handle);
@@ -33,45 +42,45 @@ impl BLEWriter {
         ));
       }
 
+      let completion = Completion::new(());
       if data.len() <= mtu {
-        ble!(esp_idf_sys::ble_gattc_write_flat(
-          self.conn_handle,
-          self.handle,
-          data.as_ptr() as _,
-          data.len() as _,
-          Some(Self::on_write_cb),
-          self as *mut Self as _,
-        ))?;
+        completion.start(|arg| {
+          esp_idf_sys::ble_gattc_write_flat(
+            self.conn_handle,
+            self.handle,
+            data.as_ptr() as _,
+            data.len() as _,
+            Some(Self::on_write_cb),
+            arg,
+          )
+        })?;
       } else {
         let om = esp_idf_sys::ble_hs_mbuf_from_flat(data.as_ptr() as _, data.len() as _);
-        ble!(esp_idf_sys::ble_gattc_write_long(
-          self.conn_handle,
-          self.handle,
-          0,
-          om,
-          Some(Self::on_write_cb),
-          self as *mut Self as _
-        ))?;
+        completion.start(|arg| {
+          esp_idf_sys::ble_gattc_write_long(
+            self.conn_handle,
+            self.handle,
+            0,
+            om,
+            Some(Self::on_write_cb),
+            arg,
+          )
+        })?;
       }
 
-      ble!(self.signal.wait().await)?;
+      ble!(with_conn_timeout(self.conn_handle, timeout, completion.wait()).await?)?;
     }
 
     Ok(())
   }
 
   extern "C" fn on_write_cb(
-    conn_handle: u16,
+    _conn_handle: u16,
     error: *const esp_idf_sys::ble_gatt_error,
     _service: *mut esp_idf_sys::ble_gatt_attr,
     arg: *mut core::ffi::c_void,
   ) -> i32 {
-    let writer = unsafe { voidp_to_ref::<Self>(arg) };
-    if writer.conn_handle != conn_handle {
-      return 0;
-    }
-
-    writer.signal.signal(unsafe { (*error).status as _ });
+    unsafe { Completion::<()>::complete(arg, (*error).status as _) };
     0
   }
 }
@@ -1,6 +1,8 @@
 mod ble_advertised_device;
 pub use self::ble_advertised_device::BLEAdvertisedDevice;
 pub use self::ble_advertised_device::BLEServiceData;
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+pub(crate) use self::ble_advertised_device::ExtAdvReport;
 
 mod ble_attribute;
 pub(crate) use self::ble_attribute::*;
@@ -17,8 +19,25 @@ pub use self::ble_remote_descriptor::BLERemoteDescriptor;
 mod ble_remote_service;
 pub use self::ble_remote_service::BLERemoteService;
 
+#[cfg(esp_idf_bt_nimble_enable_periodic_sync)]
+mod periodic_sync;
+#[cfg(esp_idf_bt_nimble_enable_periodic_sync)]
+pub use self::periodic_sync::{PeriodicReport, PeriodicSync, PeriodicSyncInfo, PeriodicSyncParams};
+
 mod ble_scan;
-pub use self::ble_scan::BLEScan;
+pub use self::ble_scan::{BLEScan, BLEScanStream, ScanStreamParams};
+
+mod scan_cache;
+use scan_cache::ScanCache;
+
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+mod report_assembler;
+#[cfg(esp_idf_bt_nimble_ext_adv)]
+use report_assembler::ReportAssembler;
+
+mod scan_filter;
+use scan_filter::PendingAdvertisements;
+pub use scan_filter::ScanFilter;
 
 mod ble_reader;
 use ble_reader::BLEReader;
@@ -0,0 +1,439 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+  ffi::c_void,
+  num::NonZeroI32,
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+  ble,
+  enums::{AdvDataStatus, Phy},
+  utilities::{mutex::Mutex, OverflowPolicy, Queue},
+  BLEAddress, BLEError, Signal,
+};
+
+/// Parameters of a periodic advertising sync.
+#[derive(Copy, Clone, Debug)]
+pub struct PeriodicSyncParams {
+  /// Number of periodic advertising events that can be skipped after a successful receive.
+  pub skip: u16,
+  /// Time without a received packet after which the sync is lost (in 10ms units).
+  pub sync_timeout: u16,
+  /// Number of reports buffered until the consumer catches up.
+  pub capacity: usize,
+  /// What to do with a new report when the buffer is full.
+  pub overflow: OverflowPolicy,
+}
+
+impl Default for PeriodicSyncParams {
+  fn default() -> Self {
+    Self {
+      skip: 0,
+      sync_timeout: 1000,
+      capacity: 16,
+      overflow: OverflowPolicy::DropOldest,
+    }
+  }
+}
+
+/// Periodic advertising train the sync is established with.
+#[derive(Copy, Clone, Debug)]
+pub struct PeriodicSyncInfo {
+  /// Advertising set ID.
+  pub sid: u8,
+  /// Advertiser address.
+  pub addr: BLEAddress,
+  /// PHY of the periodic advertisements.
+  pub phy: Phy,
+  /// Periodic advertising interval (in 1.25ms units).
+  pub interval: u16,
+  /// Clock accuracy of the advertiser.
+  pub clock_accuracy: u8,
+  /// Value set by the peer that transferred the sync, `None` if the sync was created locally.
+  pub service_data: Option<u16>,
+}
+
+/// Periodic advertisement received by a [`PeriodicSync`].
+#[derive(Clone, Debug)]
+pub struct PeriodicReport {
+  rssi: i8,
+  tx_power: i8,
+  data_status: AdvDataStatus,
+  data: Vec<u8>,
+}
+
+impl PeriodicReport {
+  pub fn rssi(&self) -> i8 {
+    self.rssi
+  }
+
+  /// Get the transmission power reported by the controller (dBm).
+  pub fn tx_power(&self) -> Option<i8> {
+    (self.tx_power != 127).then_some(self.tx_power)
+  }
+
+  /// Get whether the data is complete or was truncated.
+  pub fn data_status(&self) -> AdvDataStatus {
+    self.data_status
+  }
+
+  /// Get the periodic advertising data.
+  pub fn data(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+/// State shared with the GAP event callback.
+struct SyncState {
+  sync_handle: Mutex<Option<u16>>,
+  established: Mutex<Option<Result<PeriodicSyncInfo, BLEError>>>,
+  lost_reason: Mutex<Option<BLEError>>,
+  buf: Mutex<Vec<u8>>,
+  reports: Queue<PeriodicReport>,
+  signal: Signal<()>,
+  dropped: AtomicBool,
+  /// Set by the first of a transfer reaching the callback and the handle cancelling the
+  /// receive; that side owns the callback argument.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  transferred: AtomicBool,
+}
+
+/// Synchronization with the periodic advertising train of a device.
+///
+/// The sync is terminated when the handle is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use esp32_nimble::{BLEDevice, BLEError, PeriodicSyncParams};
+/// # async fn run() -> Result<(), BLEError> {
+/// let ble_scan = BLEDevice::take().get_scan();
+/// let device = ble_scan.find_device(10000, |x| x.periodic_adv_interval().is_some()).await?.unwrap();
+/// let mut sync = ble_scan.periodic_sync(&device, PeriodicSyncParams::default())?;
+/// let info = sync.established().await?;
+/// while let Some(report) = sync.next().await {
+///   ::log::info!("Periodic report: {:X?}", report.data());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct PeriodicSync {
+  state: Arc<SyncState>,
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  receive_conn: Option<u16>,
+}
+
+impl PeriodicSync {
+  /// Maximum length of periodic advertising data.
+  const MAX_DATA_LEN: usize = 1650;
+
+  pub(crate) fn create(
+    addr: &BLEAddress,
+    sid: u8,
+    params: &PeriodicSyncParams,
+  ) -> Result<Self, BLEError> {
+    let (sync, arg) = Self::new(params);
+    let rc = unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_sync_create(
+        &addr.value,
+        sid,
+        &Self::sync_params(params),
+        Some(Self::handle_gap_event),
+        arg,
+      ))
+    };
+    if let Err(err) = rc {
+      unsafe { Self::release(arg) };
+      return Err(err);
+    }
+
+    Ok(sync)
+  }
+
+  /// Create a sync, and the callback argument that keeps its state alive
+  /// until the sync is lost or fails to be established.
+  fn new(params: &PeriodicSyncParams) -> (Self, *mut c_void) {
+    let state = Arc::new(SyncState {
+      sync_handle: Mutex::new(None),
+      established: Mutex::new(None),
+      lost_reason: Mutex::new(None),
+      buf: Mutex::new(Vec::new()),
+      reports: Queue::new(params.capacity, params.overflow),
+      signal: Signal::new(),
+      dropped: AtomicBool::new(false),
+      #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+      transferred: AtomicBool::new(false),
+    });
+    let arg = Arc::into_raw(state.clone()) as *mut c_void;
+    let sync = Self {
+      state,
+      #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+      receive_conn: None,
+    };
+    (sync, arg)
+  }
+
+  /// Accept a periodic sync transferred by the peer of a connection.
+  ///
+  /// The sync is established once the peer transfers one, see [`PeriodicSync::established`].
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn receive(conn_handle: u16, params: PeriodicSyncParams) -> Result<Self, BLEError> {
+    let (mut sync, arg) = Self::new(&params);
+    let rc = unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_sync_receive(
+        conn_handle,
+        &Self::sync_params(&params),
+        Some(Self::handle_gap_event),
+        arg,
+      ))
+    };
+    if let Err(err) = rc {
+      unsafe { Self::release(arg) };
+      return Err(err);
+    }
+
+    sync.receive_conn = Some(conn_handle);
+    Ok(sync)
+  }
+
+  /// Transfer the sync to the peer of a connection.
+  ///
+  /// * `service_data`: value identifying the periodic advertising train to the peer.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn transfer(&self, conn_handle: u16, service_data: u16) -> Result<(), BLEError> {
+    let Some(sync_handle) = *self.state.sync_handle.lock() else {
+      return BLEError::convert(esp_idf_sys::BLE_HS_ENOTCONN);
+    };
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_sync_transfer(
+        sync_handle,
+        conn_handle,
+        service_data
+      ))
+    }
+  }
+
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  fn stop_receive(conn_handle: u16) -> i32 {
+    unsafe {
+      esp_idf_sys::ble_gap_periodic_adv_sync_receive(
+        conn_handle,
+        core::ptr::null(),
+        None,
+        core::ptr::null_mut(),
+      )
+    }
+  }
+
+  fn sync_params(params: &PeriodicSyncParams) -> esp_idf_sys::ble_gap_periodic_sync_params {
+    esp_idf_sys::ble_gap_periodic_sync_params {
+      skip: params.skip,
+      sync_timeout: params.sync_timeout,
+      ..Default::default()
+    }
+  }
+
+  unsafe fn release(arg: *mut c_void) {
+    drop(Arc::from_raw(arg as *const SyncState));
+  }
+
+  /// Wait until the sync is established.
+  pub async fn established(&mut self) -> Result<PeriodicSyncInfo, BLEError> {
+    loop {
+      if let Some(result) = *self.state.established.lock() {
+        return result;
+      }
+      if self.state.reports.is_closed() {
+        return Err(BLEError::fail().unwrap_err());
+      }
+      self.state.signal.wait().await;
+    }
+  }
+
+  /// Wait for the next periodic report.
+  ///
+  /// Returns `None` once the sync is lost and all buffered reports were received.
+  pub async fn next(&mut self) -> Option<PeriodicReport> {
+    self.state.reports.pop().await
+  }
+
+  /// Returns whether the sync was lost or could not be established.
+  pub fn is_lost(&self) -> bool {
+    self.state.reports.is_closed()
+  }
+
+  /// Get why the sync was lost, `None` if it is active or was terminated locally.
+  pub fn lost_reason(&self) -> Option<BLEError> {
+    *self.state.lost_reason.lock()
+  }
+
+  /// Enable or disable the reception of periodic reports, keeping the sync.
+  pub fn set_reporting(&mut self, enable: bool) -> Result<(), BLEError> {
+    let Some(sync_handle) = *self.state.sync_handle.lock() else {
+      return BLEError::convert(esp_idf_sys::BLE_HS_ENOTCONN);
+    };
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_sync_reporting(
+        sync_handle,
+        enable
+      ))
+    }
+  }
+
+  extern "C" fn handle_gap_event(event: *mut esp_idf_sys::ble_gap_event, arg: *mut c_void) -> i32 {
+    let event = unsafe { &*event };
+    let state = unsafe { &*(arg as *const SyncState) };
+
+    match event.type_ as u32 {
+      esp_idf_sys::BLE_GAP_EVENT_PERIODIC_SYNC => {
+        let sync = unsafe { &event.__bindgen_anon_1.periodic_sync };
+        if sync.status == 0 {
+          let info = PeriodicSyncInfo {
+            sid: sync.sid,
+            addr: sync.adv_addr.into(),
+            phy: Phy::try_from(sync.adv_phy).unwrap_or(Phy::Le1M),
+            interval: sync.per_adv_ival,
+            clock_accuracy: sync.adv_clk_accuracy,
+            service_data: None,
+          };
+          Self::on_established(state, sync.sync_handle, info);
+        } else {
+          unsafe { Self::on_failed(state, sync.status, arg) };
+        }
+      }
+      #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+      esp_idf_sys::BLE_GAP_EVENT_PERIODIC_TRANSFER => {
+        let transfer = unsafe { &event.__bindgen_anon_1.periodic_transfer };
+        // A sync handles a single transfer.
+        Self::stop_receive(transfer.conn_handle);
+        if state.transferred.swap(true, Ordering::AcqRel) {
+          // The handle was dropped and released the callback argument: the state must not be
+          // used anymore, and a sync established by the transfer has no owner.
+          if transfer.status == 0 {
+            unsafe { esp_idf_sys::ble_gap_periodic_adv_sync_terminate(transfer.sync_handle) };
+          }
+          return 0;
+        }
+
+        if transfer.status == 0 {
+          let info = PeriodicSyncInfo {
+            sid: transfer.sid,
+            addr: transfer.adv_addr.into(),
+            phy: Phy::try_from(transfer.adv_phy).unwrap_or(Phy::Le1M),
+            interval: transfer.per_itvl,
+            clock_accuracy: transfer.adv_clk_accuracy,
+            service_data: Some(transfer.service_data),
+          };
+          Self::on_established(state, transfer.sync_handle, info);
+        } else {
+          unsafe { Self::on_failed(state, transfer.status, arg) };
+        }
+      }
+      esp_idf_sys::BLE_GAP_EVENT_PERIODIC_REPORT => {
+        let report = unsafe { &event.__bindgen_anon_1.periodic_report };
+        let data = if report.data_length == 0 {
+          &[]
+        } else {
+          unsafe { core::slice::from_raw_parts(report.data, report.data_length as _) }
+        };
+        let data_status =
+          AdvDataStatus::try_from(report.data_status).unwrap_or(AdvDataStatus::Complete);
+
+        let mut buf = state.buf.lock();
+        let len = data.len().min(Self::MAX_DATA_LEN - buf.len());
+        buf.extend_from_slice(&data[..len]);
+        if data_status == AdvDataStatus::Incomplete && len == data.len() {
+          return 0;
+        }
+
+        let periodic_report = PeriodicReport {
+          rssi: report.rssi,
+          tx_power: report.tx_power,
+          data_status: if len == data.len() {
+            data_status
+          } else {
+            AdvDataStatus::Truncated
+          },
+          data: core::mem::take(&mut *buf),
+        };
+        drop(buf);
+        if !state.reports.push(periodic_report) {
+          ::log::debug!("periodic sync overflow");
+        }
+      }
+      esp_idf_sys::BLE_GAP_EVENT_PERIODIC_SYNC_LOST => {
+        let sync_lost = unsafe { &event.__bindgen_anon_1.periodic_sync_lost };
+        *state.sync_handle.lock() = None;
+        *state.lost_reason.lock() = NonZeroI32::new(sync_lost.reason)
+          .filter(|x| x.get() != esp_idf_sys::BLE_HS_EDONE as i32)
+          .map(BLEError::from_non_zero);
+        state.reports.close();
+        state.signal.signal(());
+        unsafe { Self::release(arg) };
+      }
+      _ => {}
+    }
+
+    0
+  }
+}
+
+impl PeriodicSync {
+  fn on_established(state: &SyncState, sync_handle: u16, info: PeriodicSyncInfo) {
+    *state.sync_handle.lock() = Some(sync_handle);
+    *state.established.lock() = Some(Ok(info));
+    state.signal.signal(());
+
+    if state.dropped.load(Ordering::Acquire) {
+      unsafe { esp_idf_sys::ble_gap_periodic_adv_sync_terminate(sync_handle) };
+    }
+  }
+
+  unsafe fn on_failed(state: &SyncState, status: u8, arg: *mut c_void) {
+    let rc = esp_idf_sys::BLE_HS_ERR_HCI_BASE + status as u32;
+    *state.established.lock() = Some(Err(BLEError::convert(rc).unwrap_err()));
+    state.reports.close();
+    state.signal.signal(());
+    Self::release(arg);
+  }
+
+  fn cancel(&self) -> i32 {
+    #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+    if let Some(conn_handle) = self.receive_conn {
+      // Whichever of this and the transfer callback sets `transferred` first owns the
+      // callback argument: if it is this, the argument is released here and the callback
+      // ignores a transfer still in flight. Otherwise the callback releases it when the sync
+      // ends.
+      match Self::stop_receive(conn_handle) as u32 {
+        0 | esp_idf_sys::BLE_HS_ENOTCONN => {}
+        rc => return rc as _,
+      }
+      if !self.state.transferred.swap(true, Ordering::AcqRel) {
+        unsafe { Self::release(Arc::as_ptr(&self.state) as _) };
+      }
+      return 0;
+    }
+
+    unsafe { esp_idf_sys::ble_gap_periodic_adv_sync_create_cancel() }
+  }
+}
+
+impl Drop for PeriodicSync {
+  fn drop(&mut self) {
+    self.state.dropped.store(true, Ordering::Release);
+    if self.state.reports.is_closed() {
+      return;
+    }
+
+    let rc = match *self.state.sync_handle.lock() {
+      Some(sync_handle) => unsafe { esp_idf_sys::ble_gap_periodic_adv_sync_terminate(sync_handle) },
+      None => self.cancel(),
+    };
+    if rc != 0 {
+      ::log::warn!(
+        "failed to terminate periodic sync: {:?}",
+        BLEError::convert(rc as _)
+      );
+    }
+  }
+}
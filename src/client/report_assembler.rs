@@ -0,0 +1,174 @@
+use alloc::{borrow::Cow, collections::VecDeque, vec::Vec};
+
+use crate::enums::AdvDataStatus;
+
+/// A chain of reports being reassembled.
+struct Chain {
+  addr: esp_idf_sys::ble_addr_t,
+  sid: u8,
+  data: Vec<u8>,
+  /// The chain was already delivered truncated, its remaining reports are dropped.
+  truncated: bool,
+}
+
+/// Reassembles the advertising data of extended advertisement reports,
+/// which the controller splits into a chain of reports with the `Incomplete` status.
+///
+/// Chains are identified by the advertiser address and the advertising set ID.
+pub(crate) struct ReportAssembler {
+  entries: VecDeque<Chain>,
+}
+
+impl ReportAssembler {
+  const CAPACITY: usize = 8;
+  /// Maximum length of extended advertising data.
+  const MAX_DATA_LEN: usize = 1650;
+
+  pub(crate) fn new() -> Self {
+    Self {
+      entries: VecDeque::new(),
+    }
+  }
+
+  /// Add a report to its chain.
+  ///
+  /// Returns the advertising data once the chain is complete or truncated,
+  /// with the status of the last report.
+  pub(crate) fn push<'a>(
+    &mut self,
+    addr: &esp_idf_sys::ble_addr_t,
+    sid: u8,
+    status: AdvDataStatus,
+    data: &'a [u8],
+  ) -> Option<(Cow<'a, [u8]>, AdvDataStatus)> {
+    let idx = self
+      .entries
+      .iter()
+      .position(|x| x.addr.type_ == addr.type_ && x.addr.val == addr.val && x.sid == sid);
+
+    if status != AdvDataStatus::Incomplete {
+      return match idx.and_then(|idx| self.entries.remove(idx)) {
+        Some(chain) if chain.truncated => None,
+        Some(mut chain) => {
+          let status = append(&mut chain.data, data).unwrap_or(status);
+          Some((Cow::Owned(chain.data), status))
+        }
+        None => Some((Cow::Borrowed(data), status)),
+      };
+    }
+
+    let idx = match idx {
+      Some(idx) => idx,
+      None => {
+        if self.entries.len() >= Self::CAPACITY {
+          self.entries.pop_front();
+        }
+        self.entries.push_back(Chain {
+          addr: *addr,
+          sid,
+          data: Vec::new(),
+          truncated: false,
+        });
+        self.entries.len() - 1
+      }
+    };
+
+    let chain = &mut self.entries[idx];
+    if chain.truncated {
+      return None;
+    }
+
+    if append(&mut chain.data, data).is_some() {
+      // The chain is longer than allowed, deliver what was received and drop the rest of
+      // the chain until its last report.
+      chain.truncated = true;
+      let data = core::mem::take(&mut chain.data);
+      return Some((Cow::Owned(data), AdvDataStatus::Truncated));
+    }
+    None
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.entries.clear();
+  }
+}
+
+/// Append `data` to `buf`, returning `Truncated` if it does not fit.
+fn append(buf: &mut Vec<u8>, data: &[u8]) -> Option<AdvDataStatus> {
+  let len = data.len().min(ReportAssembler::MAX_DATA_LEN - buf.len());
+  buf.extend_from_slice(&data[..len]);
+  (len < data.len()).then_some(AdvDataStatus::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+
+  fn addr(x: u8) -> esp_idf_sys::ble_addr_t {
+    esp_idf_sys::ble_addr_t {
+      type_: 1,
+      val: [x, 0, 0, 0, 0, 0xc0],
+    }
+  }
+
+  #[test]
+  fn reassembles_chains() {
+    let mut reports = ReportAssembler::new();
+    assert!(reports
+      .push(&addr(1), 0, AdvDataStatus::Incomplete, &[1, 2])
+      .is_none());
+    // Another set of the same advertiser, interleaved.
+    assert!(reports
+      .push(&addr(1), 1, AdvDataStatus::Incomplete, &[9])
+      .is_none());
+
+    let (data, status) = reports
+      .push(&addr(1), 0, AdvDataStatus::Complete, &[3])
+      .unwrap();
+    assert_eq!(&*data, &[1, 2, 3]);
+    assert_eq!(status, AdvDataStatus::Complete);
+
+    let (data, status) = reports
+      .push(&addr(1), 1, AdvDataStatus::Truncated, &[8])
+      .unwrap();
+    assert_eq!(&*data, &[9, 8]);
+    assert_eq!(status, AdvDataStatus::Truncated);
+
+    let (data, _) = reports
+      .push(&addr(2), 0, AdvDataStatus::Complete, &[7])
+      .unwrap();
+    assert!(matches!(data, Cow::Borrowed(&[7])));
+  }
+
+  #[test]
+  fn drops_rest_of_truncated_chain() {
+    let mut reports = ReportAssembler::new();
+    let fragment = vec![0xaa; 250];
+    let mut delivered = None;
+    for _ in 0..7 {
+      if let Some((data, status)) = reports.push(&addr(1), 0, AdvDataStatus::Incomplete, &fragment)
+      {
+        delivered = Some((data.len(), status));
+      }
+    }
+    assert_eq!(
+      delivered,
+      Some((ReportAssembler::MAX_DATA_LEN, AdvDataStatus::Truncated))
+    );
+
+    assert!(reports
+      .push(&addr(1), 0, AdvDataStatus::Incomplete, &fragment)
+      .is_none());
+    assert!(reports
+      .push(&addr(1), 0, AdvDataStatus::Complete, &fragment)
+      .is_none());
+
+    // The next chain is reassembled again.
+    let (data, status) = reports
+      .push(&addr(1), 0, AdvDataStatus::Complete, &[1])
+      .unwrap();
+    assert_eq!(&*data, &[1]);
+    assert_eq!(status, AdvDataStatus::Complete);
+  }
+}
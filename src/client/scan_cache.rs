@@ -0,0 +1,168 @@
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::BLEAdvertisedDevice;
+
+/// Scan results, ordered from the least to the most recently seen device.
+///
+/// Devices are identified by their address type and address bytes. When the cache is full the
+/// least recently seen device is evicted, and devices that have not been seen for `max_age`
+/// are purged.
+pub(crate) struct ScanCache {
+  entries: VecDeque<BLEAdvertisedDevice>,
+  capacity: usize,
+  max_age: Option<Duration>,
+}
+
+impl ScanCache {
+  pub(crate) const DEFAULT_CAPACITY: usize = 64;
+
+  pub(crate) fn new() -> Self {
+    Self {
+      entries: VecDeque::new(),
+      capacity: Self::DEFAULT_CAPACITY,
+      max_age: None,
+    }
+  }
+
+  pub(crate) fn set_capacity(&mut self, capacity: usize) {
+    self.capacity = capacity.max(1);
+    self.shrink();
+  }
+
+  pub(crate) fn set_max_age(&mut self, max_age: Option<Duration>) {
+    self.max_age = max_age;
+  }
+
+  /// Find a device and mark it as the most recently seen one.
+  pub(crate) fn touch(
+    &mut self,
+    addr: &esp_idf_sys::ble_addr_t,
+  ) -> Option<&mut BLEAdvertisedDevice> {
+    let idx = self.entries.iter().position(|x| {
+      let value = &x.addr().value;
+      value.type_ == addr.type_ && value.val == addr.val
+    })?;
+
+    let device = self.entries.remove(idx)?;
+    self.entries.push_back(device);
+    self.entries.back_mut()
+  }
+
+  /// Insert a newly found device, evicting the least recently seen ones if needed.
+  pub(crate) fn insert(&mut self, device: BLEAdvertisedDevice) {
+    self.entries.push_back(device);
+    self.shrink();
+  }
+
+  /// Get the most recently seen device.
+  pub(crate) fn last_mut(&mut self) -> Option<&mut BLEAdvertisedDevice> {
+    self.entries.back_mut()
+  }
+
+  /// Remove the devices that have not been seen since `now - max_age`.
+  pub(crate) fn purge(&mut self, now: Duration) {
+    if let Some(max_age) = self.max_age {
+      self
+        .entries
+        .retain(|x| now.saturating_sub(x.last_seen()) <= max_age);
+    }
+  }
+
+  pub(crate) fn iter(&self) -> alloc::collections::vec_deque::Iter<'_, BLEAdvertisedDevice> {
+    self.entries.iter()
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  fn shrink(&mut self) {
+    while self.entries.len() > self.capacity {
+      self.entries.pop_front();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{enums::AdvType, BLEAddressType};
+  use alloc::vec::Vec;
+
+  fn addr(x: u8, addr_type: BLEAddressType) -> esp_idf_sys::ble_addr_t {
+    esp_idf_sys::ble_addr_t {
+      type_: addr_type as _,
+      val: [x, 0x22, 0x33, 0x44, 0x55, 0xc6],
+    }
+  }
+
+  fn device(x: u8, addr_type: BLEAddressType) -> BLEAdvertisedDevice {
+    BLEAdvertisedDevice::new(addr(x, addr_type), AdvType::Ind as _, -50)
+  }
+
+  fn ids(cache: &ScanCache) -> Vec<u8> {
+    cache.iter().map(|x| x.addr().value.val[0]).collect()
+  }
+
+  #[test]
+  fn evicts_least_recently_seen_at_capacity() {
+    let mut cache = ScanCache::new();
+    cache.set_capacity(2);
+    cache.insert(device(1, BLEAddressType::Public));
+    cache.insert(device(2, BLEAddressType::Public));
+    cache.insert(device(3, BLEAddressType::Public));
+    assert_eq!(ids(&cache), [2, 3]);
+
+    cache.set_capacity(1);
+    assert_eq!(ids(&cache), [3]);
+  }
+
+  #[test]
+  fn touch_refreshes_existing_address() {
+    let mut cache = ScanCache::new();
+    cache.set_capacity(2);
+    cache.insert(device(1, BLEAddressType::Public));
+    cache.insert(device(2, BLEAddressType::Public));
+
+    assert!(cache.touch(&addr(1, BLEAddressType::Public)).is_some());
+    assert_eq!(ids(&cache), [2, 1]);
+    assert_eq!(cache.last_mut().unwrap().addr().value.val[0], 1);
+
+    cache.insert(device(3, BLEAddressType::Public));
+    assert_eq!(ids(&cache), [1, 3]);
+    assert!(cache.touch(&addr(2, BLEAddressType::Public)).is_none());
+  }
+
+  #[test]
+  fn purges_devices_older_than_max_age() {
+    let mut cache = ScanCache::new();
+    for (x, last_seen) in [(1, 1), (2, 5)] {
+      let mut device = device(x, BLEAddressType::Public);
+      device.set_last_seen(Duration::from_secs(last_seen));
+      cache.insert(device);
+    }
+
+    cache.purge(Duration::from_secs(100));
+    assert_eq!(ids(&cache), [1, 2], "no max age");
+
+    cache.set_max_age(Some(Duration::from_secs(3)));
+    cache.purge(Duration::from_secs(4));
+    assert_eq!(ids(&cache), [1, 2]);
+    cache.purge(Duration::from_secs(6));
+    assert_eq!(ids(&cache), [2]);
+  }
+
+  #[test]
+  fn keeps_public_and_random_addresses_apart() {
+    let mut cache = ScanCache::new();
+    cache.insert(device(1, BLEAddressType::Public));
+    cache.insert(device(1, BLEAddressType::Random));
+    assert_eq!(cache.iter().count(), 2);
+
+    let device = cache.touch(&addr(1, BLEAddressType::Public)).unwrap();
+    assert_eq!(device.addr().addr_type(), BLEAddressType::Public);
+    let device = cache.touch(&addr(1, BLEAddressType::Random)).unwrap();
+    assert_eq!(device.addr().addr_type(), BLEAddressType::Random);
+  }
+}
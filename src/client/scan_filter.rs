@@ -0,0 +1,313 @@
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use bstr::BString;
+
+use crate::{
+  utilities::{AdStructure, BleUuid},
+  BLEAddress, BLEAddressType,
+};
+
+/// Filter applied to advertisement reports before they reach the scan results.
+///
+/// Filters are evaluated on the raw advertising data, so they can be combined freely and
+/// checked without a running scan.
+///
+/// # Examples
+///
+/// ```
+/// use esp32_nimble::{utilities::BleUuid, BLEAddress, BLEAddressType, ScanFilter};
+///
+/// let filter = ScanFilter::NamePrefix("Sensor".into())
+///   .or(ScanFilter::ServiceUuid(BleUuid::from_uuid16(0x181A)))
+///   .and(ScanFilter::MinRssi(-80));
+///
+/// let addr = BLEAddress::new([0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03], BLEAddressType::Public);
+/// // Complete local name "Sensor-1".
+/// let payload = b"\x09\x09Sensor-1";
+/// assert!(filter.matches(&addr, -60, payload));
+/// assert!(!filter.matches(&addr, -90, payload));
+/// ```
+///
+/// The filter is set on a scan with [`BLEScan::filter`](crate::BLEScan::filter).
+#[derive(Clone, Debug)]
+pub enum ScanFilter {
+  /// The service UUID is listed in the advertised service UUIDs.
+  ServiceUuid(BleUuid),
+  /// Service data is advertised for the UUID.
+  ServiceData(BleUuid),
+  /// Manufacturer specific data with the company ID, whose first bytes match `data`.
+  ///
+  /// Only the bits set in `mask` are compared; bytes missing from `mask` are compared fully.
+  ManufacturerData {
+    company_id: u16,
+    data: Vec<u8>,
+    mask: Vec<u8>,
+  },
+  /// The local name (complete or shortened) starts with the prefix.
+  NamePrefix(BString),
+  /// The local name (complete or shortened) matches the pattern,
+  /// where `*` matches any sequence of bytes and `?` matches a single byte.
+  NameGlob(BString),
+  /// The RSSI is at least this value (dBm).
+  MinRssi(i8),
+  /// The advertiser address and address type are equal.
+  Address(BLEAddress),
+  /// The advertiser address type is equal.
+  AddressType(BLEAddressType),
+  /// All of the filters match.
+  All(Vec<ScanFilter>),
+  /// Any of the filters matches.
+  Any(Vec<ScanFilter>),
+  /// The filter does not match.
+  Not(Box<ScanFilter>),
+}
+
+impl ScanFilter {
+  /// Match reports for which both `self` and `other` match.
+  pub fn and(self, other: ScanFilter) -> Self {
+    match self {
+      Self::All(mut filters) => {
+        filters.push(other);
+        Self::All(filters)
+      }
+      filter => Self::All(alloc::vec![filter, other]),
+    }
+  }
+
+  /// Match reports for which `self` or `other` matches.
+  pub fn or(self, other: ScanFilter) -> Self {
+    match self {
+      Self::Any(mut filters) => {
+        filters.push(other);
+        Self::Any(filters)
+      }
+      filter => Self::Any(alloc::vec![filter, other]),
+    }
+  }
+
+  /// Evaluate the filter against an advertisement report.
+  ///
+  /// * `addr`: advertiser address
+  /// * `rssi`: received signal strength (dBm)
+  /// * `payload`: advertising data, followed by the scan response data if any
+  pub fn matches(&self, addr: &BLEAddress, rssi: i8, payload: &[u8]) -> bool {
+    match self {
+      Self::ServiceUuid(uuid) => AdStructure::parse(payload).any(|ad| match ad {
+        AdStructure::ServiceUuids16 { uuids, .. } => {
+          uuids.into_iter().any(|x| BleUuid::from_uuid16(x) == *uuid)
+        }
+        AdStructure::ServiceUuids32 { uuids, .. } => {
+          uuids.into_iter().any(|x| BleUuid::from_uuid32(x) == *uuid)
+        }
+        AdStructure::ServiceUuids128 { uuids, .. } => {
+          uuids.into_iter().any(|x| BleUuid::from_uuid128(x) == *uuid)
+        }
+        _ => false,
+      }),
+      Self::ServiceData(uuid) => AdStructure::parse(payload).any(|ad| match ad {
+        AdStructure::ServiceData16 { uuid: x, .. } => BleUuid::from_uuid16(x) == *uuid,
+        AdStructure::ServiceData32 { uuid: x, .. } => BleUuid::from_uuid32(x) == *uuid,
+        AdStructure::ServiceData128 { uuid: x, .. } => BleUuid::from_uuid128(x) == *uuid,
+        _ => false,
+      }),
+      Self::ManufacturerData {
+        company_id,
+        data,
+        mask,
+      } => AdStructure::parse(payload).any(|ad| match ad {
+        AdStructure::ManufacturerSpecificData(mfg) => {
+          mfg.len() >= 2 + data.len()
+            && mfg[..2] == company_id.to_le_bytes()
+            && data.iter().enumerate().all(|(i, x)| {
+              let mask = mask.get(i).copied().unwrap_or(0xFF);
+              (mfg[2 + i] & mask) == (x & mask)
+            })
+        }
+        _ => false,
+      }),
+      Self::NamePrefix(prefix) => name_matches(payload, |name| name.starts_with(prefix)),
+      Self::NameGlob(pattern) => name_matches(payload, |name| glob_matches(pattern, name)),
+      Self::MinRssi(min) => rssi >= *min,
+      Self::Address(x) => x.val() == addr.val() && x.addr_type() == addr.addr_type(),
+      Self::AddressType(addr_type) => *addr_type == addr.addr_type(),
+      Self::All(filters) => filters.iter().all(|x| x.matches(addr, rssi, payload)),
+      Self::Any(filters) => filters.iter().any(|x| x.matches(addr, rssi, payload)),
+      Self::Not(filter) => !filter.matches(addr, rssi, payload),
+    }
+  }
+}
+
+impl core::ops::Not for ScanFilter {
+  type Output = Self;
+
+  fn not(self) -> Self {
+    Self::Not(Box::new(self))
+  }
+}
+
+fn name_matches(payload: &[u8], f: impl Fn(&[u8]) -> bool) -> bool {
+  AdStructure::parse(payload).any(|ad| match ad {
+    AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => f(&name),
+    _ => false,
+  })
+}
+
+/// Match `text` against a pattern where `*` matches any sequence and `?` matches one byte.
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+  let (mut p, mut t) = (0, 0);
+  let mut backtrack = None;
+
+  while t < text.len() {
+    match pattern.get(p) {
+      Some(b'*') => {
+        backtrack = Some((p, t));
+        p += 1;
+      }
+      Some(&c) if c == b'?' || c == text[t] => {
+        p += 1;
+        t += 1;
+      }
+      _ => match backtrack {
+        Some((bp, bt)) => {
+          p = bp + 1;
+          t = bt + 1;
+          backtrack = Some((bp, bt + 1));
+        }
+        None => return false,
+      },
+    }
+  }
+
+  pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Scannable advertisements rejected by the filter, kept until their scan response arrives
+/// so that the filter can be evaluated on the complete advertising data.
+pub(crate) struct PendingAdvertisements {
+  entries: VecDeque<(esp_idf_sys::ble_addr_t, u8, Vec<u8>)>,
+}
+
+impl PendingAdvertisements {
+  const CAPACITY: usize = 8;
+
+  pub(crate) fn new() -> Self {
+    Self {
+      entries: VecDeque::new(),
+    }
+  }
+
+  pub(crate) fn push(&mut self, addr: esp_idf_sys::ble_addr_t, event_type: u8, data: &[u8]) {
+    self.take(&addr);
+    if self.entries.len() >= Self::CAPACITY {
+      self.entries.pop_front();
+    }
+    self.entries.push_back((addr, event_type, data.to_vec()));
+  }
+
+  /// Remove the advertisement of `addr`, returning its event type and data.
+  pub(crate) fn take(&mut self, addr: &esp_idf_sys::ble_addr_t) -> Option<(u8, Vec<u8>)> {
+    let idx = self
+      .entries
+      .iter()
+      .position(|(x, _, _)| x.type_ == addr.type_ && x.val == addr.val)?;
+    self
+      .entries
+      .remove(idx)
+      .map(|(_, event_type, data)| (event_type, data))
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.entries.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+
+  const PAYLOAD: &[u8] = &[
+    0x02, 0x01, 0x06, // flags
+    0x03, 0x03, 0x1a, 0x18, // complete 16-bit service UUIDs: 0x181A
+    0x05, 0x16, 0x6f, 0xfd, 0xaa, 0xbb, // service data 0xFD6F
+    0x06, 0xff, 0x59, 0x00, 0x02, 0x15, 0x42, // manufacturer data, company 0x0059
+    0x06, 0x08, b'T', b'h', b'e', b'r', b'm', // shortened local name
+  ];
+
+  fn addr() -> BLEAddress {
+    BLEAddress::new([0xc0, 0x01, 0x02, 0x03, 0x04, 0x05], BLEAddressType::Random)
+  }
+
+  fn matches(filter: ScanFilter) -> bool {
+    filter.matches(&addr(), -70, PAYLOAD)
+  }
+
+  #[test]
+  fn services() {
+    assert!(matches(ScanFilter::ServiceUuid(BleUuid::from_uuid16(
+      0x181a
+    ))));
+    assert!(!matches(ScanFilter::ServiceUuid(BleUuid::from_uuid16(
+      0x180d
+    ))));
+    assert!(matches(ScanFilter::ServiceData(BleUuid::from_uuid16(
+      0xfd6f
+    ))));
+    assert!(!matches(ScanFilter::ServiceData(BleUuid::from_uuid16(
+      0x181a
+    ))));
+  }
+
+  #[test]
+  fn manufacturer_data() {
+    let filter = |company_id, data: &[u8], mask: &[u8]| ScanFilter::ManufacturerData {
+      company_id,
+      data: data.to_vec(),
+      mask: mask.to_vec(),
+    };
+    assert!(matches(filter(0x0059, &[], &[])));
+    assert!(matches(filter(0x0059, &[0x02, 0x15], &[])));
+    assert!(!matches(filter(0x0059, &[0x02, 0x16], &[])));
+    assert!(matches(filter(0x0059, &[0x02, 0x10], &[0xff, 0xf0])));
+    assert!(!matches(filter(0x004c, &[0x02], &[])));
+    // Longer than the advertised data.
+    assert!(!matches(filter(0x0059, &[0x02, 0x15, 0x42, 0x00], &[])));
+  }
+
+  #[test]
+  fn names() {
+    assert!(matches(ScanFilter::NamePrefix("Th".into())));
+    assert!(!matches(ScanFilter::NamePrefix("therm".into())));
+    assert!(matches(ScanFilter::NameGlob("T*m".into())));
+    assert!(matches(ScanFilter::NameGlob("?her*".into())));
+    assert!(!matches(ScanFilter::NameGlob("T*x".into())));
+    assert!(!ScanFilter::NamePrefix("".into()).matches(&addr(), 0, &PAYLOAD[..3]));
+  }
+
+  #[test]
+  fn glob() {
+    assert!(glob_matches(b"*", b""));
+    assert!(glob_matches(b"a*b*c", b"aXbYbc"));
+    assert!(!glob_matches(b"a*b?c", b"abc"));
+    assert!(glob_matches(b"**?", b"x"));
+  }
+
+  #[test]
+  fn combinators() {
+    assert!(matches(ScanFilter::MinRssi(-70)));
+    assert!(!matches(ScanFilter::MinRssi(-69)));
+    assert!(matches(ScanFilter::Address(addr())));
+    assert!(!matches(ScanFilter::Address(BLEAddress::new(
+      [0xc0, 0x01, 0x02, 0x03, 0x04, 0x05],
+      BLEAddressType::Public
+    ))));
+    assert!(matches(ScanFilter::AddressType(BLEAddressType::Random)));
+
+    let name = ScanFilter::NamePrefix("Therm".into());
+    assert!(matches(name.clone().and(ScanFilter::MinRssi(-80))));
+    assert!(!matches(name.clone().and(ScanFilter::MinRssi(-60))));
+    assert!(matches(ScanFilter::MinRssi(-60).or(name.clone())));
+    assert!(!matches(!name));
+    assert!(matches(ScanFilter::All(vec![])));
+    assert!(!matches(ScanFilter::Any(vec![])));
+  }
+}
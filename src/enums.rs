@@ -199,3 +199,39 @@ pub enum AdvFilterPolicy {
   /// only allow scan/connections from those on the white list.
   Both = BLE_HCI_ADV_FILT_BOTH as _,
 }
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, IntoPrimitive, TryFromPrimitive)]
+pub enum Phy {
+  /// LE 1M PHY
+  Le1M = BLE_HCI_LE_PHY_1M as _,
+  /// LE 2M PHY
+  Le2M = BLE_HCI_LE_PHY_2M as _,
+  /// LE Coded PHY
+  Coded = BLE_HCI_LE_PHY_CODED as _,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, IntoPrimitive, TryFromPrimitive)]
+pub enum AdvDataStatus {
+  /// The advertising data is complete.
+  Complete = BLE_HCI_ADV_DATA_STATUS_COMPLETE as _,
+  /// More advertising data is to come.
+  Incomplete = BLE_HCI_ADV_DATA_STATUS_INCOMPLETE as _,
+  /// The advertising data was truncated by the controller.
+  Truncated = BLE_HCI_ADV_DATA_STATUS_TRUNCATED as _,
+}
+
+/// How a client recovers when an attribute operation fails with insufficient authentication
+/// or encryption.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum SecurityRetry {
+  /// Return the error.
+  #[default]
+  Disabled,
+  /// Encrypt the link with the keys of an existing bond and retry once.
+  /// The error is returned if the peer is not bonded.
+  Encrypt,
+  /// Secure the connection, pairing if the peer is not bonded, and retry once.
+  Pair,
+}
@@ -26,8 +26,26 @@ mod ble_error;
 pub(crate) use self::ble_error::ble;
 pub use self::ble_error::BLEError;
 
+mod ble_error_kind;
+pub use self::ble_error_kind::*;
+
+mod bond_store;
+pub use self::bond_store::*;
+
 mod ble_security;
-pub use self::ble_security::BLESecurity;
+pub use self::ble_security::{BLESecurity, ScOobData};
+
+mod own_address;
+pub use self::own_address::PrivateAddressKind;
+
+mod pairing_agent;
+pub use self::pairing_agent::{OobData, PairingAgent};
+
+mod pairing_window;
+pub use self::pairing_window::PairingWindowEvent;
+
+mod signed_write;
+pub use self::signed_write::SIGNATURE_LEN;
 
 pub mod enums;
 
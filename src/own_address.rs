@@ -0,0 +1,143 @@
+use core::{
+  ffi::{c_int, c_void, CStr},
+  time::Duration,
+};
+use esp_idf_sys::*;
+
+use crate::{
+  ble,
+  utilities::{mutex::Mutex, nvs_load, nvs_open_namespace, nvs_store},
+  BLEAddress, BLEAddressKind, BLEAddressType, BLEError,
+};
+
+extern "C" {
+  fn ble_hs_pvcy_our_irk(out_irk: *mut *const u8) -> c_int;
+}
+
+const NAMESPACE: &str = "ble_addr";
+const KEY: &CStr = c"static";
+
+/// Kind of the private addresses of
+/// [`BLEDevice::start_address_rotation`](crate::BLEDevice::start_address_rotation).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrivateAddressKind {
+  NonResolvable,
+  /// Resolvable with the identity resolving key distributed to bonded peers.
+  Resolvable,
+}
+
+struct Rotation {
+  timer: esp_timer_handle_t,
+  kind: PrivateAddressKind,
+}
+
+// The timer handle is only used under the mutex.
+unsafe impl Send for Rotation {}
+
+static ROTATION: Mutex<Option<Rotation>> = Mutex::new(None);
+
+/// The static random address stored in NVS, generated and stored on first use.
+pub(crate) fn persisted_static_address() -> Result<BLEAddress, BLEError> {
+  let handle = nvs_open_namespace(NAMESPACE)?;
+  let ret = load_or_generate(handle);
+  unsafe { nvs_close(handle) };
+  ret
+}
+
+fn load_or_generate(handle: nvs_handle_t) -> Result<BLEAddress, BLEError> {
+  if let Some(data) = nvs_load(handle, KEY)? {
+    if let Ok(val) = <[u8; 6]>::try_from(data.as_slice()) {
+      let addr = BLEAddress::from_le_bytes(val, BLEAddressType::Random);
+      if addr.kind() == BLEAddressKind::RandomStatic {
+        return Ok(addr);
+      }
+    }
+    ::log::warn!("discarding invalid static random address");
+  }
+
+  let addr = BLEAddress::random_static();
+  nvs_store(handle, KEY, &addr.value.val)?;
+  Ok(addr)
+}
+
+pub(crate) fn set_random_address(addr: &BLEAddress) -> Result<(), BLEError> {
+  unsafe { ble!(ble_hs_id_set_rnd(addr.value.val.as_ptr())) }
+}
+
+fn generate(kind: PrivateAddressKind) -> Result<BLEAddress, BLEError> {
+  match kind {
+    PrivateAddressKind::NonResolvable => Ok(BLEAddress::random_non_resolvable()),
+    PrivateAddressKind::Resolvable => {
+      let mut irk = core::ptr::null();
+      unsafe {
+        ble!(ble_hs_pvcy_our_irk(&mut irk))?;
+        Ok(BLEAddress::random_resolvable(&*(irk as *const [u8; 16])))
+      }
+    }
+  }
+}
+
+/// Set a new private address now and every `interval`.
+pub(crate) fn start_rotation(
+  kind: PrivateAddressKind,
+  interval: Duration,
+) -> Result<BLEAddress, BLEError> {
+  stop_rotation();
+  let addr = generate(kind)?;
+  set_random_address(&addr)?;
+
+  let args = esp_timer_create_args_t {
+    callback: Some(on_rotate),
+    arg: core::ptr::null_mut(),
+    dispatch_method: esp_timer_dispatch_t_ESP_TIMER_TASK,
+    name: c"ble_addr_rotation".as_ptr(),
+    skip_unhandled_events: true,
+  };
+  let mut timer = core::ptr::null_mut();
+  unsafe {
+    if esp_timer_create(&args, &mut timer) != ESP_OK as _ {
+      return Err(BLEError::convert(BLE_HS_ENOMEM).unwrap_err());
+    }
+    if esp_timer_start_periodic(timer, interval.as_micros() as _) != ESP_OK as _ {
+      esp_timer_delete(timer);
+      return Err(BLEError::convert(BLE_HS_EINVAL).unwrap_err());
+    }
+  }
+
+  *ROTATION.lock() = Some(Rotation { timer, kind });
+  Ok(addr)
+}
+
+pub(crate) fn stop_rotation() {
+  if let Some(rotation) = ROTATION.lock().take() {
+    unsafe {
+      esp_timer_stop(rotation.timer);
+      esp_timer_delete(rotation.timer);
+    }
+  }
+}
+
+extern "C" fn on_rotate(_: *mut c_void) {
+  let Some(kind) = ROTATION.lock().as_ref().map(|x| x.kind) else {
+    return;
+  };
+  if let Err(err) = generate(kind).and_then(|addr| rotate_to(&addr)) {
+    ::log::warn!("failed to rotate the random address: {:?}", err);
+  }
+}
+
+/// Set the random address, pausing the advertising since the controller does not accept a
+/// new address while advertising.
+fn rotate_to(addr: &BLEAddress) -> Result<(), BLEError> {
+  #[cfg(not(esp_idf_bt_nimble_ext_adv))]
+  {
+    let mut advertising = crate::BLEDevice::take().get_advertising().lock();
+    if advertising.is_advertising() {
+      advertising.stop()?;
+      let ret = set_random_address(addr);
+      advertising.restart()?;
+      return ret;
+    }
+  }
+  set_random_address(addr)
+}
@@ -0,0 +1,279 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+  ffi::c_void,
+  future::Future,
+  pin::Pin,
+  sync::atomic::{AtomicBool, Ordering},
+};
+use esp_idf_sys::*;
+
+use crate::{
+  utilities::{ble_gap_conn_find, mutex::Mutex},
+  BLEConnDesc, BLEDevice, BLEError, Channel, ScOobData,
+};
+
+const TASK_STACK_SIZE: u32 = 4096;
+const TASK_PRIORITY: u32 = 5;
+
+/// OOB data returned by [`PairingAgent::oob`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OobData {
+  /// Temporary key of legacy OOB pairing.
+  Tk([u8; 16]),
+  /// LE Secure Connections OOB data received from the peer.
+  PeerSc(ScOobData),
+}
+
+/// Answers the pairing interactions of the security manager, for the server and the clients
+/// alike. Set with [`BLESecurity::set_pairing_agent`](crate::BLESecurity::set_pairing_agent).
+///
+/// The methods run one at a time in a dedicated task and can wait for the user; the answer is
+/// injected when the method returns. The peer aborts the pairing if it gets no answer within
+/// 30 seconds.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use esp32_nimble::{BLEConnDesc, BLEDevice, BLEError, PairingAgent};
+/// # mod ui {
+/// #   pub async fn confirm(_: String) -> bool { true }
+/// # }
+/// struct Agent;
+///
+/// impl PairingAgent for Agent {
+///   async fn confirm_numeric(&self, desc: &BLEConnDesc, number: u32) -> bool {
+///     ui::confirm(format!("Pair with {}? Code: {number:06}", desc.address())).await
+///   }
+/// }
+///
+/// # fn run() -> Result<(), BLEError> {
+/// BLEDevice::take().security().set_pairing_agent(Agent)?;
+/// # Ok(())
+/// # }
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait PairingAgent: Send + Sync {
+  /// Display `passkey`, to be entered on the peer. The passkey is the one set by
+  /// [`BLESecurity::set_passkey`](crate::BLESecurity::set_passkey), or a random one if none is
+  /// set, and is injected before this method is called.
+  async fn display_passkey(&self, _desc: &BLEConnDesc, _passkey: u32) {}
+
+  /// Get the passkey displayed by the peer, `None` to reject the pairing.
+  async fn request_passkey(&self, _desc: &BLEConnDesc) -> Option<u32> {
+    None
+  }
+
+  /// Whether `number` matches the number displayed by the peer.
+  async fn confirm_numeric(&self, _desc: &BLEConnDesc, _number: u32) -> bool {
+    false
+  }
+
+  /// Get the OOB data of the peer, `None` to use the data already set on
+  /// [`BLESecurity`](crate::BLESecurity).
+  async fn oob(&self, _desc: &BLEConnDesc, _secure_connections: bool) -> Option<OobData> {
+    None
+  }
+}
+
+/// Object-safe form of [`PairingAgent`].
+trait DynPairingAgent: Send + Sync {
+  fn handle(&self, request: Request) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+}
+
+impl<T: PairingAgent> DynPairingAgent for T {
+  fn handle(&self, request: Request) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+    Box::pin(handle(self, request))
+  }
+}
+
+#[derive(Copy, Clone)]
+struct Request {
+  conn_handle: u16,
+  action: u8,
+  numcmp: u32,
+}
+
+static AGENT: Mutex<Option<Arc<dyn DynPairingAgent>>> = Mutex::new(None);
+static REQUESTS: Channel<Request, 4> = Channel::new();
+static TASK_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Set the agent, starting its task on first use.
+pub(crate) fn set_agent(agent: impl PairingAgent + 'static) -> Result<(), BLEError> {
+  if !TASK_STARTED.swap(true, Ordering::AcqRel) {
+    let rc = unsafe {
+      xTaskCreatePinnedToCore(
+        Some(agent_task),
+        c"ble_pairing".as_ptr(),
+        TASK_STACK_SIZE,
+        core::ptr::null_mut(),
+        TASK_PRIORITY,
+        core::ptr::null_mut(),
+        tskNO_AFFINITY as _,
+      )
+    };
+    if rc != 1 {
+      TASK_STARTED.store(false, Ordering::Release);
+      return BLEError::convert(BLE_HS_ENOMEM);
+    }
+  }
+
+  *AGENT.lock() = Some(Arc::new(agent));
+  Ok(())
+}
+
+/// Remove the agent; the pending requests are rejected.
+pub(crate) fn clear_agent() {
+  *AGENT.lock() = None;
+}
+
+extern "C" fn agent_task(_: *mut c_void) {
+  esp_idf_hal::task::block_on(async {
+    loop {
+      let request = REQUESTS.receive().await;
+      // Cloned out of the lock, so that the agent can be replaced while it runs.
+      let agent = AGENT.lock().clone();
+      match agent {
+        Some(agent) => agent.handle(request).await,
+        None => terminate(request.conn_handle),
+      }
+    }
+  })
+}
+
+async fn handle<A: PairingAgent + ?Sized>(agent: &A, request: Request) {
+  let Ok(desc) = ble_gap_conn_find(request.conn_handle) else {
+    return;
+  };
+  let mut pkey = ble_sm_io {
+    action: request.action,
+    ..Default::default()
+  };
+
+  match request.action as _ {
+    BLE_SM_IOACT_DISP => {
+      let passkey = match BLEDevice::take().security().get_passkey() {
+        0 => unsafe { esp_random() % 1_000_000 },
+        passkey => passkey,
+      };
+      pkey.__bindgen_anon_1.passkey = passkey;
+      let rc = unsafe { ble_sm_inject_io(request.conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_DISP; ble_sm_inject_io result: {}", rc);
+      agent.display_passkey(&desc, passkey).await;
+    }
+    BLE_SM_IOACT_INPUT => {
+      let Some(passkey) = agent.request_passkey(&desc).await else {
+        terminate(request.conn_handle);
+        return;
+      };
+      pkey.__bindgen_anon_1.passkey = passkey;
+      let rc = unsafe { ble_sm_inject_io(request.conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_INPUT; ble_sm_inject_io result: {}", rc);
+    }
+    BLE_SM_IOACT_NUMCMP => {
+      pkey.__bindgen_anon_1.numcmp_accept = agent.confirm_numeric(&desc, request.numcmp).await as _;
+      let rc = unsafe { ble_sm_inject_io(request.conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_NUMCMP; ble_sm_inject_io result: {}", rc);
+    }
+    BLE_SM_IOACT_OOB | BLE_SM_IOACT_OOB_SC => {
+      let secure_connections = request.action == BLE_SM_IOACT_OOB_SC as _;
+      let data = agent.oob(&desc, secure_connections).await;
+      let security = BLEDevice::take().security();
+      match data {
+        Some(OobData::Tk(tk)) => {
+          security.set_oob_tk(Some(tk));
+        }
+        Some(OobData::PeerSc(data)) => {
+          security.set_peer_sc_oob_data(Some(data));
+        }
+        None => {}
+      }
+      let rc = security.inject_oob(request.conn_handle, request.action);
+      ::log::debug!("BLE_SM_IOACT_OOB; ble_sm_inject_io result: {}", rc);
+    }
+    action => {
+      ::log::warn!("unsupported passkey action: {}", action);
+      terminate(request.conn_handle);
+    }
+  }
+}
+
+fn terminate(conn_handle: u16) {
+  unsafe { ble_gap_terminate(conn_handle, ble_error_codes_BLE_ERR_AUTH_FAIL as _) };
+}
+
+/// Handle `BLE_GAP_EVENT_PASSKEY_ACTION` for the server and the clients: forward it to the
+/// pairing agent if one is set, or answer with the synchronous callbacks otherwise.
+pub(crate) fn on_passkey_action(
+  conn_handle: u16,
+  params: &ble_gap_passkey_params,
+  on_passkey_display: Option<&(dyn Fn() -> u32 + Send + Sync)>,
+  on_passkey_request: Option<&(dyn Fn() -> u32 + Send + Sync)>,
+  on_confirm_pin: Option<&(dyn Fn(u32) -> bool + Send + Sync)>,
+) {
+  if params.action == BLE_SM_IOACT_NONE as _ {
+    ::log::debug!("BLE_SM_IOACT_NONE; No passkey action required");
+    return;
+  }
+
+  if crate::pairing_window::refuse_pairing(conn_handle) {
+    return;
+  }
+
+  if AGENT.lock().is_some() {
+    let request = Request {
+      conn_handle,
+      action: params.action,
+      numcmp: params.numcmp,
+    };
+    if REQUESTS.try_send(request).is_err() {
+      ::log::warn!("too many pending pairing requests");
+      terminate(conn_handle);
+    }
+    return;
+  }
+
+  let mut pkey = ble_sm_io {
+    action: params.action,
+    ..Default::default()
+  };
+  match params.action as _ {
+    BLE_SM_IOACT_DISP => {
+      pkey.__bindgen_anon_1.passkey = if let Some(callback) = on_passkey_display {
+        callback()
+      } else {
+        BLEDevice::take().security().get_passkey()
+      };
+
+      let rc = unsafe { ble_sm_inject_io(conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_DISP; ble_sm_inject_io result: {}", rc);
+    }
+    BLE_SM_IOACT_NUMCMP => {
+      if let Some(callback) = on_confirm_pin {
+        pkey.__bindgen_anon_1.numcmp_accept = callback(params.numcmp) as _;
+      } else {
+        ::log::warn!("on_confirm_pin is not set");
+      }
+      let rc = unsafe { ble_sm_inject_io(conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_NUMCMP; ble_sm_inject_io result: {}", rc);
+    }
+    BLE_SM_IOACT_INPUT => {
+      if let Some(callback) = on_passkey_request {
+        pkey.__bindgen_anon_1.passkey = callback();
+      } else {
+        ::log::warn!("on_passkey_request is not set");
+      }
+      let rc = unsafe { ble_sm_inject_io(conn_handle, &mut pkey) };
+      ::log::debug!("BLE_SM_IOACT_INPUT; ble_sm_inject_io result: {}", rc);
+    }
+    BLE_SM_IOACT_OOB | BLE_SM_IOACT_OOB_SC => {
+      let rc = BLEDevice::take()
+        .security()
+        .inject_oob(conn_handle, params.action);
+      ::log::debug!("BLE_SM_IOACT_OOB; ble_sm_inject_io result: {}", rc);
+    }
+    action => {
+      ::log::warn!("unsupported passkey action: {}", action);
+      terminate(conn_handle);
+    }
+  }
+}
@@ -0,0 +1,193 @@
+use alloc::boxed::Box;
+use core::{ffi::c_void, time::Duration};
+use esp_idf_sys::*;
+
+use crate::{ble, utilities::mutex::Mutex, BLEError};
+
+/// Event emitted when the pairing window of [`BLESecurity::pairing_window`](crate::BLESecurity::pairing_window)
+/// opens or closes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PairingWindowEvent {
+  Opened,
+  Closed,
+}
+
+struct PairingWindow {
+  duration: Duration,
+  open: bool,
+  /// Created when the window is opened for the first time.
+  timer: esp_timer_handle_t,
+  /// Whether bonding is enabled by `BLESecurity::set_auth`, applied while the window is open.
+  bonding: bool,
+  on_event: Option<Box<dyn FnMut(PairingWindowEvent) + Send + Sync>>,
+}
+
+// The timer handle is only used under the mutex.
+unsafe impl Send for PairingWindow {}
+
+static WINDOW: Mutex<PairingWindow> = Mutex::new(PairingWindow {
+  duration: Duration::ZERO,
+  open: false,
+  timer: core::ptr::null_mut(),
+  bonding: false,
+  on_event: None,
+});
+
+/// Enable the pairing window, initially closed.
+pub(crate) fn enable(duration: Duration) {
+  {
+    let mut window = WINDOW.lock();
+    window.duration = duration;
+    window.open = false;
+  }
+  apply();
+}
+
+pub(crate) fn set_on_event(callback: impl FnMut(PairingWindowEvent) + Send + Sync + 'static) {
+  WINDOW.lock().on_event = Some(Box::new(callback));
+}
+
+/// Whether bonding is requested; it takes effect only while the window is open.
+pub(crate) fn set_bonding(bonding: bool) {
+  WINDOW.lock().bonding = bonding;
+  apply();
+}
+
+/// `None` if the pairing window is not enabled, whether it is open otherwise.
+pub(crate) fn state() -> Option<bool> {
+  let window = WINDOW.lock();
+  (!window.duration.is_zero()).then_some(window.open)
+}
+
+/// Whether peers that are not bonded are accepted.
+pub(crate) fn accepts_new_peers() -> bool {
+  state() != Some(false)
+}
+
+/// Terminate the connection if the window is closed, before the pairing exchanges keys.
+///
+/// Returns whether the pairing was refused.
+pub(crate) fn refuse_pairing(conn_handle: u16) -> bool {
+  if accepts_new_peers() {
+    return false;
+  }
+  ::log::info!("pairing window closed, refusing to pair on {}", conn_handle);
+  unsafe { ble_gap_terminate(conn_handle, ble_error_codes_BLE_ERR_AUTH_FAIL as _) };
+  true
+}
+
+/// Handle `BLE_GAP_EVENT_REPEAT_PAIRING` for the server and the clients: keep the old bond
+/// while the window is closed, delete it and pair again otherwise.
+pub(crate) fn on_repeat_pairing(conn_handle: u16) -> i32 {
+  if !accepts_new_peers() {
+    return BLE_GAP_REPEAT_PAIRING_IGNORE as _;
+  }
+
+  let Ok(desc) = crate::utilities::ble_gap_conn_find(conn_handle) else {
+    return BLE_GAP_REPEAT_PAIRING_IGNORE as _;
+  };
+  unsafe { ble_store_util_delete_peer(&desc.0.peer_id_addr) };
+
+  // Return BLE_GAP_REPEAT_PAIRING_RETRY to indicate that the host should
+  // continue with the pairing operation.
+  BLE_GAP_REPEAT_PAIRING_RETRY as _
+}
+
+/// Set the white list to the bonded peers, for the advertising while the window is closed.
+///
+/// Returns `false` without touching the white list if no peer is bonded, since the host
+/// rejects an empty white list.
+pub(crate) fn set_bonded_white_list() -> Result<bool, BLEError> {
+  let bonded = crate::BLEDevice::take().bonded_addresses()?;
+  if bonded.is_empty() {
+    return Ok(false);
+  }
+  unsafe { ble!(ble_gap_wl_set(bonded.as_ptr() as _, bonded.len() as _))? };
+  Ok(true)
+}
+
+/// Open the window, or restart its timer if it is open.
+pub(crate) fn open() -> Result<(), BLEError> {
+  let opened = {
+    let mut window = WINDOW.lock();
+    if window.duration.is_zero() {
+      return BLEError::convert(BLE_HS_EINVAL);
+    }
+
+    if window.timer.is_null() {
+      let args = esp_timer_create_args_t {
+        callback: Some(on_expired),
+        arg: core::ptr::null_mut(),
+        dispatch_method: esp_timer_dispatch_t_ESP_TIMER_TASK,
+        name: c"ble_pairing".as_ptr(),
+        skip_unhandled_events: false,
+      };
+      if unsafe { esp_timer_create(&args, &mut window.timer) } != ESP_OK as _ {
+        return BLEError::convert(BLE_HS_ENOMEM);
+      }
+    }
+
+    unsafe {
+      esp_timer_stop(window.timer);
+      if esp_timer_start_once(window.timer, window.duration.as_micros() as _) != ESP_OK as _ {
+        return BLEError::convert(BLE_HS_ENOMEM);
+      }
+    }
+    !core::mem::replace(&mut window.open, true)
+  };
+
+  if opened {
+    apply();
+    emit(PairingWindowEvent::Opened);
+  }
+  Ok(())
+}
+
+pub(crate) fn close() {
+  let closed = {
+    let mut window = WINDOW.lock();
+    if !window.timer.is_null() {
+      unsafe { esp_timer_stop(window.timer) };
+    }
+    core::mem::replace(&mut window.open, false)
+  };
+
+  if closed {
+    apply();
+    emit(PairingWindowEvent::Closed);
+  }
+}
+
+extern "C" fn on_expired(_: *mut c_void) {
+  close();
+}
+
+/// Apply the window state to the bonding flag and to the advertising.
+///
+/// Called without holding the window lock, since the advertising reads the window state.
+fn apply() {
+  let (bonding, enabled, open) = {
+    let window = WINDOW.lock();
+    (window.bonding, !window.duration.is_zero(), window.open)
+  };
+  unsafe { ble_hs_cfg.set_sm_bonding((bonding && (open || !enabled)) as _) };
+
+  if enabled {
+    let mut advertising = crate::BLEDevice::take().get_advertising().lock();
+    if let Err(err) = advertising.refresh_pairing_window() {
+      ::log::warn!("failed to update the advertising: {:?}", err);
+    }
+  }
+}
+
+fn emit(event: PairingWindowEvent) {
+  // Taken out of the lock, so that the callback can open or close the window.
+  let callback = WINDOW.lock().on_event.take();
+  if let Some(mut callback) = callback {
+    callback(event);
+    let mut window = WINDOW.lock();
+    if window.on_event.is_none() {
+      window.on_event = Some(callback);
+    }
+  }
+}
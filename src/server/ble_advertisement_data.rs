@@ -1,40 +1,51 @@
-use crate::{enums::PowerType, utilities::BleUuid, BLEDevice};
+use crate::{
+  enums::{AdvFlag, PowerType},
+  utilities::{AdStructure, BleUuid},
+  BLEAddress, BLEDevice, BLEError,
+};
 use alloc::{string::String, vec::Vec};
+use bstr::BString;
 
 pub struct BLEAdvertisementData {
   // 0x01 - Flags
   pub(crate) flags: u8,
   // 0x02,0x03 - 16-bit service class UUIDs
-  service_uuids_16: Vec<esp_idf_sys::ble_uuid16_t>,
+  service_uuids_16: Vec<u16>,
   uuids16_is_complete: bool,
   // 0x04,0x05 - 32-bit service class UUIDs
-  service_uuids_32: Vec<esp_idf_sys::ble_uuid32_t>,
+  service_uuids_32: Vec<u32>,
   uuids32_is_complete: bool,
   // 0x06,0x07 - 128-bit service class UUIDs.
-  service_uuids_128: Vec<esp_idf_sys::ble_uuid128_t>,
+  service_uuids_128: Vec<[u8; 16]>,
   uuids128_is_complete: bool,
   // 0x08,0x09 - Local name
   name: String,
-  name_is_complete: bool,
   // 0x0a - Tx power level
   tx_pwr_lvl_is_present: bool,
-  // Not Implemented: 0x0d - Slave connection interval range
-
+  // 0x12 - Peripheral connection interval range
+  peripheral_itvl_range: Option<(u16, u16)>,
+  // 0x14,0x15,0x1f - Service solicitation UUIDs
+  solicitation_uuids: Vec<BleUuid>,
   // 0x16 - Service data - 16-bit UUID
-  svc_data_uuid16: Vec<u8>,
-  // Not Implemented: 0x17 - Public target address
-
+  svc_data_uuid16: Option<AdStructure>,
+  // 0x17 - Public target address
+  public_target_address: Vec<BLEAddress>,
   // 0x19 - Appearance
   appearance: Option<u16>,
-  // Not Implemented: 0x1a - Advertising interval
-
+  // 0x1a - Advertising interval
+  adv_itvl: Option<u16>,
+  // 0x1c - LE role
+  le_role: Option<u8>,
   // 0x20 - Service data - 32-bit UUID
-  svc_data_uuid32: Vec<u8>,
+  svc_data_uuid32: Option<AdStructure>,
   // 0x21 - Service data - 128-bit UUID
-  svc_data_uuid128: Vec<u8>,
-  // Not Implemented: 0x24 - URI
+  svc_data_uuid128: Option<AdStructure>,
+  // 0x24 - URI
+  uri: Vec<u8>,
   // 0xff - Manufacturer specific data.
   mfg_data: Vec<u8>,
+  // Any other AD structure
+  others: Vec<AdStructure>,
 }
 
 impl BLEAdvertisementData {
@@ -48,13 +59,19 @@ impl BLEAdvertisementData {
       service_uuids_128: Vec::new(),
       uuids128_is_complete: true,
       name: String::new(),
-      name_is_complete: true,
       tx_pwr_lvl_is_present: false,
-      svc_data_uuid16: Vec::new(),
+      peripheral_itvl_range: None,
+      solicitation_uuids: Vec::new(),
+      svc_data_uuid16: None,
+      public_target_address: Vec::new(),
       appearance: None,
-      svc_data_uuid32: Vec::new(),
-      svc_data_uuid128: Vec::new(),
+      adv_itvl: None,
+      le_role: None,
+      svc_data_uuid32: None,
+      svc_data_uuid128: None,
+      uri: Vec::new(),
       mfg_data: Vec::new(),
+      others: Vec::new(),
     }
   }
 
@@ -67,16 +84,15 @@ impl BLEAdvertisementData {
   }
 
   pub fn add_service_uuid(&mut self, uuid: BleUuid) -> &mut Self {
-    let x = esp_idf_sys::ble_uuid_any_t::from(uuid);
     match uuid {
-      BleUuid::Uuid16(_) => {
-        self.service_uuids_16.push(unsafe { x.u16_ });
+      BleUuid::Uuid16(uuid) => {
+        self.service_uuids_16.push(uuid);
       }
-      BleUuid::Uuid32(_) => {
-        self.service_uuids_32.push(unsafe { x.u32_ });
+      BleUuid::Uuid32(uuid) => {
+        self.service_uuids_32.push(uuid);
       }
-      BleUuid::Uuid128(_) => {
-        self.service_uuids_128.push(unsafe { x.u128_ });
+      BleUuid::Uuid128(uuid) => {
+        self.service_uuids_128.push(uuid);
       }
     }
 
@@ -84,22 +100,11 @@ impl BLEAdvertisementData {
   }
 
   pub fn service_data(&mut self, uuid: BleUuid, data: &[u8]) {
+    let service_data = Some(AdStructure::service_data(uuid, data));
     match uuid {
-      BleUuid::Uuid16(uuid) => {
-        self.svc_data_uuid16.clear();
-        self.svc_data_uuid16.extend_from_slice(&uuid.to_ne_bytes());
-        self.svc_data_uuid16.extend_from_slice(data);
-      }
-      BleUuid::Uuid32(uuid) => {
-        self.svc_data_uuid32.clear();
-        self.svc_data_uuid32.extend_from_slice(&uuid.to_ne_bytes());
-        self.svc_data_uuid32.extend_from_slice(data);
-      }
-      BleUuid::Uuid128(uuid) => {
-        self.svc_data_uuid128.clear();
-        self.svc_data_uuid128.extend_from_slice(&uuid);
-        self.svc_data_uuid128.extend_from_slice(data);
-      }
+      BleUuid::Uuid16(_) => self.svc_data_uuid16 = service_data,
+      BleUuid::Uuid32(_) => self.svc_data_uuid32 = service_data,
+      BleUuid::Uuid128(_) => self.svc_data_uuid128 = service_data,
     }
   }
 
@@ -124,117 +129,194 @@ impl BLEAdvertisementData {
     self
   }
 
-  pub(crate) fn payload_len(&self) -> usize {
-    let mut payload_len: usize = if self.flags > 0 { 2 + 1 } else { 0 };
+  /// Add a service UUID to the list of service solicitation UUIDs.
+  pub fn add_solicitation_uuid(&mut self, uuid: BleUuid) -> &mut Self {
+    self.solicitation_uuids.push(uuid);
 
-    if !self.service_uuids_16.is_empty() {
-      payload_len += 2 + 2 * self.service_uuids_16.len();
-    }
-    if !self.service_uuids_32.is_empty() {
-      payload_len += 2 + 4 * self.service_uuids_32.len();
-    }
-    if !self.service_uuids_128.is_empty() {
-      payload_len += 2 + 16 * self.service_uuids_128.len();
-    }
+    self
+  }
 
-    if !self.name.is_empty() {
-      payload_len += 2 + self.name.len();
-    }
+  /// Set the preferred connection interval range of the peripheral.
+  ///
+  /// * `min_interval`: The minimum connection interval in 1.25ms units.
+  /// * `max_interval`: The maximum connection interval in 1.25ms units.
+  pub fn peripheral_conn_interval_range(
+    &mut self,
+    min_interval: u16,
+    max_interval: u16,
+  ) -> &mut Self {
+    self.peripheral_itvl_range = Some((min_interval, max_interval));
 
-    if self.tx_pwr_lvl_is_present {
-      payload_len += 2 + (esp_idf_sys::BLE_HS_ADV_TX_PWR_LVL_LEN as usize);
-    }
+    self
+  }
 
-    if !self.svc_data_uuid16.is_empty() {
-      payload_len += 2 + self.svc_data_uuid16.len();
-    }
+  /// Add the public address of a device this advertisement is intended for.
+  pub fn add_public_target_address(&mut self, addr: BLEAddress) -> &mut Self {
+    self.public_target_address.push(addr);
 
-    if !self.svc_data_uuid32.is_empty() {
-      payload_len += 2 + self.svc_data_uuid32.len();
-    }
+    self
+  }
 
-    if !self.svc_data_uuid128.is_empty() {
-      payload_len += 2 + self.svc_data_uuid128.len();
-    }
+  /// Add the advertising interval to the advertisement packet.
+  ///
+  /// * `interval`: advertising interval in 0.625ms units.
+  pub fn advertising_interval(&mut self, interval: u16) -> &mut Self {
+    self.adv_itvl = Some(interval);
 
-    if self.appearance.is_some() {
-      payload_len += 2 + (esp_idf_sys::BLE_HS_ADV_APPEARANCE_LEN as usize);
-    }
+    self
+  }
 
-    if !self.mfg_data.is_empty() {
-      payload_len += 2 + self.mfg_data.len();
-    }
+  /// Set the supported and preferred LE roles. (see [`AdStructure::LeRole`])
+  pub fn le_role(&mut self, role: u8) -> &mut Self {
+    self.le_role = Some(role);
 
-    // if self.uri_len > 0 {
-    //   payload_len += 2 + adv_data.uri_len;
-    // }
+    self
+  }
 
-    // if !adv_data.slave_itvl_range.is_null() {
-    //   payload_len += 2 + (esp_idf_sys::BLE_HS_ADV_SLAVE_ITVL_RANGE_LEN as _);
-    // }
+  /// Set the URI. The first byte is the scheme name string code.
+  pub fn uri(&mut self, uri: &[u8]) -> &mut Self {
+    self.uri.clear();
+    self.uri.extend_from_slice(uri);
 
-    payload_len
+    self
+  }
+
+  /// Add an arbitrary AD structure to the advertisement packet.
+  pub fn add_ad_structure(&mut self, ad: AdStructure) -> &mut Self {
+    self.others.push(ad);
+
+    self
   }
 
-  pub(crate) fn as_ble_hs_adv_fields(&self) -> esp_idf_sys::ble_hs_adv_fields {
-    let mut ret = esp_idf_sys::ble_hs_adv_fields {
-      flags: self.flags,
-      ..Default::default()
-    };
+  /// Get the AD structures of this advertisement, in the order they are advertised.
+  pub fn ad_structures(&self) -> Vec<AdStructure> {
+    let mut ret = Vec::new();
+
+    if self.flags > 0 {
+      ret.push(AdStructure::Flags(AdvFlag::from_bits_retain(self.flags)));
+    }
 
     if !self.service_uuids_16.is_empty() {
-      ret.set_uuids16_is_complete(self.uuids16_is_complete as _);
-      ret.uuids16 = self.service_uuids_16.as_ptr();
-      ret.num_uuids16 = self.service_uuids_16.len() as _;
+      ret.push(AdStructure::ServiceUuids16 {
+        uuids: self.service_uuids_16.clone(),
+        complete: self.uuids16_is_complete,
+      });
     }
     if !self.service_uuids_32.is_empty() {
-      ret.set_uuids32_is_complete(self.uuids32_is_complete as _);
-      ret.uuids32 = self.service_uuids_32.as_ptr();
-      ret.num_uuids32 = self.service_uuids_32.len() as _;
+      ret.push(AdStructure::ServiceUuids32 {
+        uuids: self.service_uuids_32.clone(),
+        complete: self.uuids32_is_complete,
+      });
     }
     if !self.service_uuids_128.is_empty() {
-      ret.set_uuids128_is_complete(self.uuids128_is_complete as _);
-      ret.uuids128 = self.service_uuids_128.as_ptr();
-      ret.num_uuids128 = self.service_uuids_128.len() as _;
+      ret.push(AdStructure::ServiceUuids128 {
+        uuids: self.service_uuids_128.clone(),
+        complete: self.uuids128_is_complete,
+      });
     }
 
     if !self.name.is_empty() {
-      ret.name = self.name.as_ptr().cast();
-      ret.name_len = self.name.len() as _;
-      ret.set_name_is_complete(self.name_is_complete as _);
+      ret.push(AdStructure::CompleteLocalName(BString::from(
+        self.name.as_str(),
+      )));
     }
 
     if self.tx_pwr_lvl_is_present {
-      ret.set_tx_pwr_lvl_is_present(1);
       let ble_device = BLEDevice::take();
-      ret.tx_pwr_lvl = ble_device.get_power(PowerType::Advertising).to_dbm();
+      ret.push(AdStructure::TxPowerLevel(
+        ble_device.get_power(PowerType::Advertising).to_dbm(),
+      ));
+    }
+
+    if let Some((min, max)) = self.peripheral_itvl_range {
+      ret.push(AdStructure::PeripheralConnectionIntervalRange { min, max });
     }
 
-    if !self.svc_data_uuid16.is_empty() {
-      ret.svc_data_uuid16 = self.svc_data_uuid16.as_ptr();
-      ret.svc_data_uuid16_len = self.svc_data_uuid16.len() as _;
+    for size in [2, 4, 16] {
+      let uuids: Vec<BleUuid> = self
+        .solicitation_uuids
+        .iter()
+        .copied()
+        .filter(|x| uuid_size(x) == size)
+        .collect();
+      if let Some(x) = solicitation_uuids(&uuids) {
+        ret.push(x);
+      }
     }
 
-    if !self.svc_data_uuid32.is_empty() {
-      ret.svc_data_uuid32 = self.svc_data_uuid32.as_ptr();
-      ret.svc_data_uuid32_len = self.svc_data_uuid32.len() as _;
+    if let Some(x) = &self.svc_data_uuid16 {
+      ret.push(x.clone());
     }
 
-    if !self.svc_data_uuid128.is_empty() {
-      ret.svc_data_uuid128 = self.svc_data_uuid128.as_ptr();
-      ret.svc_data_uuid128_len = self.svc_data_uuid128.len() as _;
+    if !self.public_target_address.is_empty() {
+      ret.push(AdStructure::PublicTargetAddress(
+        self.public_target_address.clone(),
+      ));
     }
 
     if let Some(appearance) = self.appearance {
-      ret.set_appearance_is_present(1);
-      ret.appearance = appearance;
+      ret.push(AdStructure::Appearance(appearance));
+    }
+
+    if let Some(itvl) = self.adv_itvl {
+      ret.push(AdStructure::AdvertisingInterval(itvl));
     }
 
+    if let Some(role) = self.le_role {
+      ret.push(AdStructure::LeRole(role));
+    }
+
+    if let Some(x) = &self.svc_data_uuid32 {
+      ret.push(x.clone());
+    }
+
+    if let Some(x) = &self.svc_data_uuid128 {
+      ret.push(x.clone());
+    }
+
+    if !self.uri.is_empty() {
+      ret.push(AdStructure::Uri(self.uri.clone()));
+    }
+
+    ret.extend(self.others.iter().cloned());
+
     if !self.mfg_data.is_empty() {
-      ret.mfg_data = self.mfg_data.as_ptr();
-      ret.mfg_data_len = self.mfg_data.len() as _;
+      ret.push(AdStructure::ManufacturerSpecificData(self.mfg_data.clone()));
     }
 
     ret
   }
+
+  /// Get the encoded advertisement payload.
+  ///
+  /// Fails with `BLE_HS_EMSGSIZE` if a field does not fit in a legacy advertising packet
+  /// (more than [`AdStructure::MAX_LEGACY_DATA_LEN`] data bytes).
+  pub fn payload(&self) -> Result<Vec<u8>, BLEError> {
+    let structures = self.ad_structures();
+    if structures
+      .iter()
+      .any(|x| x.data_len() > AdStructure::MAX_LEGACY_DATA_LEN)
+    {
+      return Err(BLEError::convert(esp_idf_sys::BLE_HS_EMSGSIZE).unwrap_err());
+    }
+    crate::utilities::encode_ad_structures(&structures)
+  }
+}
+
+fn uuid_size(uuid: &BleUuid) -> usize {
+  match uuid {
+    BleUuid::Uuid16(_) => 2,
+    BleUuid::Uuid32(_) => 4,
+    BleUuid::Uuid128(_) => 16,
+  }
+}
+
+fn solicitation_uuids(uuids: &[BleUuid]) -> Option<AdStructure> {
+  let ret = match AdStructure::service_uuids(uuids, true)? {
+    AdStructure::ServiceUuids16 { uuids, .. } => AdStructure::ServiceSolicitationUuids16(uuids),
+    AdStructure::ServiceUuids32 { uuids, .. } => AdStructure::ServiceSolicitationUuids32(uuids),
+    AdStructure::ServiceUuids128 { uuids, .. } => AdStructure::ServiceSolicitationUuids128(uuids),
+    _ => unreachable!(),
+  };
+  Some(ret)
 }
@@ -1,7 +1,12 @@
 use core::ffi::{c_int, c_void};
 
-use crate::{ble, enums::*, utilities::voidp_to_ref, BLEAdvertisementData, BLEError, BLEServer};
-use alloc::boxed::Box;
+use crate::{
+  ble,
+  enums::*,
+  utilities::{voidp_to_ref, AdStructure, AdvertisementPlan},
+  BLEAdvertisementData, BLEError, BLEServer,
+};
+use alloc::{boxed::Box, vec::Vec};
 use once_cell::sync::Lazy;
 
 const BLE_HS_ADV_MAX_SZ: usize = esp_idf_sys::BLE_HS_ADV_MAX_SZ as usize;
@@ -13,6 +18,9 @@ pub struct BLEAdvertising {
   adv_params: esp_idf_sys::ble_gap_adv_params,
   scan_response: bool,
   on_complete: Option<Box<dyn FnMut(c_int) + Send + Sync>>,
+  /// The last structures set, kept to update the flags when the pairing window changes.
+  ad_structures: Vec<AdStructure>,
+  duration_ms: i32,
 }
 
 impl BLEAdvertising {
@@ -22,6 +30,8 @@ impl BLEAdvertising {
       adv_params: esp_idf_sys::ble_gap_adv_params::default(),
       scan_response: true,
       on_complete: None,
+      ad_structures: Vec::new(),
+      duration_ms: BLE_HS_FOREVER,
     };
 
     ret.reset().unwrap();
@@ -40,6 +50,11 @@ impl BLEAdvertising {
     Ok(())
   }
 
+  /// Set the advertising data.
+  ///
+  /// Fields that do not fit in the advertising packet are moved to the scan response,
+  /// see [`BLEAdvertising::set_ad_structures`]. Fails with `BLE_HS_EMSGSIZE`, without
+  /// changing the advertising data, if some fields fit in neither.
   pub fn set_data(&mut self, data: &mut BLEAdvertisementData) -> Result<(), BLEError> {
     if self.adv_params.conn_mode == (ConnMode::Non as _) && !self.scan_response {
       data.flags = 0;
@@ -48,48 +63,64 @@ impl BLEAdvertising {
         (esp_idf_sys::BLE_HS_ADV_F_DISC_GEN | esp_idf_sys::BLE_HS_ADV_F_BREDR_UNSUP) as _;
     }
 
-    let mut adv_data = data.as_ble_hs_adv_fields();
-    let mut scan_data = esp_idf_sys::ble_hs_adv_fields::default();
-
-    let mut payload_len = data.payload_len();
-
-    if payload_len > BLE_HS_ADV_MAX_SZ {
-      if self.scan_response {
-        scan_data.name = adv_data.name;
-        scan_data.name_len = adv_data.name_len;
-        if scan_data.name_len > (BLE_HS_ADV_MAX_SZ - 2) as _ {
-          scan_data.name_len = (BLE_HS_ADV_MAX_SZ - 2) as _;
-          scan_data.set_name_is_complete(0);
-        } else {
-          scan_data.set_name_is_complete(1);
-        }
-
-        adv_data.name = core::ptr::null();
-        adv_data.name_len = 0;
-        adv_data.set_name_is_complete(0);
-      } else {
-        if adv_data.tx_pwr_lvl_is_present() > 0 {
-          adv_data.set_tx_pwr_lvl_is_present(0);
-          payload_len -= 2 + 1;
-        }
+    let fields = data.ad_structures();
+    if !self.plan(&fields).is_complete() {
+      return BLEError::convert(esp_idf_sys::BLE_HS_EMSGSIZE);
+    }
+    self.set_ad_structures(&fields)?;
+    Ok(())
+  }
 
-        if payload_len > BLE_HS_ADV_MAX_SZ {
-          adv_data.name_len -= (payload_len - BLE_HS_ADV_MAX_SZ) as u8;
-          adv_data.set_name_is_complete(0);
+  /// Split a list of AD structures, highest priority first, between the advertising packet
+  /// and the scan response as [`BLEAdvertising::set_ad_structures`] does, without setting
+  /// the advertising data.
+  pub fn plan(&self, fields: &[AdStructure]) -> AdvertisementPlan {
+    let mut structures = fields.to_vec();
+    if let Some(open) = crate::pairing_window::state() {
+      for field in &mut structures {
+        if let AdStructure::Flags(flags) = field {
+          flags.remove(AdvFlag::DiscGeneral | AdvFlag::DiscLimited);
+          flags.set(AdvFlag::DiscGeneral, open);
         }
       }
     }
 
-    unsafe {
-      if self.scan_response {
-        ble!(esp_idf_sys::ble_gap_adv_rsp_set_fields(&scan_data))?;
-      }
+    AdvertisementPlan::new(&structures, BLE_HS_ADV_MAX_SZ, self.scan_response)
+  }
+
+  /// Set the advertising data from a list of AD structures, highest priority first.
+  ///
+  /// The structures are split between the advertising packet and the scan response
+  /// (see [`AdvertisementPlan`]). The returned plan lists the fields that did not fit in
+  /// [`AdvertisementPlan::dropped`]; use [`BLEAdvertising::plan`] to check them beforehand.
+  ///
+  /// While the [pairing window](crate::BLESecurity::pairing_window) is enabled, the flags
+  /// are general discoverable only while it is open.
+  pub fn set_ad_structures(
+    &mut self,
+    fields: &[AdStructure],
+  ) -> Result<AdvertisementPlan, BLEError> {
+    let plan = self.plan(fields);
+    for field in &plan.dropped {
+      ::log::warn!("advertising field does not fit: {:?}", field);
+    }
 
-      ble!(esp_idf_sys::ble_gap_adv_set_fields(&adv_data))
+    if self.scan_response {
+      self.set_raw_scan_response_data(&plan.scan_response_payload()?)?;
     }
+
+    self.set_raw_data(&plan.adv_payload()?)?;
+    self.ad_structures = fields.to_vec();
+
+    Ok(plan)
   }
 
+  /// Set the advertising data.
+  ///
+  /// Unlike [`BLEAdvertising::set_ad_structures`], the flags are not updated when the
+  /// pairing window opens or closes.
   pub fn set_raw_data(&mut self, data: &[u8]) -> Result<(), BLEError> {
+    self.ad_structures.clear();
     let rc = unsafe { esp_idf_sys::ble_gap_adv_set_data(data.as_ptr(), data.len() as i32) } as u32;
 
     // convert BLE_ERR_INV_HCI_CMD_PARMS to BLE_HS_EINVAL
@@ -179,6 +210,23 @@ impl BLEAdvertising {
       self.adv_params.disc_mode = esp_idf_sys::BLE_GAP_DISC_MODE_GEN as _;
     }
 
+    // While the pairing window is closed, only bonded peers can connect, and the
+    // advertising is not connectable if there are none.
+    let mut adv_params = self.adv_params;
+    match crate::pairing_window::state() {
+      Some(false) => {
+        adv_params.disc_mode = esp_idf_sys::BLE_GAP_DISC_MODE_NON as _;
+        if crate::pairing_window::set_bonded_white_list()? {
+          adv_params.filter_policy = AdvFilterPolicy::Connect.into();
+        } else {
+          adv_params.conn_mode = ConnMode::Non as _;
+        }
+      }
+      Some(true) => adv_params.filter_policy = AdvFilterPolicy::None.into(),
+      None => {}
+    }
+    self.duration_ms = duration_ms;
+
     let handle_gap_event = if server.is_some() {
       BLEServer::handle_gap_event
     } else {
@@ -189,7 +237,7 @@ impl BLEAdvertising {
         crate::ble_device::OWN_ADDR_TYPE as _,
         core::ptr::null(),
         duration_ms,
-        &self.adv_params,
+        &adv_params,
         Some(handle_gap_event),
         self as *mut Self as _,
       ))?;
@@ -206,6 +254,25 @@ impl BLEAdvertising {
     unsafe { esp_idf_sys::ble_gap_adv_active() != 0 }
   }
 
+  /// Update the flags and restart the advertising after the pairing window opened or closed.
+  #[cfg(not(esp_idf_bt_nimble_ext_adv))]
+  pub(crate) fn refresh_pairing_window(&mut self) -> Result<(), BLEError> {
+    if !self.ad_structures.is_empty() {
+      self.set_ad_structures(&self.ad_structures.clone())?;
+    }
+    if self.is_advertising() {
+      self.stop()?;
+      self.restart()?;
+    }
+    Ok(())
+  }
+
+  /// Start advertising again with the duration of the last start.
+  #[cfg(not(esp_idf_bt_nimble_ext_adv))]
+  pub(crate) fn restart(&mut self) -> Result<(), BLEError> {
+    self.start_with_duration(self.duration_ms)
+  }
+
   pub fn on_complete(&mut self, callback: impl FnMut(c_int) + Send + Sync + 'static) -> &mut Self {
     self.on_complete = Some(Box::new(callback));
     self
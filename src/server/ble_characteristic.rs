@@ -33,6 +33,10 @@ bitflags! {
     const WRITE_ENC = esp_idf_sys::BLE_GATT_CHR_F_WRITE_ENC as _;
     const WRITE_AUTHEN = esp_idf_sys::BLE_GATT_CHR_F_WRITE_AUTHEN as _;
     const WRITE_AUTHOR = esp_idf_sys::BLE_GATT_CHR_F_WRITE_AUTHOR as _;
+    /// Values written on unencrypted connections must be signed with the CSRK of the peer,
+    /// see [`BLERemoteCharacteristic::write_signed`](crate::BLERemoteCharacteristic::write_signed).
+    /// The characteristic also needs `WRITE_NO_RSP`, which carries the signed values.
+    const AUTH_SIGN_WRITE = esp_idf_sys::BLE_GATT_CHR_F_AUTH_SIGN_WRITE as _;
     const BROADCAST = esp_idf_sys::BLE_GATT_CHR_F_BROADCAST as _;
     const NOTIFY = esp_idf_sys::BLE_GATT_CHR_F_NOTIFY as _;
     const INDICATE = esp_idf_sys::BLE_GATT_CHR_F_INDICATE as _;
@@ -335,16 +339,34 @@ impl BLECharacteristic {
           om = unsafe { (*om).om_next.sle_next };
         }
 
+        let Ok(desc) = crate::utilities::ble_gap_conn_find(conn_handle) else {
+          return esp_idf_sys::BLE_ATT_ERR_UNLIKELY as _;
+        };
+        let mut sign_counter = None;
+        if !desc.encrypted()
+          && characteristic
+            .properties
+            .contains(NimbleProperties::AUTH_SIGN_WRITE)
+        {
+          let Some((len, counter)) =
+            crate::signed_write::verify(&desc, characteristic.handle, &buf)
+          else {
+            return esp_idf_sys::BLE_ATT_ERR_INSUFFICIENT_AUTHEN as _;
+          };
+          buf.truncate(len);
+          sign_counter = Some(counter);
+        }
+
         let mut notify = false;
 
         unsafe {
           let characteristic = UnsafeCell::new(&mut characteristic);
           if let Some(callback) = &mut (*characteristic.get()).on_write {
-            let desc = crate::utilities::ble_gap_conn_find(conn_handle).unwrap();
             let mut arg = OnWriteArgs {
               current_data: (*characteristic.get()).value.value(),
               recv_data: &buf,
               desc: &desc,
+              sign_counter,
               reject: false,
               error_code: 0,
               notify: false,
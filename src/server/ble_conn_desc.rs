@@ -83,6 +83,27 @@ impl BLEConnDesc {
     }
     Ok(rssi)
   }
+
+  /// Transfer a periodic sync to the peer.
+  ///
+  /// * `service_data`: value identifying the periodic advertising train to the peer.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn transfer_periodic_sync(
+    &self,
+    sync: &crate::PeriodicSync,
+    service_data: u16,
+  ) -> Result<(), BLEError> {
+    sync.transfer(self.0.conn_handle, service_data)
+  }
+
+  /// Accept a periodic sync transferred by the peer.
+  #[cfg(esp_idf_bt_nimble_periodic_adv_sync_transfer)]
+  pub fn receive_periodic_sync(
+    &self,
+    params: crate::PeriodicSyncParams,
+  ) -> Result<crate::PeriodicSync, BLEError> {
+    crate::PeriodicSync::receive(self.0.conn_handle, params)
+  }
 }
 
 impl core::fmt::Debug for BLEConnDesc {
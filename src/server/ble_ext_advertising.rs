@@ -7,12 +7,14 @@ use alloc::vec::Vec;
 use crate::{
   ble,
   enums::*,
-  utilities::{os_mbuf_append, os_msys_get_pkthdr, voidp_to_ref, BleUuid},
+  utilities::{os_mbuf_append, os_msys_get_pkthdr, voidp_to_ref, AdStructure, BleUuid},
   BLEAddress, BLEError, BLEServer,
 };
 
 pub struct BLEExtAdvertisement {
   payload: Vec<u8>,
+  /// A structure added by a convenience setter was too long; reported when the data is set.
+  oversized: bool,
   params: esp_idf_sys::ble_gap_ext_adv_params,
   adv_address: Option<BLEAddress>,
 }
@@ -21,6 +23,7 @@ impl BLEExtAdvertisement {
   pub fn new(primary_phy: u8, secondary_phy: u8) -> Self {
     Self {
       payload: Vec::new(),
+      oversized: false,
       params: esp_idf_sys::ble_gap_ext_adv_params {
         own_addr_type: unsafe { crate::ble_device::OWN_ADDR_TYPE as _ },
         primary_phy,
@@ -90,6 +93,7 @@ impl BLEExtAdvertisement {
   /// Clears the data stored in this instance, does not change settings.
   pub fn clear(&mut self) {
     self.payload.clear();
+    self.oversized = false;
   }
 
   /// Get the size of the current data.
@@ -98,125 +102,159 @@ impl BLEExtAdvertisement {
   }
 
   pub fn appearance(&mut self, appearance: u16) {
-    self.add_data(
-      esp_idf_sys::BLE_HS_ADV_TYPE_APPEARANCE as _,
-      &appearance.to_le_bytes(),
-    );
+    self.push_ad_structure(&AdStructure::Appearance(appearance));
   }
 
   /// Set manufacturer specific data.
   pub fn manufacturer_data(&mut self, data: &[u8]) {
-    self.add_data(esp_idf_sys::BLE_HS_ADV_TYPE_MFG_DATA as _, data);
+    self.push_ad_structure(&AdStructure::ManufacturerSpecificData(data.to_vec()));
   }
 
   /// Set the complete name of this device.
   pub fn name(&mut self, name: &str) {
-    self.add_data(esp_idf_sys::BLE_HS_ADV_TYPE_COMP_NAME as _, name.as_bytes());
+    self.push_ad_structure(&AdStructure::CompleteLocalName(name.into()));
   }
 
   // Set a single service to advertise as a complete list of services.
   pub fn complete_service(&mut self, uuid: &BleUuid) {
-    let bit_size = match uuid {
-      BleUuid::Uuid16(_) => 16,
-      BleUuid::Uuid32(_) => 32,
-      BleUuid::Uuid128(_) => 128,
-    };
-    self.set_services(true, bit_size, &[*uuid]);
+    if let Some(services) = AdStructure::service_uuids(&[*uuid], true) {
+      self.push_ad_structure(&services);
+    }
   }
 
-  fn set_services(&mut self, complete: bool, size: u8, uuids: &[BleUuid]) {
-    let data_type: u8 = match size {
-      16 => {
-        if complete {
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID16 as _
-        } else {
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS16 as _
-        }
-      }
-      32 => {
-        if complete {
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID32 as _
-        } else {
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS32 as _
-        }
-      }
-      128 => {
-        if complete {
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID128 as _
-        } else {
-          esp_idf_sys::BLE_HS_ADV_TYPE_INCOMP_UUIDS128 as _
-        }
-      }
-      _ => return,
-    };
-    self.payload.push((size / 8) * (uuids.len() as u8) + 1);
-    self.payload.push(data_type);
-
-    for uuid in uuids {
-      match uuid {
-        BleUuid::Uuid16(uuid) => {
-          self.payload.extend_from_slice(&uuid.to_ne_bytes());
-        }
-        BleUuid::Uuid32(uuid) => {
-          self.payload.extend_from_slice(&uuid.to_ne_bytes());
-        }
-        BleUuid::Uuid128(uuid) => {
-          self.payload.extend_from_slice(uuid);
-        }
-      }
+  /// Set the service data (UUID + data)
+  pub fn service_data(&mut self, uuid: BleUuid, data: &[u8]) {
+    self.push_ad_structure(&AdStructure::service_data(uuid, data));
+  }
+
+  /// Append an AD structure to the payload.
+  ///
+  /// Fails with `BLE_HS_EMSGSIZE` if the structure is longer than
+  /// [`AdStructure::MAX_DATA_LEN`].
+  pub fn add_ad_structure(&mut self, ad: &AdStructure) -> Result<(), BLEError> {
+    ad.encode(&mut self.payload)
+  }
+
+  fn push_ad_structure(&mut self, ad: &AdStructure) {
+    if self.add_ad_structure(ad).is_err() {
+      self.oversized = true;
+    }
+  }
+
+  fn check_size(&self) -> Result<(), BLEError> {
+    if self.oversized {
+      return BLEError::convert(esp_idf_sys::BLE_HS_EMSGSIZE);
     }
+    Ok(())
+  }
+}
+
+/// Data and parameters of a periodic advertising train.
+///
+/// The data can be longer than a single PDU, the controller splits it into a chain of PDUs.
+#[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+pub struct BLEPeriodicAdvertisement {
+  payload: Vec<u8>,
+  oversized: bool,
+  params: esp_idf_sys::ble_gap_periodic_adv_params,
+}
+
+#[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+impl BLEPeriodicAdvertisement {
+  pub fn new() -> Self {
+    Self {
+      payload: Vec::new(),
+      oversized: false,
+      params: Default::default(),
+    }
+  }
+
+  /// Set the minimum periodic advertising interval (in 1.25ms units).
+  pub fn min_interval(&mut self, val: u16) {
+    self.params.itvl_min = val;
+  }
+
+  /// Set the maximum periodic advertising interval (in 1.25ms units).
+  pub fn max_interval(&mut self, val: u16) {
+    self.params.itvl_max = val;
+  }
+
+  /// Sets whether the transmission power is included in the periodic advertising PDUs.
+  pub fn include_tx_power(&mut self, val: bool) {
+    self.params.set_include_tx_power(val as _);
+  }
+
+  /// Clears the data stored in this instance, does not change settings.
+  pub fn clear(&mut self) {
+    self.payload.clear();
+    self.oversized = false;
+  }
+
+  /// Get the size of the current data.
+  pub fn size(&self) -> usize {
+    self.payload.len()
+  }
+
+  /// Set manufacturer specific data.
+  pub fn manufacturer_data(&mut self, data: &[u8]) {
+    self.push_ad_structure(&AdStructure::ManufacturerSpecificData(data.to_vec()));
   }
 
   /// Set the service data (UUID + data)
   pub fn service_data(&mut self, uuid: BleUuid, data: &[u8]) {
-    match uuid {
-      BleUuid::Uuid16(uuid) => {
-        self.add_data2(
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID16 as _,
-          &uuid.to_ne_bytes(),
-          data,
-        );
-      }
-      BleUuid::Uuid32(uuid) => {
-        self.add_data2(
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID32 as _,
-          &uuid.to_ne_bytes(),
-          data,
-        );
-      }
-      BleUuid::Uuid128(uuid) => {
-        self.add_data2(
-          esp_idf_sys::BLE_HS_ADV_TYPE_SVC_DATA_UUID128 as _,
-          &uuid,
-          data,
-        );
-      }
+    self.push_ad_structure(&AdStructure::service_data(uuid, data));
+  }
+
+  /// Append an AD structure to the payload.
+  ///
+  /// Fails with `BLE_HS_EMSGSIZE` if the structure is longer than
+  /// [`AdStructure::MAX_DATA_LEN`].
+  pub fn add_ad_structure(&mut self, ad: &AdStructure) -> Result<(), BLEError> {
+    ad.encode(&mut self.payload)
+  }
+
+  fn push_ad_structure(&mut self, ad: &AdStructure) {
+    if self.add_ad_structure(ad).is_err() {
+      self.oversized = true;
     }
   }
 
-  fn add_data(&mut self, data_type: u8, data: &[u8]) {
-    self.payload.push((data.len() as u8) + 1);
-    self.payload.push(data_type);
-    self.payload.extend_from_slice(data);
+  fn check_size(&self) -> Result<(), BLEError> {
+    if self.oversized {
+      return BLEError::convert(esp_idf_sys::BLE_HS_EMSGSIZE);
+    }
+    Ok(())
   }
+}
 
-  fn add_data2(&mut self, data_type: u8, data0: &[u8], data1: &[u8]) {
-    self.payload.push((data0.len() + data1.len() + 1) as u8);
-    self.payload.push(data_type);
-    self.payload.extend_from_slice(data0);
-    self.payload.extend_from_slice(data1);
+#[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+impl Default for BLEPeriodicAdvertisement {
+  fn default() -> Self {
+    Self::new()
   }
 }
 
+/// A configured instance, kept to apply the pairing window to it.
+#[derive(Copy, Clone)]
+struct Instance {
+  params: esp_idf_sys::ble_gap_ext_adv_params,
+  handle_gap_event: esp_idf_sys::ble_gap_event_fn,
+  duration: i32,
+  max_event: i32,
+}
+
 pub struct BLEExtAdvertising {
   adv_status: Vec<bool>,
+  instances: Vec<Option<Instance>>,
 }
 
 impl BLEExtAdvertising {
   #[allow(dead_code)]
   pub(crate) fn new() -> Self {
+    let count = (esp_idf_sys::CONFIG_BT_NIMBLE_MAX_EXT_ADV_INSTANCES + 1) as usize;
     Self {
-      adv_status: vec![false; (esp_idf_sys::CONFIG_BT_NIMBLE_MAX_EXT_ADV_INSTANCES + 1) as _],
+      adv_status: vec![false; count],
+      instances: vec![None; count],
     }
   }
 
@@ -225,6 +263,7 @@ impl BLEExtAdvertising {
     inst_id: u8,
     adv: &mut BLEExtAdvertisement,
   ) -> Result<(), BLEError> {
+    adv.check_size()?;
     adv.params.sid = inst_id;
 
     // Legacy advertising as connectable requires the scannable flag also.
@@ -250,15 +289,18 @@ impl BLEExtAdvertising {
       Self::handle_gap_event
     };
 
-    unsafe {
-      ble!(esp_idf_sys::ble_gap_ext_adv_configure(
-        inst_id,
-        &adv.params,
-        core::ptr::null_mut(),
-        Some(handle_gap_event),
-        self as *mut Self as _
-      ))?;
+    let instance = Instance {
+      params: adv.params,
+      handle_gap_event: Some(handle_gap_event),
+      duration: 0,
+      max_event: 0,
+    };
+    self.configure(inst_id, &instance)?;
+    if let Some(x) = self.instances.get_mut(inst_id as usize) {
+      *x = Some(instance);
+    }
 
+    unsafe {
       let buf = os_msys_get_pkthdr(adv.payload.len() as _, 0);
       if buf.is_null() {
         return BLEError::fail();
@@ -284,6 +326,7 @@ impl BLEExtAdvertising {
     inst_id: u8,
     lsr: &BLEExtAdvertisement,
   ) -> Result<(), BLEError> {
+    lsr.check_size()?;
     unsafe {
       let buf = os_msys_get_pkthdr(lsr.payload.len() as _, 0);
       if buf.is_null() {
@@ -308,6 +351,136 @@ impl BLEExtAdvertising {
     unsafe {
       ble!(esp_idf_sys::ble_gap_ext_adv_start(
         inst_id, duration, max_event
+      ))?;
+    }
+    if let Some(Some(instance)) = self.instances.get_mut(inst_id as usize) {
+      instance.duration = duration;
+      instance.max_event = max_event;
+    }
+    Ok(())
+  }
+
+  /// Configure an instance with its parameters.
+  ///
+  /// While the [pairing window](crate::BLESecurity::pairing_window) is closed, a connectable
+  /// instance accepts connections from bonded peers only, and is not connectable if there
+  /// are none.
+  fn configure(&mut self, inst_id: u8, instance: &Instance) -> Result<(), BLEError> {
+    let mut params = instance.params;
+    if params.connectable() != 0 && crate::pairing_window::state() == Some(false) {
+      if crate::pairing_window::set_bonded_white_list()? {
+        params.filter_policy = AdvFilterPolicy::Connect.into();
+      } else {
+        params.set_connectable(0);
+      }
+    }
+
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_ext_adv_configure(
+        inst_id,
+        &params,
+        core::ptr::null_mut(),
+        instance.handle_gap_event,
+        self as *mut Self as _
+      ))
+    }
+  }
+
+  /// Reconfigure the connectable instances after the pairing window opened or closed,
+  /// restarting the ones that were advertising.
+  pub(crate) fn refresh_pairing_window(&mut self) -> Result<(), BLEError> {
+    for inst_id in 0..self.instances.len() {
+      let Some(instance) = self.instances[inst_id] else {
+        continue;
+      };
+      if instance.params.connectable() == 0 {
+        continue;
+      }
+
+      let inst_id = inst_id as u8;
+      let active = unsafe { esp_idf_sys::ble_gap_ext_adv_active(inst_id) };
+      if active {
+        unsafe { ble!(esp_idf_sys::ble_gap_ext_adv_stop(inst_id))? };
+      }
+      self.configure(inst_id, &instance)?;
+      if active {
+        self.start_with_duration(inst_id, instance.duration, instance.max_event)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Configure periodic advertising on an instance and set its data.
+  ///
+  /// The instance must be set with [`BLEExtAdvertising::set_instance_data`] first,
+  /// as a non-connectable and non-scannable extended advertisement.
+  #[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+  pub fn set_periodic_instance_data(
+    &mut self,
+    inst_id: u8,
+    adv: &BLEPeriodicAdvertisement,
+  ) -> Result<(), BLEError> {
+    adv.check_size()?;
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_configure(
+        inst_id,
+        &adv.params
+      ))?;
+    }
+    self.set_periodic_data(inst_id, adv)
+  }
+
+  /// Update the periodic advertising data of an instance, keeping its parameters.
+  #[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+  pub fn set_periodic_data(
+    &mut self,
+    inst_id: u8,
+    adv: &BLEPeriodicAdvertisement,
+  ) -> Result<(), BLEError> {
+    adv.check_size()?;
+    unsafe {
+      let buf = os_msys_get_pkthdr(adv.payload.len() as _, 0);
+      if buf.is_null() {
+        return BLEError::fail();
+      }
+      ble!(os_mbuf_append(buf, &adv.payload))?;
+
+      ble!(esp_idf_sys::ble_gap_periodic_adv_set_data(inst_id, buf))
+    }
+  }
+
+  /// Start the periodic advertising train of an instance.
+  ///
+  /// The periodic advertisements are only sent while the instance is advertising.
+  #[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+  pub fn start_periodic(&mut self, inst_id: u8) -> Result<(), BLEError> {
+    unsafe { ble!(esp_idf_sys::ble_gap_periodic_adv_start(inst_id)) }
+  }
+
+  /// Stop the periodic advertising train of an instance.
+  #[cfg(esp_idf_bt_nimble_enable_periodic_adv)]
+  pub fn stop_periodic(&mut self, inst_id: u8) -> Result<(), BLEError> {
+    unsafe { ble!(esp_idf_sys::ble_gap_periodic_adv_stop(inst_id)) }
+  }
+
+  /// Transfer the periodic advertising train of an instance to the peer of a connection.
+  ///
+  /// * `service_data`: value identifying the periodic advertising train to the peer.
+  #[cfg(all(
+    esp_idf_bt_nimble_enable_periodic_adv,
+    esp_idf_bt_nimble_periodic_adv_sync_transfer
+  ))]
+  pub fn transfer_periodic_sync_info(
+    &mut self,
+    inst_id: u8,
+    conn_handle: u16,
+    service_data: u16,
+  ) -> Result<(), BLEError> {
+    unsafe {
+      ble!(esp_idf_sys::ble_gap_periodic_adv_sync_set_info(
+        inst_id,
+        conn_handle,
+        service_data
       ))
     }
   }
@@ -272,6 +272,7 @@ impl BLEServer {
         {
           server.connections.swap_remove(idx);
         }
+        crate::signed_write::persist_counters();
 
         if let Some(callback) = server.on_disconnect.as_mut() {
           callback(
@@ -356,70 +357,34 @@ impl BLEServer {
       esp_idf_sys::BLE_GAP_EVENT_CONN_UPDATE_REQ => {}
       esp_idf_sys::BLE_GAP_EVENT_REPEAT_PAIRING => {
         let repeat_pairing = unsafe { &event.__bindgen_anon_1.repeat_pairing };
-
-        // Delete the old bond
-        let Ok(desc) = crate::utilities::ble_gap_conn_find(repeat_pairing.conn_handle) else {
-          return esp_idf_sys::BLE_GAP_REPEAT_PAIRING_IGNORE as _;
-        };
-        unsafe {
-          esp_idf_sys::ble_store_util_delete_peer(&desc.0.peer_id_addr);
-        }
-
-        // Return BLE_GAP_REPEAT_PAIRING_RETRY to indicate that the host should
-        // continue with the pairing operation.
-        return esp_idf_sys::BLE_GAP_REPEAT_PAIRING_RETRY as _;
+        return crate::pairing_window::on_repeat_pairing(repeat_pairing.conn_handle);
       }
       esp_idf_sys::BLE_GAP_EVENT_ENC_CHANGE => {
         let enc_change = unsafe { &event.__bindgen_anon_1.enc_change };
         let Ok(desk) = ble_gap_conn_find(enc_change.conn_handle) else {
           return esp_idf_sys::BLE_ATT_ERR_INVALID_HANDLE as _;
         };
+        if enc_change.status == 0 {
+          crate::bond_store::on_bond_encrypted(&desk);
+          // Just Works pairing has no passkey action to refuse it earlier; no keys were
+          // distributed, since bonding is disabled while the pairing window is closed.
+          if !desk.bonded() && crate::pairing_window::refuse_pairing(enc_change.conn_handle) {
+            return 0;
+          }
+        }
         if let Some(callback) = &server.on_authentication_complete {
           callback(&desk, BLEError::convert(enc_change.status as _));
         }
       }
       esp_idf_sys::BLE_GAP_EVENT_PASSKEY_ACTION => {
         let passkey = unsafe { &event.__bindgen_anon_1.passkey };
-        let mut pkey = esp_idf_sys::ble_sm_io {
-          action: passkey.params.action,
-          ..Default::default()
-        };
-        match passkey.params.action as _ {
-          esp_idf_sys::BLE_SM_IOACT_DISP => {
-            pkey.__bindgen_anon_1.passkey = if let Some(callback) = &server.on_passkey_request {
-              callback()
-            } else {
-              BLEDevice::take().security().get_passkey()
-            };
-
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_DISP; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_NUMCMP => {
-            if let Some(callback) = &server.on_confirm_pin {
-              pkey.__bindgen_anon_1.numcmp_accept = callback(passkey.params.numcmp) as _;
-            } else {
-              ::log::warn!("on_passkey_request is not setted");
-            }
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_NUMCMP; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_INPUT => {
-            if let Some(callback) = &server.on_passkey_request {
-              pkey.__bindgen_anon_1.passkey = callback();
-            } else {
-              ::log::warn!("on_passkey_request is not setted");
-            }
-            let rc = unsafe { esp_idf_sys::ble_sm_inject_io(passkey.conn_handle, &mut pkey) };
-            ::log::debug!("BLE_SM_IOACT_INPUT; ble_sm_inject_io result: {}", rc);
-          }
-          esp_idf_sys::BLE_SM_IOACT_NONE => {
-            ::log::debug!("BLE_SM_IOACT_NONE; No passkey action required");
-          }
-          action => {
-            todo!("implementation required: {}", action);
-          }
-        }
+        crate::pairing_agent::on_passkey_action(
+          passkey.conn_handle,
+          &passkey.params,
+          server.on_passkey_request.as_deref(),
+          server.on_passkey_request.as_deref(),
+          server.on_confirm_pin.as_deref(),
+        );
       }
       esp_idf_sys::BLE_GAP_EVENT_IDENTITY_RESOLVED
       | esp_idf_sys::BLE_GAP_EVENT_PHY_UPDATE_COMPLETE => {}
@@ -1,9 +1,10 @@
-use crate::BLEConnDesc;
+use crate::{AttError, BLEConnDesc};
 
 pub struct OnWriteArgs<'a> {
   pub(crate) current_data: &'a [u8],
   pub(crate) recv_data: &'a [u8],
   pub(crate) desc: &'a BLEConnDesc,
+  pub(crate) sign_counter: Option<u32>,
   pub(crate) reject: bool,
   pub(crate) error_code: u8,
   pub(crate) notify: bool,
@@ -22,17 +23,26 @@ impl OnWriteArgs<'_> {
     self.desc
   }
 
+  /// The sign counter of a signed write whose signature was verified, `None` if the value
+  /// was not signed. The signature is not part of [`OnWriteArgs::recv_data`].
+  pub fn sign_counter(&self) -> Option<u32> {
+    self.sign_counter
+  }
+
   /// If the reject is called, no value is written to BLECharacteristic or BLEDescriptor.
   /// A write error (0xFF) is sent to the sender.
   pub fn reject(&mut self) {
-    self.reject_with_error_code(0xFF);
+    self.reject_with_error_code(AttError::OutOfRange);
   }
 
   /// If the reject is called, no value is written to BLECharacteristic or BLEDescriptor.
   /// The argument error code is sent to the sender.
-  pub fn reject_with_error_code(&mut self, error_code: u8) {
+  ///
+  /// `error_code` is an ATT error code, e.g. [`AttError::ValueNotAllowed`](crate::AttError),
+  /// or an application error code in `0x80..=0x9F`.
+  pub fn reject_with_error_code(&mut self, error_code: impl Into<u8>) {
     self.reject = true;
-    self.error_code = error_code;
+    self.error_code = error_code.into();
   }
 
   pub fn notify(&mut self) {
@@ -64,13 +74,16 @@ impl OnWriteDescriptorArgs<'_> {
   /// If the reject is called, no value is written to BLECharacteristic or BLEDescriptor.
   /// A write error (0xFF) is sent to the sender.
   pub fn reject(&mut self) {
-    self.reject_with_error_code(0xFF);
+    self.reject_with_error_code(AttError::OutOfRange);
   }
 
   /// If the reject is called, no value is written to BLECharacteristic or BLEDescriptor.
   /// The argument error code is sent to the sender.
-  pub fn reject_with_error_code(&mut self, error_code: u8) {
+  ///
+  /// `error_code` is an ATT error code, e.g. [`AttError::ValueNotAllowed`](crate::AttError),
+  /// or an application error code in `0x80..=0x9F`.
+  pub fn reject_with_error_code(&mut self, error_code: impl Into<u8>) {
     self.reject = true;
-    self.error_code = error_code;
+    self.error_code = error_code.into();
   }
 }
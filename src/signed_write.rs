@@ -0,0 +1,273 @@
+use alloc::vec::Vec;
+use core::ffi::CStr;
+use esp_idf_sys::*;
+
+use crate::{
+  utilities::{aes_cmac, mutex::Mutex, nvs_load, nvs_open_namespace, nvs_store},
+  BLEAddress, BLEAddressType, BLEConnDesc, BLEError,
+};
+
+/// Length of the signature appended to a signed value: the sign counter and the 64-bit MAC.
+pub const SIGNATURE_LEN: usize = 12;
+
+const NAMESPACE: &str = "ble_sign";
+const KEY: &CStr = c"counters";
+const FORMAT_VERSION: u8 = 1;
+const ENTRY_LEN: usize = 24;
+
+/// Number of local counters reserved in NVS at a time, so that signing does not write NVS
+/// for every value. The unused counters of a reservation are skipped after a restart.
+const LOCAL_RESERVATION: u32 = 32;
+
+/// The sign counters used with the CSRKs of a peer.
+struct SignCounters {
+  address: BLEAddress,
+  /// Identifies the CSRK we distributed; the counter restarts when we pair again.
+  local_key: [u8; 4],
+  /// Next counter of the values we sign.
+  local: u32,
+  /// End of the local counters reserved in NVS; the persisted local counter.
+  local_reserved: u32,
+  /// Identifies the CSRK the peer distributed.
+  peer_key: [u8; 4],
+  /// Last counter of the values the peer signed.
+  peer: Option<u32>,
+}
+
+struct Counters {
+  handle: Option<nvs_handle_t>,
+  entries: Vec<SignCounters>,
+  /// The peer counters changed since they were persisted.
+  dirty: bool,
+}
+
+static COUNTERS: Mutex<Option<Counters>> = Mutex::new(None);
+
+impl Counters {
+  fn open() -> Self {
+    let handle = nvs_open_namespace(NAMESPACE).ok();
+    let entries = match handle.map(|x| nvs_load(x, KEY)) {
+      Some(Ok(Some(data))) => decode_counters(&data).unwrap_or_else(|| {
+        ::log::warn!("discarding undecodable sign counters");
+        Vec::new()
+      }),
+      _ => Vec::new(),
+    };
+    Self {
+      handle,
+      entries,
+      dirty: false,
+    }
+  }
+
+  fn entry(&mut self, address: &BLEAddress) -> &mut SignCounters {
+    let idx = match self.entries.iter().position(|x| x.address == *address) {
+      Some(idx) => idx,
+      None => {
+        self.entries.push(SignCounters {
+          address: *address,
+          local_key: [0; 4],
+          local: 0,
+          local_reserved: 0,
+          peer_key: [0; 4],
+          peer: None,
+        });
+        self.entries.len() - 1
+      }
+    };
+    &mut self.entries[idx]
+  }
+
+  fn persist(&mut self) -> Result<(), BLEError> {
+    if let Some(handle) = self.handle {
+      nvs_store(handle, KEY, &encode_counters(&self.entries))?;
+    }
+    self.dirty = false;
+    Ok(())
+  }
+}
+
+fn with_counters<R>(f: impl FnOnce(&mut Counters) -> R) -> R {
+  let mut counters = COUNTERS.lock();
+  f(counters.get_or_insert_with(Counters::open))
+}
+
+/// The CSRK we distributed to the peer, or the one the peer distributed to us, most
+/// significant byte first.
+fn csrk(address: &BLEAddress, ours: bool) -> Option<[u8; 16]> {
+  let mut key: ble_store_key_sec = unsafe { core::mem::zeroed() };
+  key.peer_addr = address.value;
+  let mut value = ble_store_value_sec::default();
+  let rc = unsafe {
+    if ours {
+      ble_store_read_our_sec(&key, &mut value)
+    } else {
+      ble_store_read_peer_sec(&key, &mut value)
+    }
+  };
+  if rc != 0 || value.csrk_present() == 0 {
+    return None;
+  }
+  let mut csrk = value.csrk;
+  csrk.reverse();
+  Some(csrk)
+}
+
+fn key_id(csrk: &[u8; 16]) -> [u8; 4] {
+  let mac = aes_cmac(csrk, &[]);
+  [mac[0], mac[1], mac[2], mac[3]]
+}
+
+/// The signature of the Signed Write Command of `value` to `handle` (Core Vol 3, Part C,
+/// 10.4.1): the sign counter and the 64 most significant bits of the MAC, least significant
+/// byte first.
+fn signature(csrk: &[u8; 16], handle: u16, value: &[u8], counter: u32) -> [u8; SIGNATURE_LEN] {
+  let mut msg = Vec::with_capacity(value.len() + 7);
+  msg.push(BLE_ATT_OP_SIGNED_WRITE_CMD as u8);
+  msg.extend_from_slice(&handle.to_le_bytes());
+  msg.extend_from_slice(value);
+  msg.extend_from_slice(&counter.to_le_bytes());
+  msg.reverse();
+  let mac = aes_cmac(csrk, &msg);
+
+  let mut signature = [0u8; SIGNATURE_LEN];
+  signature[..4].copy_from_slice(&counter.to_le_bytes());
+  for (x, m) in signature[4..].iter_mut().zip(mac[..8].iter().rev()) {
+    *x = *m;
+  }
+  signature
+}
+
+/// `value` followed by its signature with the CSRK we distributed to the peer of `desc`.
+///
+/// The counter is reserved in NVS before the signature is returned, so that it is never
+/// reused; NVS is written once per `LOCAL_RESERVATION` values.
+pub(crate) fn sign(desc: &BLEConnDesc, handle: u16, value: &[u8]) -> Result<Vec<u8>, BLEError> {
+  let address = desc.id_address();
+  let Some(csrk) = csrk(&address, true) else {
+    return Err(BLEError::convert(BLE_HS_EAUTHEN).unwrap_err());
+  };
+
+  let counter = with_counters(|counters| {
+    let entry = counters.entry(&address);
+    let key = key_id(&csrk);
+    if entry.local_key != key {
+      entry.local_key = key;
+      entry.local = 0;
+      entry.local_reserved = 0;
+    }
+    let counter = entry.local;
+    entry.local = counter
+      .checked_add(1)
+      .ok_or_else(|| BLEError::convert(BLE_HS_EAUTHEN).unwrap_err())?;
+    if entry.local > entry.local_reserved {
+      entry.local_reserved = entry.local.saturating_add(LOCAL_RESERVATION);
+      counters.persist()?;
+    }
+    Ok::<_, BLEError>(counter)
+  })?;
+
+  let mut data = Vec::with_capacity(value.len() + SIGNATURE_LEN);
+  data.extend_from_slice(value);
+  data.extend_from_slice(&signature(&csrk, handle, value, counter));
+  Ok(data)
+}
+
+/// Verify the signature at the end of `data` with the CSRK the peer of `desc` distributed,
+/// rejecting counters that are not greater than the last accepted one.
+///
+/// Returns the length of the value and its sign counter. The accepted counter is kept in
+/// RAM, and persisted by [`persist_counters`] when the peer disconnects.
+pub(crate) fn verify(desc: &BLEConnDesc, handle: u16, data: &[u8]) -> Option<(usize, u32)> {
+  let len = data.len().checked_sub(SIGNATURE_LEN)?;
+  let (value, received) = data.split_at(len);
+  let address = desc.id_address();
+  let csrk = csrk(&address, false)?;
+  let counter = u32::from_le_bytes(received[..4].try_into().unwrap());
+
+  let expected = signature(&csrk, handle, value, counter);
+  if expected
+    .iter()
+    .zip(received)
+    .fold(0, |acc, (x, y)| acc | (x ^ y))
+    != 0
+  {
+    ::log::warn!("invalid signature from {}", address);
+    return None;
+  }
+
+  with_counters(|counters| {
+    let entry = counters.entry(&address);
+    let key = key_id(&csrk);
+    if entry.peer_key != key {
+      entry.peer_key = key;
+      entry.peer = None;
+    }
+    if entry.peer.is_some_and(|x| counter <= x) {
+      ::log::warn!("replayed sign counter {} from {}", counter, address);
+      return None;
+    }
+    entry.peer = Some(counter);
+    counters.dirty = true;
+    Some((len, counter))
+  })
+}
+
+/// Persist the peer counters accepted since they were last persisted.
+pub(crate) fn persist_counters() {
+  let mut counters = COUNTERS.lock();
+  let Some(counters) = counters.as_mut().filter(|x| x.dirty) else {
+    return;
+  };
+  if let Err(err) = counters.persist() {
+    ::log::warn!("failed to persist the sign counters: {:?}", err);
+  }
+}
+
+/// Forget the counters of `address`, or of all the peers if `None`.
+pub(crate) fn forget_counters(address: Option<&BLEAddress>) {
+  with_counters(|counters| {
+    counters
+      .entries
+      .retain(|x| address.is_some_and(|address| x.address != *address));
+    counters.persist().ok();
+  });
+}
+
+fn encode_counters(entries: &[SignCounters]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(1 + entries.len() * ENTRY_LEN);
+  out.push(FORMAT_VERSION);
+  for entry in entries {
+    out.push(entry.address.value.type_);
+    out.extend_from_slice(&entry.address.value.val);
+    out.extend_from_slice(&entry.local_key);
+    out.extend_from_slice(&entry.local_reserved.to_le_bytes());
+    out.extend_from_slice(&entry.peer_key);
+    out.push(entry.peer.is_some() as u8);
+    out.extend_from_slice(&entry.peer.unwrap_or(0).to_le_bytes());
+  }
+  out
+}
+
+fn decode_counters(data: &[u8]) -> Option<Vec<SignCounters>> {
+  let (&version, data) = data.split_first()?;
+  if version != FORMAT_VERSION || data.len() % ENTRY_LEN != 0 {
+    return None;
+  }
+
+  data
+    .chunks_exact(ENTRY_LEN)
+    .map(|x| {
+      let addr_type = BLEAddressType::try_from(x[0]).ok()?;
+      let local = u32::from_le_bytes(x[11..15].try_into().unwrap());
+      Some(SignCounters {
+        address: BLEAddress::from_le_bytes(x[1..7].try_into().unwrap(), addr_type),
+        local_key: x[7..11].try_into().unwrap(),
+        local,
+        local_reserved: local,
+        peer_key: x[15..19].try_into().unwrap(),
+        peer: (x[19] != 0).then(|| u32::from_le_bytes(x[20..24].try_into().unwrap())),
+      })
+    })
+    .collect()
+}
@@ -0,0 +1,733 @@
+use alloc::vec::Vec;
+use bstr::BString;
+
+use crate::{enums::AdvFlag, utilities::BleUuid, BLEAddress, BLEAddressType, BLEError};
+
+/// Assigned numbers for the Common Data Types (Bluetooth Assigned Numbers, section 2.3).
+pub mod ad_type {
+  pub const FLAGS: u8 = 0x01;
+  pub const INCOMP_UUIDS16: u8 = 0x02;
+  pub const COMP_UUIDS16: u8 = 0x03;
+  pub const INCOMP_UUIDS32: u8 = 0x04;
+  pub const COMP_UUIDS32: u8 = 0x05;
+  pub const INCOMP_UUIDS128: u8 = 0x06;
+  pub const COMP_UUIDS128: u8 = 0x07;
+  pub const SHORT_NAME: u8 = 0x08;
+  pub const COMP_NAME: u8 = 0x09;
+  pub const TX_PWR_LVL: u8 = 0x0A;
+  pub const CLASS_OF_DEVICE: u8 = 0x0D;
+  pub const SP_HASH_C192: u8 = 0x0E;
+  pub const SP_RAND_R192: u8 = 0x0F;
+  pub const SM_TK_VALUE: u8 = 0x10;
+  pub const SM_OOB_FLAGS: u8 = 0x11;
+  pub const PERIPHERAL_ITVL_RANGE: u8 = 0x12;
+  pub const SOL_UUIDS16: u8 = 0x14;
+  pub const SOL_UUIDS128: u8 = 0x15;
+  pub const SVC_DATA_UUID16: u8 = 0x16;
+  pub const PUBLIC_TGT_ADDR: u8 = 0x17;
+  pub const RANDOM_TGT_ADDR: u8 = 0x18;
+  pub const APPEARANCE: u8 = 0x19;
+  pub const ADV_ITVL: u8 = 0x1A;
+  pub const LE_ADDR: u8 = 0x1B;
+  pub const LE_ROLE: u8 = 0x1C;
+  pub const SP_HASH_C256: u8 = 0x1D;
+  pub const SP_RAND_R256: u8 = 0x1E;
+  pub const SOL_UUIDS32: u8 = 0x1F;
+  pub const SVC_DATA_UUID32: u8 = 0x20;
+  pub const SVC_DATA_UUID128: u8 = 0x21;
+  pub const LE_SC_CONFIRM: u8 = 0x22;
+  pub const LE_SC_RANDOM: u8 = 0x23;
+  pub const URI: u8 = 0x24;
+  pub const INDOOR_POSITIONING: u8 = 0x25;
+  pub const TRANSPORT_DISCOVERY: u8 = 0x26;
+  pub const LE_SUPP_FEATURES: u8 = 0x27;
+  pub const CHANNEL_MAP_UPDATE: u8 = 0x28;
+  pub const PB_ADV: u8 = 0x29;
+  pub const MESH_MESSAGE: u8 = 0x2A;
+  pub const MESH_BEACON: u8 = 0x2B;
+  pub const BIG_INFO: u8 = 0x2C;
+  pub const BROADCAST_CODE: u8 = 0x2D;
+  pub const RSI: u8 = 0x2E;
+  pub const ADV_ITVL_LONG: u8 = 0x2F;
+  pub const BROADCAST_NAME: u8 = 0x30;
+  pub const ENCRYPTED_ADV_DATA: u8 = 0x31;
+  pub const PAWR_TIMING: u8 = 0x32;
+  pub const ESL: u8 = 0x34;
+  pub const INFO_3D: u8 = 0x3D;
+  pub const MFG_DATA: u8 = 0xFF;
+}
+
+/// One AD structure of an advertising or scan response payload.
+///
+/// The same type is used to build payloads ([`crate::BLEAdvertisementData`],
+/// [`crate::BLEExtAdvertisement`]) and to parse received ones
+/// ([`crate::BLEAdvertisedDevice`]), so both sides agree on the wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdStructure {
+  /// 0x01 - Flags
+  Flags(AdvFlag),
+  /// 0x02, 0x03 - 16-bit Service Class UUIDs
+  ServiceUuids16 { uuids: Vec<u16>, complete: bool },
+  /// 0x04, 0x05 - 32-bit Service Class UUIDs
+  ServiceUuids32 { uuids: Vec<u32>, complete: bool },
+  /// 0x06, 0x07 - 128-bit Service Class UUIDs
+  ServiceUuids128 {
+    uuids: Vec<[u8; 16]>,
+    complete: bool,
+  },
+  /// 0x08 - Shortened Local Name
+  ShortenedLocalName(BString),
+  /// 0x09 - Complete Local Name
+  CompleteLocalName(BString),
+  /// 0x0A - Tx Power Level (dBm)
+  TxPowerLevel(i8),
+  /// 0x0D - Class of Device
+  ClassOfDevice([u8; 3]),
+  /// 0x0E - Simple Pairing Hash C-192
+  SimplePairingHashC192([u8; 16]),
+  /// 0x0F - Simple Pairing Randomizer R-192
+  SimplePairingRandomizerR192([u8; 16]),
+  /// 0x10 - Security Manager TK Value
+  SecurityManagerTkValue([u8; 16]),
+  /// 0x11 - Security Manager Out of Band Flags
+  SecurityManagerOobFlags(u8),
+  /// 0x12 - Peripheral Connection Interval Range (1.25ms units, 0xFFFF = no specific value)
+  PeripheralConnectionIntervalRange { min: u16, max: u16 },
+  /// 0x14 - List of 16-bit Service Solicitation UUIDs
+  ServiceSolicitationUuids16(Vec<u16>),
+  /// 0x15 - List of 128-bit Service Solicitation UUIDs
+  ServiceSolicitationUuids128(Vec<[u8; 16]>),
+  /// 0x16 - Service Data - 16-bit UUID
+  ServiceData16 { uuid: u16, data: Vec<u8> },
+  /// 0x17 - Public Target Address
+  PublicTargetAddress(Vec<BLEAddress>),
+  /// 0x18 - Random Target Address
+  RandomTargetAddress(Vec<BLEAddress>),
+  /// 0x19 - Appearance
+  Appearance(u16),
+  /// 0x1A - Advertising Interval (0.625ms units)
+  AdvertisingInterval(u16),
+  /// 0x1B - LE Bluetooth Device Address
+  LeBluetoothDeviceAddress(BLEAddress),
+  /// 0x1C - LE Role
+  ///
+  /// * 0x00: Only Peripheral Role supported.
+  /// * 0x01: Only Central Role supported.
+  /// * 0x02: Peripheral and Central Role supported, Peripheral Role preferred.
+  /// * 0x03: Peripheral and Central Role supported, Central Role preferred.
+  LeRole(u8),
+  /// 0x1D - Simple Pairing Hash C-256
+  SimplePairingHashC256([u8; 16]),
+  /// 0x1E - Simple Pairing Randomizer R-256
+  SimplePairingRandomizerR256([u8; 16]),
+  /// 0x1F - List of 32-bit Service Solicitation UUIDs
+  ServiceSolicitationUuids32(Vec<u32>),
+  /// 0x20 - Service Data - 32-bit UUID
+  ServiceData32 { uuid: u32, data: Vec<u8> },
+  /// 0x21 - Service Data - 128-bit UUID
+  ServiceData128 { uuid: [u8; 16], data: Vec<u8> },
+  /// 0x22 - LE Secure Connections Confirmation Value
+  LeScConfirmationValue([u8; 16]),
+  /// 0x23 - LE Secure Connections Random Value
+  LeScRandomValue([u8; 16]),
+  /// 0x24 - URI (scheme name string code followed by the rest of the URI)
+  Uri(Vec<u8>),
+  /// 0x25 - Indoor Positioning
+  IndoorPositioning(Vec<u8>),
+  /// 0x26 - Transport Discovery Data
+  TransportDiscoveryData(Vec<u8>),
+  /// 0x27 - LE Supported Features
+  LeSupportedFeatures(Vec<u8>),
+  /// 0x28 - Channel Map Update Indication
+  ChannelMapUpdateIndication(Vec<u8>),
+  /// 0x29 - PB-ADV
+  PbAdv(Vec<u8>),
+  /// 0x2A - Mesh Message
+  MeshMessage(Vec<u8>),
+  /// 0x2B - Mesh Beacon
+  MeshBeacon(Vec<u8>),
+  /// 0x2C - BIGInfo
+  BigInfo(Vec<u8>),
+  /// 0x2D - Broadcast_Code
+  BroadcastCode([u8; 16]),
+  /// 0x2E - Resolvable Set Identifier
+  ResolvableSetIdentifier([u8; 6]),
+  /// 0x2F - Advertising Interval - long (0.625ms units)
+  AdvertisingIntervalLong(u32),
+  /// 0x30 - Broadcast Name
+  BroadcastName(BString),
+  /// 0x31 - Encrypted Advertising Data
+  EncryptedAdvertisingData(Vec<u8>),
+  /// 0x32 - Periodic Advertising Response Timing Information
+  PeriodicAdvertisingResponseTimingInformation(Vec<u8>),
+  /// 0x34 - Electronic Shelf Label
+  ElectronicShelfLabel(Vec<u8>),
+  /// 0x3D - 3D Information Data
+  Information3D(Vec<u8>),
+  /// 0xFF - Manufacturer Specific Data (including the 2 byte company identifier)
+  ManufacturerSpecificData(Vec<u8>),
+  /// Any AD type not listed above.
+  Unknown { ad_type: u8, data: Vec<u8> },
+}
+
+impl AdStructure {
+  /// Maximum number of data octets a single AD structure can carry.
+  pub const MAX_DATA_LEN: usize = 254;
+
+  /// Maximum number of data octets of an AD structure in a legacy advertising packet
+  /// (31 bytes).
+  pub const MAX_LEGACY_DATA_LEN: usize = 29;
+
+  /// Creates a service UUID list from UUIDs of the same size.
+  ///
+  /// The size is taken from the first UUID, UUIDs of other sizes are ignored.
+  pub fn service_uuids(uuids: &[BleUuid], complete: bool) -> Option<Self> {
+    match uuids.first()? {
+      BleUuid::Uuid16(_) => Some(Self::ServiceUuids16 {
+        uuids: uuids.iter().filter_map(uuid16).collect(),
+        complete,
+      }),
+      BleUuid::Uuid32(_) => Some(Self::ServiceUuids32 {
+        uuids: uuids.iter().filter_map(uuid32).collect(),
+        complete,
+      }),
+      BleUuid::Uuid128(_) => Some(Self::ServiceUuids128 {
+        uuids: uuids.iter().filter_map(uuid128).collect(),
+        complete,
+      }),
+    }
+  }
+
+  /// Creates a service data structure of the size matching `uuid`.
+  pub fn service_data(uuid: BleUuid, data: &[u8]) -> Self {
+    match uuid {
+      BleUuid::Uuid16(uuid) => Self::ServiceData16 {
+        uuid,
+        data: data.to_vec(),
+      },
+      BleUuid::Uuid32(uuid) => Self::ServiceData32 {
+        uuid,
+        data: data.to_vec(),
+      },
+      BleUuid::Uuid128(uuid) => Self::ServiceData128 {
+        uuid,
+        data: data.to_vec(),
+      },
+    }
+  }
+
+  /// Get the AD type octet of this structure.
+  pub fn ad_type(&self) -> u8 {
+    match self {
+      Self::Flags(_) => ad_type::FLAGS,
+      Self::ServiceUuids16 { complete, .. } => {
+        if *complete {
+          ad_type::COMP_UUIDS16
+        } else {
+          ad_type::INCOMP_UUIDS16
+        }
+      }
+      Self::ServiceUuids32 { complete, .. } => {
+        if *complete {
+          ad_type::COMP_UUIDS32
+        } else {
+          ad_type::INCOMP_UUIDS32
+        }
+      }
+      Self::ServiceUuids128 { complete, .. } => {
+        if *complete {
+          ad_type::COMP_UUIDS128
+        } else {
+          ad_type::INCOMP_UUIDS128
+        }
+      }
+      Self::ShortenedLocalName(_) => ad_type::SHORT_NAME,
+      Self::CompleteLocalName(_) => ad_type::COMP_NAME,
+      Self::TxPowerLevel(_) => ad_type::TX_PWR_LVL,
+      Self::ClassOfDevice(_) => ad_type::CLASS_OF_DEVICE,
+      Self::SimplePairingHashC192(_) => ad_type::SP_HASH_C192,
+      Self::SimplePairingRandomizerR192(_) => ad_type::SP_RAND_R192,
+      Self::SecurityManagerTkValue(_) => ad_type::SM_TK_VALUE,
+      Self::SecurityManagerOobFlags(_) => ad_type::SM_OOB_FLAGS,
+      Self::PeripheralConnectionIntervalRange { .. } => ad_type::PERIPHERAL_ITVL_RANGE,
+      Self::ServiceSolicitationUuids16(_) => ad_type::SOL_UUIDS16,
+      Self::ServiceSolicitationUuids128(_) => ad_type::SOL_UUIDS128,
+      Self::ServiceData16 { .. } => ad_type::SVC_DATA_UUID16,
+      Self::PublicTargetAddress(_) => ad_type::PUBLIC_TGT_ADDR,
+      Self::RandomTargetAddress(_) => ad_type::RANDOM_TGT_ADDR,
+      Self::Appearance(_) => ad_type::APPEARANCE,
+      Self::AdvertisingInterval(_) => ad_type::ADV_ITVL,
+      Self::LeBluetoothDeviceAddress(_) => ad_type::LE_ADDR,
+      Self::LeRole(_) => ad_type::LE_ROLE,
+      Self::SimplePairingHashC256(_) => ad_type::SP_HASH_C256,
+      Self::SimplePairingRandomizerR256(_) => ad_type::SP_RAND_R256,
+      Self::ServiceSolicitationUuids32(_) => ad_type::SOL_UUIDS32,
+      Self::ServiceData32 { .. } => ad_type::SVC_DATA_UUID32,
+      Self::ServiceData128 { .. } => ad_type::SVC_DATA_UUID128,
+      Self::LeScConfirmationValue(_) => ad_type::LE_SC_CONFIRM,
+      Self::LeScRandomValue(_) => ad_type::LE_SC_RANDOM,
+      Self::Uri(_) => ad_type::URI,
+      Self::IndoorPositioning(_) => ad_type::INDOOR_POSITIONING,
+      Self::TransportDiscoveryData(_) => ad_type::TRANSPORT_DISCOVERY,
+      Self::LeSupportedFeatures(_) => ad_type::LE_SUPP_FEATURES,
+      Self::ChannelMapUpdateIndication(_) => ad_type::CHANNEL_MAP_UPDATE,
+      Self::PbAdv(_) => ad_type::PB_ADV,
+      Self::MeshMessage(_) => ad_type::MESH_MESSAGE,
+      Self::MeshBeacon(_) => ad_type::MESH_BEACON,
+      Self::BigInfo(_) => ad_type::BIG_INFO,
+      Self::BroadcastCode(_) => ad_type::BROADCAST_CODE,
+      Self::ResolvableSetIdentifier(_) => ad_type::RSI,
+      Self::AdvertisingIntervalLong(_) => ad_type::ADV_ITVL_LONG,
+      Self::BroadcastName(_) => ad_type::BROADCAST_NAME,
+      Self::EncryptedAdvertisingData(_) => ad_type::ENCRYPTED_ADV_DATA,
+      Self::PeriodicAdvertisingResponseTimingInformation(_) => ad_type::PAWR_TIMING,
+      Self::ElectronicShelfLabel(_) => ad_type::ESL,
+      Self::Information3D(_) => ad_type::INFO_3D,
+      Self::ManufacturerSpecificData(_) => ad_type::MFG_DATA,
+      Self::Unknown { ad_type, .. } => *ad_type,
+    }
+  }
+
+  /// Get the number of data octets (excluding the length and type octets).
+  pub fn data_len(&self) -> usize {
+    match self {
+      Self::Flags(_) | Self::SecurityManagerOobFlags(_) | Self::LeRole(_) => 1,
+      Self::TxPowerLevel(_) => 1,
+      Self::ServiceUuids16 { uuids, .. } => 2 * uuids.len(),
+      Self::ServiceUuids32 { uuids, .. } => 4 * uuids.len(),
+      Self::ServiceUuids128 { uuids, .. } => 16 * uuids.len(),
+      Self::ServiceSolicitationUuids16(uuids) => 2 * uuids.len(),
+      Self::ServiceSolicitationUuids32(uuids) => 4 * uuids.len(),
+      Self::ServiceSolicitationUuids128(uuids) => 16 * uuids.len(),
+      Self::ShortenedLocalName(name)
+      | Self::CompleteLocalName(name)
+      | Self::BroadcastName(name) => name.len(),
+      Self::ClassOfDevice(_) => 3,
+      Self::SimplePairingHashC192(_)
+      | Self::SimplePairingRandomizerR192(_)
+      | Self::SecurityManagerTkValue(_)
+      | Self::SimplePairingHashC256(_)
+      | Self::SimplePairingRandomizerR256(_)
+      | Self::LeScConfirmationValue(_)
+      | Self::LeScRandomValue(_)
+      | Self::BroadcastCode(_) => 16,
+      Self::PeripheralConnectionIntervalRange { .. } => 4,
+      Self::ServiceData16 { data, .. } => 2 + data.len(),
+      Self::ServiceData32 { data, .. } => 4 + data.len(),
+      Self::ServiceData128 { data, .. } => 16 + data.len(),
+      Self::PublicTargetAddress(addrs) | Self::RandomTargetAddress(addrs) => 6 * addrs.len(),
+      Self::Appearance(_) | Self::AdvertisingInterval(_) => 2,
+      Self::LeBluetoothDeviceAddress(_) => 7,
+      Self::ResolvableSetIdentifier(_) => 6,
+      Self::AdvertisingIntervalLong(itvl) => {
+        if *itvl > 0x00FF_FFFF {
+          4
+        } else {
+          3
+        }
+      }
+      Self::Uri(data)
+      | Self::IndoorPositioning(data)
+      | Self::TransportDiscoveryData(data)
+      | Self::LeSupportedFeatures(data)
+      | Self::ChannelMapUpdateIndication(data)
+      | Self::PbAdv(data)
+      | Self::MeshMessage(data)
+      | Self::MeshBeacon(data)
+      | Self::BigInfo(data)
+      | Self::EncryptedAdvertisingData(data)
+      | Self::PeriodicAdvertisingResponseTimingInformation(data)
+      | Self::ElectronicShelfLabel(data)
+      | Self::Information3D(data)
+      | Self::ManufacturerSpecificData(data)
+      | Self::Unknown { data, .. } => data.len(),
+    }
+  }
+
+  /// Get the number of octets this structure occupies in a payload,
+  /// including the length and type octets.
+  pub fn encoded_len(&self) -> usize {
+    2 + self.data_len()
+  }
+
+  /// Appends the encoded structure (length, type, data) to `buf`.
+  ///
+  /// Fails with `BLE_HS_EMSGSIZE`, leaving `buf` untouched, if the data is longer than
+  /// [`AdStructure::MAX_DATA_LEN`].
+  pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), BLEError> {
+    let data_len = self.data_len();
+    if data_len > Self::MAX_DATA_LEN {
+      return BLEError::convert(esp_idf_sys::BLE_HS_EMSGSIZE);
+    }
+
+    buf.reserve(data_len + 2);
+    buf.push((data_len + 1) as u8);
+    buf.push(self.ad_type());
+
+    match self {
+      Self::Flags(flags) => buf.push(flags.bits()),
+      Self::SecurityManagerOobFlags(x) | Self::LeRole(x) => buf.push(*x),
+      Self::TxPowerLevel(dbm) => buf.push(*dbm as u8),
+      Self::ServiceUuids16 { uuids, .. } | Self::ServiceSolicitationUuids16(uuids) => {
+        for uuid in uuids {
+          buf.extend_from_slice(&uuid.to_le_bytes());
+        }
+      }
+      Self::ServiceUuids32 { uuids, .. } | Self::ServiceSolicitationUuids32(uuids) => {
+        for uuid in uuids {
+          buf.extend_from_slice(&uuid.to_le_bytes());
+        }
+      }
+      Self::ServiceUuids128 { uuids, .. } | Self::ServiceSolicitationUuids128(uuids) => {
+        for uuid in uuids {
+          buf.extend_from_slice(uuid);
+        }
+      }
+      Self::ShortenedLocalName(name)
+      | Self::CompleteLocalName(name)
+      | Self::BroadcastName(name) => buf.extend_from_slice(name),
+      Self::ClassOfDevice(x) => buf.extend_from_slice(x),
+      Self::SimplePairingHashC192(x)
+      | Self::SimplePairingRandomizerR192(x)
+      | Self::SecurityManagerTkValue(x)
+      | Self::SimplePairingHashC256(x)
+      | Self::SimplePairingRandomizerR256(x)
+      | Self::LeScConfirmationValue(x)
+      | Self::LeScRandomValue(x)
+      | Self::BroadcastCode(x) => buf.extend_from_slice(x),
+      Self::PeripheralConnectionIntervalRange { min, max } => {
+        buf.extend_from_slice(&min.to_le_bytes());
+        buf.extend_from_slice(&max.to_le_bytes());
+      }
+      Self::ServiceData16 { uuid, data } => {
+        buf.extend_from_slice(&uuid.to_le_bytes());
+        buf.extend_from_slice(data);
+      }
+      Self::ServiceData32 { uuid, data } => {
+        buf.extend_from_slice(&uuid.to_le_bytes());
+        buf.extend_from_slice(data);
+      }
+      Self::ServiceData128 { uuid, data } => {
+        buf.extend_from_slice(uuid);
+        buf.extend_from_slice(data);
+      }
+      Self::PublicTargetAddress(addrs) | Self::RandomTargetAddress(addrs) => {
+        for addr in addrs {
+          buf.extend_from_slice(&addr.val());
+        }
+      }
+      Self::Appearance(x) | Self::AdvertisingInterval(x) => buf.extend_from_slice(&x.to_le_bytes()),
+      Self::LeBluetoothDeviceAddress(addr) => {
+        buf.extend_from_slice(&addr.val());
+        buf.push(matches!(addr.addr_type(), BLEAddressType::Random) as u8);
+      }
+      Self::ResolvableSetIdentifier(x) => buf.extend_from_slice(x),
+      Self::AdvertisingIntervalLong(itvl) => {
+        let bytes = itvl.to_le_bytes();
+        buf.extend_from_slice(&bytes[..data_len]);
+      }
+      Self::Uri(data)
+      | Self::IndoorPositioning(data)
+      | Self::TransportDiscoveryData(data)
+      | Self::LeSupportedFeatures(data)
+      | Self::ChannelMapUpdateIndication(data)
+      | Self::PbAdv(data)
+      | Self::MeshMessage(data)
+      | Self::MeshBeacon(data)
+      | Self::BigInfo(data)
+      | Self::EncryptedAdvertisingData(data)
+      | Self::PeriodicAdvertisingResponseTimingInformation(data)
+      | Self::ElectronicShelfLabel(data)
+      | Self::Information3D(data)
+      | Self::ManufacturerSpecificData(data)
+      | Self::Unknown { data, .. } => buf.extend_from_slice(data),
+    }
+    Ok(())
+  }
+
+  /// Decodes the data part of one AD structure.
+  ///
+  /// Returns `None` if the data length is invalid for the given AD type.
+  pub fn decode(ad_type: u8, data: &[u8]) -> Option<Self> {
+    let ret = match ad_type {
+      ad_type::FLAGS => Self::Flags(AdvFlag::from_bits_retain(*data.first()?)),
+      ad_type::INCOMP_UUIDS16 | ad_type::COMP_UUIDS16 => Self::ServiceUuids16 {
+        uuids: list_of(data, u16::from_le_bytes)?,
+        complete: ad_type == ad_type::COMP_UUIDS16,
+      },
+      ad_type::INCOMP_UUIDS32 | ad_type::COMP_UUIDS32 => Self::ServiceUuids32 {
+        uuids: list_of(data, u32::from_le_bytes)?,
+        complete: ad_type == ad_type::COMP_UUIDS32,
+      },
+      ad_type::INCOMP_UUIDS128 | ad_type::COMP_UUIDS128 => Self::ServiceUuids128 {
+        uuids: list_of(data, |x: [u8; 16]| x)?,
+        complete: ad_type == ad_type::COMP_UUIDS128,
+      },
+      ad_type::SHORT_NAME => Self::ShortenedLocalName(BString::from(data)),
+      ad_type::COMP_NAME => Self::CompleteLocalName(BString::from(data)),
+      ad_type::TX_PWR_LVL => Self::TxPowerLevel(*data.first()? as i8),
+      ad_type::CLASS_OF_DEVICE => Self::ClassOfDevice(data.try_into().ok()?),
+      ad_type::SP_HASH_C192 => Self::SimplePairingHashC192(data.try_into().ok()?),
+      ad_type::SP_RAND_R192 => Self::SimplePairingRandomizerR192(data.try_into().ok()?),
+      ad_type::SM_TK_VALUE => Self::SecurityManagerTkValue(data.try_into().ok()?),
+      ad_type::SM_OOB_FLAGS => Self::SecurityManagerOobFlags(*data.first()?),
+      ad_type::PERIPHERAL_ITVL_RANGE => {
+        let data: [u8; 4] = data.try_into().ok()?;
+        Self::PeripheralConnectionIntervalRange {
+          min: u16::from_le_bytes([data[0], data[1]]),
+          max: u16::from_le_bytes([data[2], data[3]]),
+        }
+      }
+      ad_type::SOL_UUIDS16 => Self::ServiceSolicitationUuids16(list_of(data, u16::from_le_bytes)?),
+      ad_type::SOL_UUIDS32 => Self::ServiceSolicitationUuids32(list_of(data, u32::from_le_bytes)?),
+      ad_type::SOL_UUIDS128 => Self::ServiceSolicitationUuids128(list_of(data, |x: [u8; 16]| x)?),
+      ad_type::SVC_DATA_UUID16 => {
+        let (uuid, data) = split_array::<2>(data)?;
+        Self::ServiceData16 {
+          uuid: u16::from_le_bytes(uuid),
+          data: data.to_vec(),
+        }
+      }
+      ad_type::SVC_DATA_UUID32 => {
+        let (uuid, data) = split_array::<4>(data)?;
+        Self::ServiceData32 {
+          uuid: u32::from_le_bytes(uuid),
+          data: data.to_vec(),
+        }
+      }
+      ad_type::SVC_DATA_UUID128 => {
+        let (uuid, data) = split_array::<16>(data)?;
+        Self::ServiceData128 {
+          uuid,
+          data: data.to_vec(),
+        }
+      }
+      ad_type::PUBLIC_TGT_ADDR => Self::PublicTargetAddress(list_of(data, |x: [u8; 6]| {
+        BLEAddress::from_le_bytes(x, BLEAddressType::Public)
+      })?),
+      ad_type::RANDOM_TGT_ADDR => Self::RandomTargetAddress(list_of(data, |x: [u8; 6]| {
+        BLEAddress::from_le_bytes(x, BLEAddressType::Random)
+      })?),
+      ad_type::APPEARANCE => Self::Appearance(u16::from_le_bytes(data.try_into().ok()?)),
+      ad_type::ADV_ITVL => Self::AdvertisingInterval(u16::from_le_bytes(data.try_into().ok()?)),
+      ad_type::LE_ADDR => {
+        let (addr, [flags]) = split_array::<6>(data)? else {
+          return None;
+        };
+        let addr_type = if flags & 0x01 == 0 {
+          BLEAddressType::Public
+        } else {
+          BLEAddressType::Random
+        };
+        Self::LeBluetoothDeviceAddress(BLEAddress::from_le_bytes(addr, addr_type))
+      }
+      ad_type::LE_ROLE => Self::LeRole(*data.first()?),
+      ad_type::SP_HASH_C256 => Self::SimplePairingHashC256(data.try_into().ok()?),
+      ad_type::SP_RAND_R256 => Self::SimplePairingRandomizerR256(data.try_into().ok()?),
+      ad_type::LE_SC_CONFIRM => Self::LeScConfirmationValue(data.try_into().ok()?),
+      ad_type::LE_SC_RANDOM => Self::LeScRandomValue(data.try_into().ok()?),
+      ad_type::URI => Self::Uri(data.to_vec()),
+      ad_type::INDOOR_POSITIONING => Self::IndoorPositioning(data.to_vec()),
+      ad_type::TRANSPORT_DISCOVERY => Self::TransportDiscoveryData(data.to_vec()),
+      ad_type::LE_SUPP_FEATURES => Self::LeSupportedFeatures(data.to_vec()),
+      ad_type::CHANNEL_MAP_UPDATE => Self::ChannelMapUpdateIndication(data.to_vec()),
+      ad_type::PB_ADV => Self::PbAdv(data.to_vec()),
+      ad_type::MESH_MESSAGE => Self::MeshMessage(data.to_vec()),
+      ad_type::MESH_BEACON => Self::MeshBeacon(data.to_vec()),
+      ad_type::BIG_INFO => Self::BigInfo(data.to_vec()),
+      ad_type::BROADCAST_CODE => Self::BroadcastCode(data.try_into().ok()?),
+      ad_type::RSI => Self::ResolvableSetIdentifier(data.try_into().ok()?),
+      ad_type::ADV_ITVL_LONG => match data.len() {
+        3 | 4 => {
+          let mut bytes = [0u8; 4];
+          bytes[..data.len()].copy_from_slice(data);
+          Self::AdvertisingIntervalLong(u32::from_le_bytes(bytes))
+        }
+        _ => return None,
+      },
+      ad_type::BROADCAST_NAME => Self::BroadcastName(BString::from(data)),
+      ad_type::ENCRYPTED_ADV_DATA => Self::EncryptedAdvertisingData(data.to_vec()),
+      ad_type::PAWR_TIMING => Self::PeriodicAdvertisingResponseTimingInformation(data.to_vec()),
+      ad_type::ESL => Self::ElectronicShelfLabel(data.to_vec()),
+      ad_type::INFO_3D => Self::Information3D(data.to_vec()),
+      ad_type::MFG_DATA => Self::ManufacturerSpecificData(data.to_vec()),
+      ad_type => Self::Unknown {
+        ad_type,
+        data: data.to_vec(),
+      },
+    };
+    Some(ret)
+  }
+
+  /// Iterates over the AD structures of an advertising or scan response payload.
+  ///
+  /// Malformed structures are skipped; iteration stops at a structure whose
+  /// length runs past the end of the payload.
+  pub fn parse(payload: &[u8]) -> AdStructureIter<'_> {
+    AdStructureIter { payload }
+  }
+}
+
+/// Encodes a list of AD structures into a payload.
+///
+/// Fails with `BLE_HS_EMSGSIZE` if a structure is longer than [`AdStructure::MAX_DATA_LEN`].
+pub fn encode_ad_structures<'a>(
+  structures: impl IntoIterator<Item = &'a AdStructure>,
+) -> Result<Vec<u8>, BLEError> {
+  let mut buf = Vec::new();
+  for ad in structures {
+    ad.encode(&mut buf)?;
+  }
+  Ok(buf)
+}
+
+/// Iterator returned by [`AdStructure::parse`].
+pub struct AdStructureIter<'a> {
+  payload: &'a [u8],
+}
+
+impl Iterator for AdStructureIter<'_> {
+  type Item = AdStructure;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      let length = *self.payload.first()? as usize;
+      if length == 0 {
+        self.payload = &self.payload[1..];
+        continue;
+      }
+
+      let Some(structure) = self.payload.get(1..(length + 1)) else {
+        self.payload = &[];
+        return None;
+      };
+      self.payload = &self.payload[(length + 1)..];
+
+      let (type_, data) = (structure[0], &structure[1..]);
+      match AdStructure::decode(type_, data) {
+        Some(ad) => return Some(ad),
+        None => {
+          ::log::debug!("Malformed AD structure: adType: 0x{:X}, {:X?}", type_, data);
+        }
+      }
+    }
+  }
+}
+
+fn list_of<const N: usize, T>(data: &[u8], f: impl Fn([u8; N]) -> T) -> Option<Vec<T>> {
+  if data.len() % N != 0 {
+    return None;
+  }
+  Some(
+    data
+      .chunks_exact(N)
+      .map(|x| f(x.try_into().unwrap()))
+      .collect(),
+  )
+}
+
+fn split_array<const N: usize>(data: &[u8]) -> Option<([u8; N], &[u8])> {
+  if data.len() < N {
+    return None;
+  }
+  let (head, tail) = data.split_at(N);
+  Some((head.try_into().unwrap(), tail))
+}
+
+fn uuid16(uuid: &BleUuid) -> Option<u16> {
+  match uuid {
+    BleUuid::Uuid16(x) => Some(*x),
+    _ => None,
+  }
+}
+
+fn uuid32(uuid: &BleUuid) -> Option<u32> {
+  match uuid {
+    BleUuid::Uuid32(x) => Some(*x),
+    _ => None,
+  }
+}
+
+fn uuid128(uuid: &BleUuid) -> Option<[u8; 16]> {
+  match uuid {
+    BleUuid::Uuid128(x) => Some(*x),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn round_trip() {
+    let structures = vec![
+      AdStructure::Flags(AdvFlag::from_bits_retain(0x06)),
+      AdStructure::service_uuids(&[BleUuid::Uuid16(0x180d), BleUuid::Uuid16(0x180f)], true)
+        .unwrap(),
+      AdStructure::CompleteLocalName("esp32".into()),
+      AdStructure::TxPowerLevel(-8),
+      AdStructure::Appearance(0x03c1),
+      AdStructure::service_data(BleUuid::Uuid16(0xfeaa), &[0x10, 0x00]),
+      AdStructure::LeBluetoothDeviceAddress(BLEAddress::from_le_bytes(
+        [1, 2, 3, 4, 5, 0xc6],
+        BLEAddressType::Random,
+      )),
+      AdStructure::AdvertisingIntervalLong(0x012345),
+      AdStructure::ManufacturerSpecificData(vec![0xe5, 0x02, 0xaa]),
+      AdStructure::Unknown {
+        ad_type: 0x40,
+        data: vec![1],
+      },
+    ];
+
+    let payload = encode_ad_structures(&structures).unwrap();
+    assert_eq!(
+      &payload[..13],
+      &[0x02, 0x01, 0x06, 0x05, 0x03, 0x0d, 0x18, 0x0f, 0x18, 0x06, 0x09, b'e', b's']
+    );
+    assert_eq!(
+      payload.len(),
+      structures.iter().map(|x| x.encoded_len()).sum::<usize>()
+    );
+    assert_eq!(AdStructure::parse(&payload).collect::<Vec<_>>(), structures);
+  }
+
+  #[test]
+  fn parse_raw() {
+    let payload = [
+      0x02, 0x01, 0x1a, // flags
+      0x00, // padding
+      0x02, 0xff, 0x4c, // manufacturer data
+      0x01, 0x0a, // TX power without data: skipped
+      0x05, 0x12, 0x06, 0x00, 0x80, 0x0c, // connection interval range
+      0x09, 0x09, b'x', // runs past the end
+    ];
+    let parsed = AdStructure::parse(&payload).collect::<Vec<_>>();
+    assert_eq!(
+      parsed,
+      [
+        AdStructure::Flags(AdvFlag::from_bits_retain(0x1a)),
+        AdStructure::ManufacturerSpecificData(vec![0x4c]),
+        AdStructure::PeripheralConnectionIntervalRange {
+          min: 0x0006,
+          max: 0x0c80
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn oversized() {
+    let mut buf = vec![0xaa];
+    let ad = AdStructure::ManufacturerSpecificData(vec![0; AdStructure::MAX_DATA_LEN]);
+    ad.encode(&mut buf).unwrap();
+    assert_eq!(&buf[..3], &[0xaa, 0xff, 0xff]);
+
+    let mut buf = vec![0xaa];
+    let ad = AdStructure::ManufacturerSpecificData(vec![0; AdStructure::MAX_DATA_LEN + 1]);
+    assert!(ad.encode(&mut buf).is_err());
+    assert_eq!(buf, [0xaa]);
+  }
+}
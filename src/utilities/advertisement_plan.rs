@@ -0,0 +1,192 @@
+use alloc::vec::Vec;
+use bstr::BString;
+
+use super::{encode_ad_structures, AdStructure};
+use crate::BLEError;
+
+/// Layout of AD structures over the advertising packet and the scan response.
+///
+/// Fields are placed in priority order: each one goes into the advertising packet if it still
+/// fits there, otherwise into the scan response. A local name that fits in neither packet is
+/// shortened to a [`AdStructure::ShortenedLocalName`] that fills the larger remaining space.
+/// Everything else that does not fit ends up in [`AdvertisementPlan::dropped`].
+///
+/// The planner does not touch the controller, so a layout can be checked before it is applied
+/// with [`crate::BLEAdvertising::set_ad_structures`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdvertisementPlan {
+  /// AD structures placed in the advertising packet.
+  pub adv_data: Vec<AdStructure>,
+  /// AD structures placed in the scan response.
+  pub scan_response: Vec<AdStructure>,
+  /// AD structures that did not fit in either packet, in priority order.
+  pub dropped: Vec<AdStructure>,
+}
+
+impl AdvertisementPlan {
+  /// Split `fields` (highest priority first) between the two packets.
+  ///
+  /// * `max_len`: maximum payload length of each packet, 31 bytes for legacy advertising.
+  /// * `use_scan_response`: if false, everything has to fit in the advertising packet.
+  pub fn new<'a>(
+    fields: impl IntoIterator<Item = &'a AdStructure>,
+    max_len: usize,
+    use_scan_response: bool,
+  ) -> Self {
+    let mut ret = Self::default();
+    let mut adv_len = 0;
+    let mut rsp_len = 0;
+    let rsp_max_len = if use_scan_response { max_len } else { 0 };
+
+    for field in fields {
+      // A structure longer than the length octet allows fits nowhere.
+      let len = if field.data_len() <= AdStructure::MAX_DATA_LEN {
+        field.encoded_len()
+      } else {
+        usize::MAX
+      };
+
+      if len <= max_len.saturating_sub(adv_len) {
+        adv_len += len;
+        ret.adv_data.push(field.clone());
+        continue;
+      }
+
+      // The flags are only allowed in the advertising packet.
+      if matches!(field, AdStructure::Flags(_)) {
+        ret.dropped.push(field.clone());
+        continue;
+      }
+
+      if len <= rsp_max_len.saturating_sub(rsp_len) {
+        rsp_len += len;
+        ret.scan_response.push(field.clone());
+        continue;
+      }
+
+      let name = match field {
+        AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => name,
+        _ => {
+          ret.dropped.push(field.clone());
+          continue;
+        }
+      };
+
+      let adv_space = max_len.saturating_sub(adv_len + 2);
+      let rsp_space = rsp_max_len.saturating_sub(rsp_len + 2);
+      let name = shorten(
+        name,
+        adv_space.max(rsp_space).min(AdStructure::MAX_DATA_LEN),
+      );
+      if name.is_empty() {
+        ret.dropped.push(field.clone());
+        continue;
+      }
+
+      let name = AdStructure::ShortenedLocalName(name);
+      if adv_space >= rsp_space {
+        adv_len += name.encoded_len();
+        ret.adv_data.push(name);
+      } else {
+        rsp_len += name.encoded_len();
+        ret.scan_response.push(name);
+      }
+    }
+
+    ret
+  }
+
+  /// Returns true if every field was placed, possibly with a shortened name.
+  pub fn is_complete(&self) -> bool {
+    self.dropped.is_empty()
+  }
+
+  /// Get the encoded advertising packet payload.
+  pub fn adv_payload(&self) -> Result<Vec<u8>, BLEError> {
+    encode_ad_structures(&self.adv_data)
+  }
+
+  /// Get the encoded scan response payload.
+  pub fn scan_response_payload(&self) -> Result<Vec<u8>, BLEError> {
+    encode_ad_structures(&self.scan_response)
+  }
+}
+
+/// Truncate `name` to at most `max_len` bytes without splitting a UTF-8 sequence.
+fn shorten(name: &BString, max_len: usize) -> BString {
+  let mut len = name.len().min(max_len);
+  if len < name.len() {
+    while len > 0 && (name[len] & 0xC0) == 0x80 {
+      len -= 1;
+    }
+  }
+  BString::from(&name[..len])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::enums::AdvFlag;
+  use alloc::vec;
+
+  fn flags() -> AdStructure {
+    AdStructure::Flags(AdvFlag::from_bits_retain(0x06))
+  }
+
+  #[test]
+  fn spills_into_scan_response() {
+    let uuid = AdStructure::ServiceUuids128 {
+      uuids: vec![[0x11; 16]],
+      complete: true,
+    };
+    let mfg = AdStructure::ManufacturerSpecificData(vec![0x22; 10]);
+    let fields = [flags(), uuid.clone(), mfg.clone()];
+
+    let plan = AdvertisementPlan::new(&fields, 31, true);
+    assert_eq!(plan.adv_data, [flags(), uuid]);
+    assert_eq!(plan.scan_response, [mfg]);
+    assert!(plan.is_complete());
+
+    let adv = plan.adv_payload().unwrap();
+    assert_eq!(adv.len(), 3 + 18);
+    assert_eq!(&adv[..5], &[0x02, 0x01, 0x06, 0x11, 0x07]);
+    assert_eq!(
+      plan.scan_response_payload().unwrap()[..3],
+      [0x0b, 0xff, 0x22]
+    );
+  }
+
+  #[test]
+  fn shortens_name() {
+    let mfg = AdStructure::ManufacturerSpecificData(vec![0; 20]);
+    // 'é' is two bytes and must not be split.
+    let name = AdStructure::CompleteLocalName("abcéf".into());
+    let fields = [flags(), mfg.clone(), name];
+
+    let plan = AdvertisementPlan::new(&fields, 31, false);
+    assert_eq!(plan.adv_data.len(), 3);
+    assert_eq!(
+      plan.adv_data[2],
+      AdStructure::ShortenedLocalName("abc".into())
+    );
+    assert_eq!(plan.adv_payload().unwrap().len(), 3 + 22 + 5);
+    assert!(plan.scan_response.is_empty());
+  }
+
+  #[test]
+  fn drops_what_does_not_fit() {
+    let big = AdStructure::ManufacturerSpecificData(vec![0; 28]);
+    let oversized = AdStructure::ManufacturerSpecificData(vec![0; AdStructure::MAX_DATA_LEN + 1]);
+    let fields = [big.clone(), flags(), oversized.clone()];
+
+    let plan = AdvertisementPlan::new(&fields, 31, true);
+    assert_eq!(plan.adv_data, [big]);
+    assert!(plan.scan_response.is_empty());
+    // The flags can not go in the scan response.
+    assert_eq!(plan.dropped, [flags(), oversized.clone()]);
+    assert!(!plan.is_complete());
+
+    let plan = AdvertisementPlan::new(&[oversized.clone()], 1650, false);
+    assert_eq!(plan.dropped, [oversized]);
+  }
+}
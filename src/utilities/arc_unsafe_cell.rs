@@ -21,6 +21,18 @@ impl<T> ArcUnsafeCell<T> {
       value: Arc::downgrade(&this.value),
     }
   }
+
+  /// Consume the `ArcUnsafeCell`, returning a pointer that keeps the strong reference.
+  pub(crate) fn into_raw(this: Self) -> *mut core::ffi::c_void {
+    Arc::into_raw(this.value) as _
+  }
+
+  /// Construct an `ArcUnsafeCell` from a pointer returned by [`ArcUnsafeCell::into_raw`].
+  pub(crate) unsafe fn from_raw(ptr: *mut core::ffi::c_void) -> Self {
+    Self {
+      value: Arc::from_raw(ptr as *const UnsafeCell<T>),
+    }
+  }
 }
 
 impl<T: ?Sized> Clone for ArcUnsafeCell<T> {
@@ -0,0 +1,72 @@
+use alloc::sync::Arc;
+use core::ffi::c_void;
+
+use super::mutex::{Mutex, MutexGuard};
+use crate::{BLEError, Signal};
+
+struct Inner<T> {
+  data: Mutex<T>,
+  signal: Signal<u32>,
+}
+
+/// State of an asynchronous NimBLE procedure, shared between the future awaiting it and the
+/// procedure callback.
+///
+/// The callback argument owns a reference to the state until the procedure completes, so a
+/// callback running after the future was dropped stays sound and its result is discarded.
+pub(crate) struct Completion<T> {
+  inner: Arc<Inner<T>>,
+}
+
+impl<T> Completion<T> {
+  pub(crate) fn new(data: T) -> Self {
+    Self {
+      inner: Arc::new(Inner {
+        data: Mutex::new(data),
+        signal: Signal::new(),
+      }),
+    }
+  }
+
+  /// Start the procedure with `f`, which receives the callback argument.
+  ///
+  /// If the procedure fails to start, the callback argument is released.
+  pub(crate) fn start(&self, f: impl FnOnce(*mut c_void) -> i32) -> Result<(), BLEError> {
+    let arg = Arc::into_raw(self.inner.clone()) as *mut c_void;
+    let rc = f(arg);
+    if rc != 0 {
+      unsafe { drop(Arc::from_raw(arg as *const Inner<T>)) };
+    }
+    BLEError::convert(rc as _)
+  }
+
+  /// Wait until the procedure completes, returning its status.
+  pub(crate) async fn wait(&self) -> u32 {
+    self.inner.signal.wait().await
+  }
+
+  pub(crate) fn data(&self) -> MutexGuard<'_, T> {
+    self.inner.data.lock()
+  }
+
+  /// Get the data of a callback argument.
+  ///
+  /// # Safety
+  ///
+  /// `arg` must be a callback argument given by [`Completion::start`], and the procedure must
+  /// not be completed yet.
+  pub(crate) unsafe fn data_of<'a>(arg: *mut c_void) -> MutexGuard<'a, T> {
+    (*(arg as *const Inner<T>)).data.lock()
+  }
+
+  /// Complete the procedure and release the callback argument.
+  ///
+  /// # Safety
+  ///
+  /// `arg` must be a callback argument given by [`Completion::start`], and the procedure must
+  /// not be completed yet.
+  pub(crate) unsafe fn complete(arg: *mut c_void, status: u32) {
+    let inner = Arc::from_raw(arg as *const Inner<T>);
+    inner.signal.signal(status);
+  }
+}
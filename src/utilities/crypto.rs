@@ -0,0 +1,191 @@
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+  0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+  0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+  0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+  0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+  0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+  0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+  0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+  0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+  0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+  0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+  0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+  0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+  0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+  0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+  0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+  0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiplication by x in GF(2^8).
+fn xtime(x: u8) -> u8 {
+  (x << 1) ^ (0x1b * (x >> 7))
+}
+
+/// The security function `e` (Core Vol 3, Part H, 2.2.1): AES-128 encryption of `data` with
+/// `key`, both most significant byte first.
+pub(crate) fn aes128(key: &[u8; 16], data: &[u8; 16]) -> [u8; 16] {
+  let mut round_keys = [[0u8; 16]; 11];
+  round_keys[0] = *key;
+  for round in 1..11 {
+    let prev = round_keys[round - 1];
+    let mut word = [prev[13], prev[14], prev[15], prev[12]];
+    for x in &mut word {
+      *x = SBOX[*x as usize];
+    }
+    word[0] ^= RCON[round - 1];
+
+    let round_key = &mut round_keys[round];
+    for i in 0..16 {
+      round_key[i] = prev[i] ^ if i < 4 { word[i] } else { round_key[i - 4] };
+    }
+  }
+
+  let mut state = *data;
+  let add_round_key = |state: &mut [u8; 16], round_key: &[u8; 16]| {
+    for (x, k) in state.iter_mut().zip(round_key) {
+      *x ^= k;
+    }
+  };
+
+  add_round_key(&mut state, &round_keys[0]);
+  for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+    // SubBytes and ShiftRows; the state is column-major.
+    let prev = state;
+    for col in 0..4 {
+      for row in 0..4 {
+        state[col * 4 + row] = SBOX[prev[((col + row) % 4) * 4 + row] as usize];
+      }
+    }
+
+    if round != 10 {
+      for col in state.chunks_exact_mut(4) {
+        let [a, b, c, d] = [col[0], col[1], col[2], col[3]];
+        let all = a ^ b ^ c ^ d;
+        col[0] ^= all ^ xtime(a ^ b);
+        col[1] ^= all ^ xtime(b ^ c);
+        col[2] ^= all ^ xtime(c ^ d);
+        col[3] ^= all ^ xtime(d ^ a);
+      }
+    }
+
+    add_round_key(&mut state, round_key);
+  }
+  state
+}
+
+/// The random address hash function `ah`, used to generate and resolve resolvable private
+/// addresses: the 24-bit hash of the 24-bit `r` (`prand`) with the identity resolving key `irk`.
+///
+/// As in the specification, `irk`, `r` and the hash are most significant byte first, the
+/// reverse of the over-the-air order used by NimBLE and [`BLEAddress::val`](crate::BLEAddress::val).
+///
+/// # Examples
+///
+/// The sample data of the specification (Vol 3, Part H, D.7):
+///
+/// ```
+/// use esp32_nimble::utilities::ah;
+///
+/// let irk = 0xec0234a357c8ad05341010a60a397d9b_u128.to_be_bytes();
+/// assert_eq!(ah(&irk, [0x70, 0x81, 0x94]), [0x0d, 0xfb, 0xaa]);
+/// ```
+pub fn ah(irk: &[u8; 16], r: [u8; 3]) -> [u8; 3] {
+  let mut data = [0u8; 16];
+  data[13..].copy_from_slice(&r);
+  let hash = aes128(irk, &data);
+  [hash[13], hash[14], hash[15]]
+}
+
+/// Doubling in GF(2^128), for the CMAC subkeys.
+fn double(x: &[u8; 16]) -> [u8; 16] {
+  let x = u128::from_be_bytes(*x);
+  ((x << 1) ^ (0x87 * (x >> 127))).to_be_bytes()
+}
+
+/// AES-CMAC (RFC 4493) of `msg` with `key`, both most significant byte first, as used by the
+/// signing function of the security manager (Core Vol 3, Part H, 2.4.5).
+pub(crate) fn aes_cmac(key: &[u8; 16], msg: &[u8]) -> [u8; 16] {
+  let k1 = double(&aes128(key, &[0; 16]));
+  let k2 = double(&k1);
+
+  let blocks = msg.len().div_ceil(16).max(1);
+  let mut mac = [0u8; 16];
+  for idx in 0..blocks {
+    let chunk = &msg[idx * 16..msg.len().min(idx * 16 + 16)];
+    let mut block = [0u8; 16];
+    block[..chunk.len()].copy_from_slice(chunk);
+    if idx == blocks - 1 {
+      let subkey = if chunk.len() == 16 {
+        &k1
+      } else {
+        block[chunk.len()] = 0x80;
+        &k2
+      };
+      for (x, k) in block.iter_mut().zip(subkey) {
+        *x ^= k;
+      }
+    }
+
+    for (x, b) in mac.iter_mut().zip(block) {
+      *x ^= b;
+    }
+    mac = aes128(key, &mac);
+  }
+  mac
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(x: u128) -> [u8; 16] {
+    x.to_be_bytes()
+  }
+
+  #[test]
+  fn aes128_fips197() {
+    assert_eq!(
+      aes128(
+        &hex(0x000102030405060708090a0b0c0d0e0f),
+        &hex(0x00112233445566778899aabbccddeeff)
+      ),
+      hex(0x69c4e0d86a7b0430d8cdb78070b4c55a)
+    );
+  }
+
+  #[test]
+  fn ah_sample_data() {
+    // Core Vol 3, Part H, D.7.
+    let irk = hex(0xec0234a357c8ad05341010a60a397d9b);
+    assert_eq!(ah(&irk, [0x70, 0x81, 0x94]), [0x0d, 0xfb, 0xaa]);
+  }
+
+  #[test]
+  fn cmac_rfc4493() {
+    let key = hex(0x2b7e151628aed2a6abf7158809cf4f3c);
+    let k1 = double(&aes128(&key, &[0; 16]));
+    assert_eq!(k1, hex(0xfbeed618357133667c85e08f7236a8de));
+    assert_eq!(double(&k1), hex(0xf7ddac306ae266ccf90bc11ee46d513b));
+
+    let msg = [
+      hex(0x6bc1bee22e409f96e93d7e117393172a),
+      hex(0xae2d8a571e03ac9c9eb76fac45af8e51),
+      hex(0x30c81c46a35ce411e5fbc1191a0a52ef),
+      hex(0xf69f2445df4f9b17ad2b417be66c3710),
+    ]
+    .concat();
+    let cases = [
+      (0, 0xbb1d6929e95937287fa37d129b756746),
+      (16, 0x070a16b46b4d4144f79bdd9dd04a287c),
+      (40, 0xdfa66747de9ae63030ca32611497c827),
+      (64, 0x51f0bebf7e3b9d92fc49741779363cfe),
+    ];
+    for (len, mac) in cases {
+      assert_eq!(aes_cmac(&key, &msg[..len]), hex(mac), "length {len}");
+    }
+  }
+}
@@ -1,3 +1,9 @@
+mod ad_structure;
+pub use ad_structure::*;
+
+mod advertisement_plan;
+pub use advertisement_plan::AdvertisementPlan;
+
 mod ble_uuid;
 pub use ble_uuid::BleUuid;
 
@@ -6,15 +12,32 @@ pub mod mutex;
 mod arc_unsafe_cell;
 pub(crate) use arc_unsafe_cell::*;
 
+mod completion;
+pub(crate) use completion::Completion;
+
 mod ble_functions;
 pub(crate) use ble_functions::*;
 
 mod nimble_npl_os;
 pub(crate) use nimble_npl_os::*;
 
+mod nvs;
+pub(crate) use nvs::*;
+
 mod os_mbuf;
 pub(crate) use os_mbuf::*;
 
+mod timeout;
+pub(crate) use timeout::{with_conn_timeout, with_timeout};
+
+mod crypto;
+pub(crate) use crypto::aes_cmac;
+pub use crypto::ah;
+
+mod queue;
+pub use queue::OverflowPolicy;
+pub(crate) use queue::Queue;
+
 #[inline]
 #[allow(unused)]
 pub(crate) unsafe fn extend_lifetime_mut<'a, 'b: 'a, T: ?Sized>(r: &'a mut T) -> &'b mut T {
@@ -31,3 +54,9 @@ pub(crate) const unsafe fn as_mut_ptr<T>(ptr: *const T) -> *mut T {
 pub(crate) unsafe fn voidp_to_ref<'a, T>(ptr: *mut core::ffi::c_void) -> &'a mut T {
   &mut *ptr.cast()
 }
+
+/// Time since boot.
+#[inline]
+pub(crate) fn now() -> core::time::Duration {
+  core::time::Duration::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as _)
+}
@@ -0,0 +1,59 @@
+use alloc::{ffi::CString, vec, vec::Vec};
+use core::ffi::CStr;
+use esp_idf_sys::*;
+
+use crate::BLEError;
+
+/// Open `namespace` of the default NVS partition for reading and writing.
+pub(crate) fn nvs_open_namespace(namespace: &str) -> Result<nvs_handle_t, BLEError> {
+  let namespace = CString::new(namespace).map_err(|_| store_fail())?;
+  let mut handle = 0;
+  esp_to_ble(unsafe {
+    nvs_open(
+      namespace.as_ptr(),
+      nvs_open_mode_t_NVS_READWRITE,
+      &mut handle,
+    )
+  })?;
+  Ok(handle)
+}
+
+/// Read the blob stored under `key`, `None` if nothing is stored yet.
+pub(crate) fn nvs_load(handle: nvs_handle_t, key: &CStr) -> Result<Option<Vec<u8>>, BLEError> {
+  let mut len = 0;
+  let rc = unsafe { nvs_get_blob(handle, key.as_ptr(), core::ptr::null_mut(), &mut len) };
+  if rc == ESP_ERR_NVS_NOT_FOUND as _ {
+    return Ok(None);
+  }
+  esp_to_ble(rc)?;
+
+  let mut data = vec![0u8; len];
+  esp_to_ble(unsafe { nvs_get_blob(handle, key.as_ptr(), data.as_mut_ptr() as _, &mut len) })?;
+  data.truncate(len);
+  Ok(Some(data))
+}
+
+/// Store `data` under `key` and commit it.
+pub(crate) fn nvs_store(handle: nvs_handle_t, key: &CStr, data: &[u8]) -> Result<(), BLEError> {
+  unsafe {
+    esp_to_ble(nvs_set_blob(
+      handle,
+      key.as_ptr(),
+      data.as_ptr() as _,
+      data.len(),
+    ))?;
+    esp_to_ble(nvs_commit(handle))
+  }
+}
+
+fn store_fail() -> BLEError {
+  BLEError::convert(BLE_HS_ESTORE_FAIL).unwrap_err()
+}
+
+fn esp_to_ble(rc: esp_err_t) -> Result<(), BLEError> {
+  if rc == ESP_OK as _ {
+    return Ok(());
+  }
+  ::log::warn!("NVS error: {}", rc);
+  Err(store_fail())
+}
@@ -0,0 +1,78 @@
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::mutex::Mutex;
+use crate::Signal;
+
+/// What a bounded queue does with a new item when it is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+  /// Discard the oldest queued item to make room for the new one.
+  DropOldest,
+  /// Discard the new item.
+  DropNewest,
+}
+
+/// Bounded queue filled from NimBLE callbacks and drained by an async consumer.
+pub(crate) struct Queue<T> {
+  items: Mutex<VecDeque<T>>,
+  capacity: usize,
+  overflow: OverflowPolicy,
+  closed: AtomicBool,
+  signal: Signal<()>,
+}
+
+impl<T> Queue<T> {
+  pub(crate) fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+    Self {
+      items: Mutex::new(VecDeque::new()),
+      capacity: capacity.max(1),
+      overflow,
+      closed: AtomicBool::new(false),
+      signal: Signal::new(),
+    }
+  }
+
+  /// Queue an item. Returns false if an item was dropped because the queue was full.
+  pub(crate) fn push(&self, item: T) -> bool {
+    let mut items = self.items.lock();
+    let mut ret = true;
+    if items.len() >= self.capacity {
+      ret = false;
+      match self.overflow {
+        OverflowPolicy::DropOldest => {
+          items.pop_front();
+        }
+        OverflowPolicy::DropNewest => return ret,
+      }
+    }
+    items.push_back(item);
+    drop(items);
+
+    self.signal.signal(());
+    ret
+  }
+
+  /// Wake up the consumer. Queued items can still be received.
+  pub(crate) fn close(&self) {
+    self.closed.store(true, Ordering::Release);
+    self.signal.signal(());
+  }
+
+  pub(crate) fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Acquire)
+  }
+
+  /// Wait for the next item. Returns `None` once the queue is closed and empty.
+  pub(crate) async fn pop(&self) -> Option<T> {
+    loop {
+      if let Some(item) = self.items.lock().pop_front() {
+        return Some(item);
+      }
+      if self.is_closed() {
+        return None;
+      }
+      self.signal.wait().await;
+    }
+  }
+}
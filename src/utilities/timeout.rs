@@ -0,0 +1,102 @@
+use alloc::sync::Arc;
+use core::{ffi::c_void, future::Future, pin::pin, task::Poll, time::Duration};
+use esp_idf_sys::*;
+
+use crate::{BLEError, Signal};
+
+/// One-shot timer signalling when it expires.
+///
+/// The timer callback owns a reference to the signal until it runs, or until the timer is
+/// stopped before expiring.
+struct Timer {
+  handle: esp_timer_handle_t,
+  signal: Arc<Signal<()>>,
+}
+
+impl Timer {
+  fn start(timeout: Duration) -> Result<Self, BLEError> {
+    let signal = Arc::new(Signal::new());
+    let arg = Arc::into_raw(signal.clone()) as *mut c_void;
+
+    let args = esp_timer_create_args_t {
+      callback: Some(Self::on_expired),
+      arg,
+      dispatch_method: esp_timer_dispatch_t_ESP_TIMER_TASK,
+      name: c"ble_timeout".as_ptr(),
+      skip_unhandled_events: false,
+    };
+
+    let mut handle = core::ptr::null_mut();
+    unsafe {
+      if esp_timer_create(&args, &mut handle) != ESP_OK as _ {
+        drop(Arc::from_raw(arg as *const Signal<()>));
+        return Err(BLEError::convert(BLE_HS_ENOMEM).unwrap_err());
+      }
+
+      let timer = Self { handle, signal };
+      if esp_timer_start_once(handle, timeout.as_micros() as _) != ESP_OK as _ {
+        // The timer is not armed, so dropping it does not release the callback argument.
+        drop(Arc::from_raw(arg as *const Signal<()>));
+        return Err(BLEError::convert(BLE_HS_ENOMEM).unwrap_err());
+      }
+      Ok(timer)
+    }
+  }
+
+  extern "C" fn on_expired(arg: *mut c_void) {
+    let signal = unsafe { Arc::from_raw(arg as *const Signal<()>) };
+    signal.signal(());
+  }
+}
+
+impl Drop for Timer {
+  fn drop(&mut self) {
+    unsafe {
+      // A timer stopped before expiring never runs its callback, release the argument here.
+      if esp_timer_stop(self.handle) == ESP_OK as _ {
+        drop(Arc::from_raw(Arc::as_ptr(&self.signal)));
+      }
+      esp_timer_delete(self.handle);
+    }
+  }
+}
+
+/// Await `fut`, failing with [`BLEError::timeout`] if it does not complete within `timeout`.
+///
+/// `None` waits forever. The caller is responsible for cancelling the procedure on expiry.
+pub(crate) async fn with_timeout<F: Future>(
+  timeout: Option<Duration>,
+  fut: F,
+) -> Result<F::Output, BLEError> {
+  let Some(timeout) = timeout else {
+    return Ok(fut.await);
+  };
+
+  let timer = Timer::start(timeout)?;
+  let mut fut = pin!(fut);
+  let mut expired = pin!(timer.signal.wait());
+
+  core::future::poll_fn(|cx| {
+    if let Poll::Ready(value) = fut.as_mut().poll(cx) {
+      return Poll::Ready(Ok(value));
+    }
+    expired.as_mut().poll(cx).map(|_| Err(BLEError::timeout()))
+  })
+  .await
+}
+
+/// Await a procedure of the connection, terminating the connection if it does not complete
+/// within `timeout`.
+///
+/// Terminating the connection ends its pending GATT procedures with `BLE_HS_ENOTCONN`.
+pub(crate) async fn with_conn_timeout<F: Future>(
+  conn_handle: u16,
+  timeout: Option<Duration>,
+  fut: F,
+) -> Result<F::Output, BLEError> {
+  let ret = with_timeout(timeout, fut).await;
+  if ret.is_err() {
+    unsafe { ble_gap_terminate(conn_handle, ble_error_codes_BLE_ERR_REM_USER_CONN_TERM as _) };
+  }
+  ret
+}